@@ -12,8 +12,43 @@ fn main() {
                 tauri_build::InlinedPlugin::new().commands(&[
                     "connect",
                     "disconnect",
+                    "disconnect_all",
+                    "drain",
                     "destroy",
+                    "rename_connection",
+                    "validate_stanza",
                     "send",
+                    "send_batch",
+                    "set_presence",
+                    "appear_offline",
+                    "appear_online",
+                    "set_receive_filter",
+                    "mute_receive",
+                    "unmute_receive",
+                    "set_connection_log_level",
+                    "time_to_timeout",
+                    "get_state",
+                    "get_stream_features",
+                    "get_metrics",
+                    "set_default_read_timeout",
+                    "get_global_stats",
+                    "dump_diagnostics",
+                    "list_connections",
+                    "list_connections_by_state",
+                    "get_recent_stanzas",
+                    "get_error_history",
+                    "clear_error_history",
+                    "list_pending_iqs",
+                    "ping_all",
+                    "resume_all",
+                    "send_to_all",
+                    "send_iq",
+                    "set_carbons",
+                    "fetch_roster",
+                    "mam_query",
+                    "probe_server",
+                    #[cfg(feature = "test-commands")]
+                    "test_inject_disconnect",
                 ]),
             )
             .plugin(
@@ -2,64 +2,419 @@
 //
 // Copyright 2024, Prose Foundation
 
+/**************************************************************************
+ * MODULES
+ * ************************************************************************* */
+
+mod compress;
+mod connector;
+mod filter;
+mod header;
+mod resume;
+mod throttle;
+mod websocket;
+
 /**************************************************************************
  * IMPORTS
  * ************************************************************************* */
 
+use dashmap::DashMap;
+use futures::future;
 use futures::stream::{SplitSink, SplitStream, StreamExt};
 use futures::SinkExt;
-use jid::{BareJid, FullJid};
-use log::{debug, error, info, warn};
-use serde::Serialize;
-use std::collections::HashMap;
-use std::sync::RwLock;
-use std::time::Duration;
+use jid::{BareJid, FullJid, Jid};
+use log::{debug, error, info, warn, LevelFilter};
+use minidom::Element;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::io::ErrorKind as IoErrorKind;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use tauri::plugin::{Builder, TauriPlugin};
-use tauri::{Emitter, Manager, Runtime, State, Window};
+use tauri::{Emitter, Manager, RunEvent, Runtime, State, Window};
 use thiserror::Error;
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::runtime::{
+    Builder as TokioRuntimeBuilder, Handle as TokioHandle, Runtime as TokioRuntime,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender, WeakUnboundedSender};
+use tokio::sync::{oneshot, Notify};
 use tokio::task::{self, JoinHandle};
-use tokio::time::timeout;
+use tokio::time::{self, timeout};
 use tokio_xmpp::connect::ServerConnector;
-use tokio_xmpp::{AsyncClient as Client, Error, Event, Packet};
+use tokio_xmpp::parsers::sasl::DefinedCondition as SaslDefinedCondition;
+use tokio_xmpp::{
+    AsyncClient as Client, AsyncConfig, AuthError, Error, Event, Packet, ProtocolError,
+};
+
+use uuid::Uuid;
+
+use crate::logger;
+use connector::{
+    parse_websocket_url, ConnectionConnector, ConnectorError, ConnectorReport, ConnectorResolver,
+    ConnectorStage, ConnectorTransport, TlsTrust, TlsVersion,
+};
+use filter::StanzaFilter;
+use resume::{ResumeOutcome, ResumeRequest};
 
 /**************************************************************************
  * CONSTANTS
  * ************************************************************************* */
 
+const EVENT_PREFIX: &'static str = "connection:";
+
 const EVENT_STATE: &'static str = "connection:state";
 const EVENT_RECEIVE: &'static str = "connection:receive";
+const EVENT_RECEIVE_MESSAGE: &'static str = "connection:receive:message";
+const EVENT_RECEIVE_PRESENCE: &'static str = "connection:receive:presence";
+const EVENT_RECEIVE_IQ: &'static str = "connection:receive:iq";
+const EVENT_RECEIVE_CHUNK: &'static str = "connection:receive-chunk";
+const EVENT_RECEIVE_MESSAGE_CHUNK: &'static str = "connection:receive:message-chunk";
+const EVENT_RECEIVE_PRESENCE_CHUNK: &'static str = "connection:receive:presence-chunk";
+const EVENT_RECEIVE_IQ_CHUNK: &'static str = "connection:receive:iq-chunk";
+const EVENT_SENT: &'static str = "connection:sent";
+const EVENT_PARSE_ERROR: &'static str = "connection:parse-error";
+const EVENT_BANDWIDTH: &'static str = "connection:bandwidth";
+const EVENT_DUPLICATE_ID: &'static str = "connection:duplicate-id";
+const EVENT_SEND_CLOSED: &'static str = "connection:send-closed";
+const EVENT_MULTIPLEXED: &'static str = "connection:event";
+const EVENT_CERT_EXPIRING: &'static str = "connection:cert-expiring";
+const EVENT_REFLECTED: &'static str = "connection:reflected";
+const EVENT_HEARTBEAT: &'static str = "connection:heartbeat";
+const EVENT_STREAM_MISMATCH: &'static str = "connection:stream-mismatch";
+const EVENT_RECEIPT: &'static str = "connection:receipt";
+const EVENT_SLOW_CONSUMER: &'static str = "connection:slow-consumer";
+const EVENT_SERVER_INFO: &'static str = "connection:server-info";
+
+const NS_CLIENT: &'static str = "jabber:client";
+const NS_PING: &'static str = "urn:xmpp:ping";
+const NS_SASL: &'static str = "urn:ietf:params:xml:ns:xmpp-sasl";
+const NS_SM: &'static str = "urn:xmpp:sm:3";
+const NS_ROSTER: &'static str = "jabber:iq:roster";
+const NS_VERSION: &'static str = "jabber:iq:version";
+const NS_MAM: &'static str = "urn:xmpp:mam:2";
+const NS_RSM: &'static str = "http://jabber.org/protocol/rsm";
+const NS_DATA_FORMS: &'static str = "jabber:x:data";
+const NS_FORWARD: &'static str = "urn:xmpp:forward:0";
+const NS_DELAY: &'static str = "urn:xmpp:delay";
+const NS_RECEIPTS: &'static str = "urn:xmpp:receipts";
+const NS_CARBONS: &'static str = "urn:xmpp:carbons:2";
+const NS_DISCO_INFO: &'static str = "http://jabber.org/protocol/disco#info";
+const NS_STANZA_ID: &'static str = "urn:xmpp:sid:0";
+const NS_STANZAS: &'static str = "urn:ietf:params:xml:ns:xmpp-stanzas";
+const NS_STREAM: &'static str = "http://etherx.jabber.org/streams";
+const NS_STREAM_ERRORS: &'static str = "urn:ietf:params:xml:ns:xmpp-streams";
+const NS_FEATURE_COMPRESS: &'static str = "http://jabber.org/features/compress";
+const NS_FEATURE_REGISTER: &'static str = "http://jabber.org/features/iq-register";
+
+const IDLE_CLOSE_CONDITIONS: [&'static str; 1] = ["connection-timeout"];
+
+const ANONYMOUS_NODE: &'static str = "anonymous";
+const ANONYMOUS_SASL_MECHANISM: &'static str = "ANONYMOUS";
 
 const READ_TIMEOUT_MILLISECONDS: u64 = 300000;
+const PING_TIMEOUT_MILLISECONDS: u64 = 10000;
+const PING_MISSES_MAXIMUM: u32 = 3;
+const ROSTER_TIMEOUT_MILLISECONDS: u64 = 30000;
+const MAM_TIMEOUT_MILLISECONDS: u64 = 30000;
+const IQ_TIMEOUT_MILLISECONDS: u64 = 30000;
+const SERVER_INFO_TIMEOUT_MILLISECONDS: u64 = 10000;
+const CARBONS_TIMEOUT_MILLISECONDS: u64 = 10000;
+const AUTO_DESTROY_GRACE_MILLISECONDS: u64 = 2000;
+const RECONNECT_DELAY_INITIAL_MILLISECONDS: u64 = 1000;
+const RECONNECT_DELAY_MAXIMUM_MILLISECONDS: u64 = 60000;
+const RECONNECT_ATTEMPTS_MAXIMUM: u32 = 10;
+const RECONNECT_BUFFER_SIZE: usize = 500;
+const REPLAY_BUFFER_SIZE_DEFAULT: usize = 50;
+
+const LANG_SIZE_MAXIMUM: usize = 35;
+const RESOURCE_CONFLICT_RETRIES_MAXIMUM: u32 = 3;
+const SYSTEM_SHUTDOWN_RECONNECT_DELAY_MILLISECONDS: u64 = 30000;
+const SLOW_EMIT_THRESHOLD_MILLISECONDS: u64 = 100;
+const SHUTDOWN_DRAIN_TIMEOUT_MILLISECONDS: u64 = 2000;
+const SHUTDOWN_DRAIN_MARGIN_MILLISECONDS: u64 = 500;
+const DRAIN_TIMEOUT_MILLISECONDS: u64 = 10000;
+const DRAIN_POLL_INTERVAL_MILLISECONDS: u64 = 10;
+const DISCONNECT_GRACE_TIMEOUT_MILLISECONDS: u64 = 5000;
+const SERVER_TIMEOUT_MILLISECONDS: u64 = 10000;
+const CERT_EXPIRY_THRESHOLD_MILLISECONDS: u64 = 1209600000;
+
+const SENT_IDS_TRACKING_SIZE: usize = 1000;
+const RECEIVE_BUFFER_SIZE_DEFAULT: usize = 1000;
+const REFLECTIONS_TRACKING_SIZE: usize = 100;
+const RECEIPTS_TRACKING_SIZE: usize = 1000;
+const ERROR_HISTORY_SIZE: usize = 20;
+const SM_UNACKED_SIZE_MAXIMUM: usize = 500;
+
+const AUTH_FAILURES_MAXIMUM: u32 = 3;
+const AUTH_FAILURES_WINDOW_MILLISECONDS: u64 = 60000;
+const AUTH_COOLDOWN_MILLISECONDS: u64 = 300000;
+
+const RETIRE_WAIT_MILLISECONDS: u64 = 250;
+
+const COMPRESSION_THRESHOLD_DEFAULT: usize = 16384;
+
+const CHUNKED_RECEIVE_THRESHOLD_DEFAULT: usize = 1048576;
+const CHUNKED_RECEIVE_CHUNK_SIZE_DEFAULT: usize = 262144;
+
+// Notice: connection log targets are nested under this module-like target, \
+//   so that the logger can let them through regardless of the global level.
+pub const LOG_TARGET: &'static str = "connection";
+
+const HIGH_TIER_WORKER_THREADS: usize = 2;
+const HIGH_TIER_THREAD_NAME: &'static str = "connection-high-tier";
+const DEDICATED_THREAD_NAME_DEFAULT: &'static str = "connection";
+
+// Notice: per-connection log levels are kept globally (outside of the \
+//   plugin state), since they get consulted by the logger, which has no \
+//   access to the plugin state.
+static LOG_LEVELS: LazyLock<DashMap<String, LevelFilter>> = LazyLock::new(DashMap::new);
+static LOG_LABELS: LazyLock<DashMap<String, String>> = LazyLock::new(DashMap::new);
+
+static HIGH_TIER_RUNTIME: LazyLock<Option<TokioRuntime>> = LazyLock::new(|| {
+    TokioRuntimeBuilder::new_multi_thread()
+        .worker_threads(HIGH_TIER_WORKER_THREADS)
+        .thread_name(HIGH_TIER_THREAD_NAME)
+        .enable_all()
+        .build()
+        .inspect_err(|err| error!("Failed to build high-tier connection runtime: {}", err))
+        .ok()
+});
 
 /**************************************************************************
  * TYPES
  * ************************************************************************* */
 
 type DisconnectError = SendError;
+type DrainError = SendError;
+type BoundResult = Result<Jid, ConnectionState>;
+type FlushSender = Arc<Mutex<Option<oneshot::Sender<()>>>>;
 
 /**************************************************************************
  * ENUMERATIONS
  * ************************************************************************* */
 
-#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+// Notice: connection state transitions go as follows:
+//   - (initial) -> 'secured' -> 'connected' (the stream is secured once \
+//     TLS got negotiated, then gets authenticated and bound)
+//   - 'connected' -> 'reconnecting' (upon a recoverable error, and only if \
+//     reconnection is enabled)
+//   - 'reconnecting' -> 'secured' -> 'connected' (reconnection attempt \
+//     succeeded)
+//   - 'reconnecting' -> 'secured' -> 'resumed' (reconnection attempt \
+//     succeeded, and the previous stream got resumed with its session)
+//   - 'reconnecting' -> error state -> 'disconnected' (attempts exhausted, \
+//     or non-recoverable error, eg. an authentication failure)
+//   - any -> error state -> 'disconnected' (error, reconnection disabled)
+//   - 'connected' -> 'reauth-required' -> 'disconnected' (server closed \
+//     the stream, requesting the client to authenticate again)
+//   - any -> 'disconnected' (explicit or server-initiated disconnection)
+// Redundant transitions (eg. a repeated state, or an error state following \
+//   another one) are never emitted, as tasks might race to emit them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
 #[serde(rename_all = "kebab-case")]
 pub enum ConnectionState {
+    Secured,
     Connected,
+    Resumed,
+    Reconnecting,
+    #[default]
     Disconnected,
     AuthenticationFailure,
     ConnectionError,
     ConnectionTimeout,
+    ResolutionFailure,
+    ReauthRequired,
+}
+
+// Notice: high-tier connections get their pollers spawned on a dedicated \
+//   runtime, so that they cannot get starved by chatty normal-tier \
+//   connections sharing the application runtime. This comes at the cost of \
+//   extra worker threads (spawned once the first high-tier connection \
+//   connects, and kept for the lifetime of the application). Also, this only \
+//   isolates task scheduling: network bandwidth and IPC are still shared.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionTier {
+    #[default]
+    Normal,
+    High,
+}
+
+// Notice: the WebSocket transport is meant for networks that only allow \
+//   HTTPS traffic (see RFC 7395). It connects to the WebSocket URL given in \
+//   options, or to the one advertised by the JID domain if none is given.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionTransport {
+    #[default]
+    Tcp,
+    WebSocket,
+}
+
+// Notice: this tells what to do when connecting on a JID that another \
+//   connection is already using, either refusing the new connection, \
+//   replacing the existing one(s) with it, or letting both run in parallel \
+//   (eg. multi-resource setups). Lifting the same-JID guard from the plugin \
+//   settings allows multiple connections for all connect requests.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum JidConflictPolicy {
+    #[default]
+    Reject,
+    TakeoverExisting,
+    AllowMultiple,
+}
+
+// Notice: SASL failure conditions and notable stream errors are mapped to \
+//   a reason, so that the client can show actionable messages (eg. a wrong \
+//   password must not be reported the same way a disabled account is).
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConnectionStateReason {
+    BadCredentials,
+    AccountDisabled,
+    CredentialsExpired,
+    TemporaryFailure,
+    EncryptionRequired,
+    MechanismUnsupported,
+    Aborted,
+    SystemShutdown,
+    IdleTimeout,
+    ServersUnreachable,
+    Other,
+}
+
+// Notice: this is a stable and machine-readable mapping of the underlying \
+//   error that caused an error state, so that the client can handle each \
+//   error precisely (eg. to show a localized message).
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConnectionErrorKind {
+    IoConnectionRefused,
+    IoConnectionReset,
+    IoConnectionAborted,
+    IoTimedOut,
+    IoUnexpectedEof,
+    IoOther,
+    ResolverUnavailable,
+    ResolveFailed,
+    ConnectFailed,
+    ConnectTimeout,
+    TorProxyMissing,
+    ProxyFailed,
+    TlsInvalidServerName,
+    TlsFailed,
+    TlsVersionUnsupported,
+    CertificatePinMismatch,
+    StreamMismatch,
+    WebSocketNotDiscovered,
+    WebSocketFailed,
+    ConnectorOther,
+    ProtocolNoTls,
+    ProtocolParse,
+    ProtocolOther,
+    AuthNoMechanism,
+    AuthCertificateRejected,
+    AuthFailure,
+    AuthOther,
+    ReadTimeout,
+    ReceiveOverflow,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReceiveOverflowPolicy {
+    #[default]
+    DropOldest,
+    DropNewest,
+    Disconnect,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum ReceiveChunkMarker {
+    Start,
+    Data,
+    End,
+}
+
+enum ReceiveQueueing {
+    Queued,
+    Emit(QueuedStanza),
+    Overflowed,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum SendClosedReason {
+    ChannelClosed,
+    PacketSendError,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceShow {
+    Away,
+    Chat,
+    Dnd,
+    Xa,
 }
 
 #[derive(Serialize, Debug, Error)]
 pub enum ConnectError {
     #[error("Invalid JID, cannot connect")]
     InvalidJid,
+    #[error("Invalid JID resource, cannot connect")]
+    InvalidResource,
+    #[error("Invalid receive filter, cannot connect")]
+    InvalidFilter,
+    #[error("Invalid stream language, cannot connect")]
+    InvalidLang,
+    #[error("Invalid trusted certificate, cannot connect")]
+    InvalidCertificate,
+    #[error("Invalid certificate pin, cannot connect")]
+    InvalidCertificatePin,
+    #[error("Invalid client certificate or key, cannot connect")]
+    InvalidIdentity,
+    #[error("Invalid server endpoint, cannot connect")]
+    InvalidServerEndpoint,
+    #[error("Invalid WebSocket URL, cannot connect")]
+    InvalidWebSocketUrl,
+    #[error("Invalid heartbeat interval, cannot connect")]
+    InvalidHeartbeatInterval,
+    #[error("Invalid bandwidth interval, cannot connect")]
+    InvalidBandwidthInterval,
+    #[error("Invalid ping interval or misses, cannot connect")]
+    InvalidPing,
     #[error("Another connection is bound on the JID")]
     AnotherConnectionBound,
     #[error("Connection identifier already exists")]
     ConnectionAlreadyExists,
+    #[error("Too many connections, cannot add another one")]
+    TooManyConnections,
+    #[error("Authentication failure")]
+    AuthenticationFailure,
+    #[error("Too many authentication failures, retry in {0}ms")]
+    AuthCooldown(u64),
+    #[error("Could not resolve server")]
+    ResolutionFailure,
+    #[error("Connection timed out")]
+    ConnectionTimeout,
+    #[error("Server certificate does not match pin")]
+    CertificatePinMismatch,
+    #[error("Server offers no acceptable SASL mechanism")]
+    NoAcceptableSaslMechanism,
+    #[error("Connection error")]
+    ConnectionError,
 }
 
 #[derive(Serialize, Debug, Error)]
@@ -68,8 +423,145 @@ pub enum SendError {
     CannotWrite,
     #[error("Failure to parse stanza to send")]
     CannotParse,
+    #[error("Failure to parse stanza to send at index: {0}")]
+    CannotParseAt(usize),
+    #[error("Invalid stanza to send: {0}")]
+    InvalidStanza(String),
+    #[error("Stanza reflection requires a correlation token and stanza identifier")]
+    CannotCorrelate,
+    #[error("Stanza was not flushed in due time")]
+    FlushTimeout,
+    #[error("Delivery receipts can only be requested on messages with an identifier")]
+    CannotRequestReceipt,
+    #[error("Connection is draining, not accepting new stanzas")]
+    Draining,
+    #[error("Send queue is full, retry later")]
+    QueueFull,
+    #[error("Only {queued} stanzas were queued, because: {error}")]
+    PartiallyQueued { queued: usize, error: Box<SendError> },
+    #[error("Connection does not exist")]
+    ConnectionDoesNotExist,
+}
+
+#[derive(Serialize, Debug, Error)]
+pub enum ValidateError {
+    #[error("Failure to parse stanza: {0}")]
+    CannotParse(String),
+    #[error("Invalid stanza: {0}")]
+    InvalidStanza(String),
+}
+
+#[derive(Serialize, Debug, Error)]
+pub enum QueryError {
+    #[error("Connection does not exist")]
+    ConnectionDoesNotExist,
+}
+
+#[cfg(feature = "test-commands")]
+#[derive(Serialize, Debug, Error)]
+pub enum InjectError {
+    #[error("Cannot inject this state")]
+    InvalidState,
+    #[error("Connection does not exist")]
+    ConnectionDoesNotExist,
+}
+
+#[derive(Serialize, Debug, Error)]
+pub enum IqError {
+    #[error("Failure to write on sender")]
+    CannotWrite,
+    #[error("Failure to build request stanza")]
+    CannotBuild,
+    #[error("Request did not get a response in due time")]
+    Timeout,
+    #[error("Request was cancelled")]
+    Cancelled,
+}
+
+#[derive(Serialize, Debug, Error)]
+pub enum LogLevelError {
+    #[error("Connection does not exist")]
+    ConnectionDoesNotExist,
+    #[error("Invalid log level")]
+    InvalidLevel,
+}
+
+#[derive(Serialize, Debug, Error)]
+pub enum FilterError {
+    #[error("Connection does not exist")]
+    ConnectionDoesNotExist,
+    #[error("Invalid filter expression: {0}")]
+    InvalidExpression(String),
+}
+
+#[derive(Serialize, Debug, Error)]
+pub enum MamError {
+    #[error("Connection does not exist")]
+    ConnectionDoesNotExist,
+    #[error("Request failed: {0}")]
+    RequestFailed(IqError),
+    #[error("Server returned an error: {0}")]
+    ServerError(String),
+    #[error("Server returned an invalid response")]
+    InvalidResponse,
+}
+
+#[derive(Serialize, Debug, Error)]
+pub enum RenameError {
+    #[error("Connection does not exist")]
+    ConnectionDoesNotExist,
+    #[error("Connection already exists")]
+    ConnectionAlreadyExists,
+}
+
+#[derive(Serialize, Debug, Error)]
+pub enum SendIqError {
+    #[error("Connection does not exist")]
+    ConnectionDoesNotExist,
+    #[error("Failure to parse stanza to send")]
+    CannotParse,
+    #[error("Invalid request stanza: {0}")]
+    InvalidStanza(String),
+    #[error("Request failed: {0}")]
+    RequestFailed(IqError),
+    #[error("Server returned an error: {0}")]
+    ServerError(String),
+}
+
+#[derive(Serialize, Debug, Error)]
+pub enum CarbonsError {
+    #[error("Connection does not exist")]
+    ConnectionDoesNotExist,
+    #[error("Server does not support message carbons")]
+    Unsupported,
+    #[error("Request failed: {0}")]
+    RequestFailed(IqError),
+    #[error("Server returned an error: {0}")]
+    ServerError(String),
+}
+
+#[derive(Serialize, Debug, Error)]
+pub enum RosterError {
     #[error("Connection does not exist")]
     ConnectionDoesNotExist,
+    #[error("Request failed: {0}")]
+    RequestFailed(IqError),
+    #[error("Server returned an error: {0}")]
+    ServerError(String),
+    #[error("Server returned an invalid response")]
+    InvalidResponse,
+}
+
+#[derive(Serialize, Debug, Error)]
+pub enum ProbeError {
+    #[error("Invalid domain, cannot probe")]
+    InvalidDomain,
+    #[error("Could not resolve server")]
+    ResolutionFailure,
+    #[error("Could not connect to server")]
+    ConnectionError,
+    #[error("Server did not answer in time")]
+    Timeout,
 }
 
 #[derive(Serialize, Debug, Error)]
@@ -78,8 +570,18 @@ pub enum PollInputError {
     AuthenticationError,
     #[error("Connection error")]
     ConnectionError,
+    #[error("Resolution error")]
+    ResolutionError,
+    #[error("Parse error")]
+    ParseError,
     #[error("Timeout error")]
     TimeoutError,
+    #[error("Overflow error")]
+    OverflowError,
+    #[error("Resource conflict error")]
+    ResourceConflictError,
+    #[error("Window is gone")]
+    WindowGone,
     #[error("Other error")]
     OtherError,
 }
@@ -88,6 +590,8 @@ pub enum PollInputError {
 pub enum PollOutputError {
     #[error("Packet send error")]
     PacketSendError,
+    #[error("Window is gone")]
+    WindowGone,
 }
 
 /**************************************************************************
@@ -96,476 +600,7482 @@ pub enum PollOutputError {
 
 struct ConnectionClient {
     jid: BareJid,
-    sender: UnboundedSender<Packet>,
+    sender: UnboundedSender<OutgoingPacket>,
+    shared: Arc<ConnectionShared>,
     read_handle: JoinHandle<()>,
     write_handle: JoinHandle<()>,
+    bandwidth_handle: Option<JoinHandle<()>>,
+    receive_handle: Option<JoinHandle<()>>,
+    ping_handle: Option<JoinHandle<()>>,
 }
 
 #[derive(Default)]
-pub struct ConnectionClientState {
-    connections: RwLock<HashMap<String, ConnectionClient>>,
+struct ConnectionShared {
+    id: RwLock<String>,
+    jid: RwLock<Option<BareJid>>,
+    options: ConnectOptions,
+    runtime: Option<TokioHandle>,
+    report: ConnectorReport,
+    counters: ConnectionCounters,
+    state: RwLock<ConnectionState>,
+    state_terminated: AtomicBool,
+    terminated: Notify,
+    state_emission: Mutex<()>,
+    reconnect_attempts: AtomicU32,
+    reconnect_cancelled: AtomicBool,
+    bound_before: AtomicBool,
+    bound_at: Mutex<Option<Instant>>,
+    reconnects: AtomicU32,
+    bound_jid: Mutex<Option<String>>,
+    carbons_enabled: AtomicBool,
+    last_presence: Mutex<Option<Element>>,
+    appearing_offline: AtomicBool,
+    draining: AtomicBool,
+    system_shutdown: AtomicBool,
+    reauth_required: AtomicBool,
+    idle_closed: AtomicBool,
+    idle_condition: Mutex<Option<String>>,
+    resource_conflict: AtomicBool,
+    resource_conflict_retries: AtomicU32,
+    error_kind: Mutex<Option<ConnectionErrorKind>>,
+    retired: AtomicBool,
+    window_gone: AtomicBool,
+    emitting: AtomicUsize,
+    emitted: Notify,
+    queueing: Mutex<()>,
+    queue_depth: AtomicUsize,
+    bound_waiter: Mutex<Option<oneshot::Sender<BoundResult>>>,
+    read_timeout: Duration,
+    last_received_at: Mutex<Option<Instant>>,
+    connected: Notify,
+    stale: Notify,
+    iq_waiters: Mutex<HashMap<String, oneshot::Sender<Element>>>,
+    mam_collectors: Mutex<HashMap<String, MamCollector>>,
+    recent_stanzas: Mutex<VecDeque<String>>,
+    error_history: Mutex<VecDeque<ErrorRecord>>,
+    auth_failures: Arc<AuthFailureTracker>,
+    receive_filter: RwLock<Option<StanzaFilter>>,
+    sent_ids: Mutex<VecDeque<String>>,
+    reflections: Mutex<VecDeque<ExpectedReflection>>,
+    receipts: Mutex<VecDeque<String>>,
+    receive_queue: Mutex<VecDeque<QueuedStanza>>,
+    receive_queued: Notify,
+    receive_flushing: Mutex<()>,
+    receive_streams: AtomicU64,
+    stream_management: Mutex<StreamManagement>,
+    muted: AtomicBool,
+    #[cfg(feature = "test-commands")]
+    injected_state: Mutex<Option<ConnectionState>>,
 }
 
-#[derive(Debug, Clone, Serialize)]
-struct EventConnectionState<'a> {
-    id: &'a str,
-    state: ConnectionState,
+#[derive(Default)]
+struct ConnectionCounters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    stanzas_received: AtomicU64,
+    stanzas_filtered: AtomicU64,
+    stanzas_overflowed: AtomicU64,
+    packets_queued: AtomicU64,
+    packets_sent: AtomicU64,
 }
 
-#[derive(Debug, Clone, Serialize)]
-struct EventConnectionReceive<'a> {
-    id: &'a str,
-    stanza: &'a str,
+#[derive(Default)]
+struct AuthFailureTracker {
+    settings: Option<AuthCooldownSettings>,
+    failures: DashMap<BareJid, AuthFailures>,
 }
 
-/**************************************************************************
- * HELPERS
- * ************************************************************************* */
+#[derive(Default)]
+struct AuthFailures {
+    count: u32,
+    window_start: Option<Instant>,
+    cooldown_until: Option<Instant>,
+}
 
-fn emit_connection_abort<R: Runtime>(window: &Window<R>, id: &str, state: ConnectionState) {
-    // Emit connection abort state
-    window
-        .emit(EVENT_STATE, EventConnectionState { id, state })
-        .unwrap();
+#[derive(Default)]
+struct StreamManagement {
+    outbound: bool,
+    inbound: bool,
+    handled: u32,
+    acked: u32,
+    ack_requested: bool,
+    unacked: VecDeque<Element>,
+    resumption: Option<StreamResumption>,
+}
 
-    // Also emit a disconnected event
-    // Notice: this informs the client that the connection is effectively \
-    //   disconnected, whether we encountered an error or not. Do not \
-    //   re-emit the disconnected state twice if current state already \
-    //   was 'disconnected'.
-    if state != ConnectionState::Disconnected {
-        window
-            .emit(
-                EVENT_STATE,
-                EventConnectionState {
-                    id,
-                    state: ConnectionState::Disconnected,
-                },
-            )
-            .unwrap();
-    }
+struct MamCollector {
+    archive: String,
+    messages: Vec<MamMessage>,
 }
 
-fn kill_event_handlers(connection: &ConnectionClient) {
-    connection.write_handle.abort();
-    connection.read_handle.abort();
+struct QueuedStanza {
+    event: &'static str,
+    stanza: String,
+    encoding: Option<&'static str>,
+    sequence: Option<u64>,
 }
 
-fn recover_closed_sender_channel<R: Runtime>(
-    window: &Window<R>,
-    id: &str,
-    connection: &ConnectionClient,
-) {
-    // Recover from dangling state: emit an implicit disconnected event
-    // Notice: this will prompt the implementor to destroy the client.
-    info!(
-        "Recovering: raising an implicit disconnected event for connection #{}",
-        id
-    );
+struct ExpectedReflection {
+    stanza_id: String,
+    token: String,
+    room: Option<BareJid>,
+}
 
-    // Abort both task handles (so that no other IPC gets sent)
-    kill_event_handlers(connection);
+struct EmissionGuard<'a> {
+    shared: &'a ConnectionShared,
+}
 
-    // Emit connection error event
-    emit_connection_abort(window, id, ConnectionState::ConnectionError);
+struct OutgoingPacket {
+    packet: Packet,
+    size: usize,
+    token: Option<String>,
+    tracked: bool,
+    flushed: Option<FlushSender>,
 }
 
-async fn poll_input_events<R: Runtime, C: ServerConnector>(
-    window: &Window<R>,
-    id: &str,
-    read_timeout: Duration,
-    mut client_reader: SplitStream<Client<C>>,
-) -> Result<(), PollInputError> {
-    // Wrap client reader in a timeout task; this is especially important \
-    //   since the underlying 'tokio-xmpp' does not implement any kind of \
-    //   timeout whatsoever. This timeout duration is served from the \
-    //   connection initiator, and will most likely depend on the PING \
-    //   interval set by the client.
-    while let Ok(event_maybe) = timeout(read_timeout, client_reader.next()).await {
-        // Handle next event
-        if let Some(result) = handle_next_input_event(window, id, event_maybe) {
-            // We received a non-empty result: we have to stop the loop there!
-            return result;
-        }
-    }
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ConnectOptions {
+    bandwidth_interval: Option<u64>,
+    auto_destroy_after_disconnect: bool,
+    reconnect: bool,
+    reconnect_attempts: Option<u32>,
+    recent_stanzas_size: Option<usize>,
+    send_queue_size: Option<usize>,
+    detect_duplicate_ids: bool,
+    validate_stanzas: bool,
+    wait_bound: bool,
+    initial_presence: Option<InitialPresence>,
+    carbons: bool,
+    // Notice: upon reconnecting, the previous stream gets resumed if the \
+    //   server allows it (in which case the 'resumed' state is emitted). \
+    //   Otherwise, a new session gets bound, and stanzas that the server \
+    //   did not handle get re-sent (or all unacknowledged stanzas, if the \
+    //   server did not tell which ones it handled).
+    stream_management: bool,
+    // Notice: restoring the session re-enables carbons first, then re-sends \
+    //   the last presence once carbons are enabled again (or could not be), \
+    //   so that no message sent to us in reply to our presence misses its \
+    //   carbon copy. A resumed stream needs no restoring, as its session \
+    //   was kept by the server.
+    restore_session: bool,
+    server_info: bool,
+    servers: Vec<ServerEndpoint>,
+    server_timeout: Option<u64>,
+    read_rate_limit: Option<u64>,
+    write_rate_limit: Option<u64>,
+    resource_prefix: Option<String>,
+    multiplex_events: bool,
+    cert_expiry_threshold: Option<u64>,
+    sequence_numbers: bool,
+    split_receive_events: bool,
+    resolver: Option<ResolverOptions>,
+    // Notice: large stanzas are always compressed using 'gzip', as this is \
+    //   the only format natively decompressed by web views (thus no \
+    //   algorithm can be picked there, only the size threshold).
+    compression: Option<CompressionOptions>,
+    chunked_receive: Option<ChunkedReceiveOptions>,
+    receive_filter: Option<String>,
+    tier: ConnectionTier,
+    transport: ConnectionTransport,
+    websocket_url: Option<String>,
+    jid_conflict: JidConflictPolicy,
+    heartbeat_interval: Option<u64>,
+    ping_interval: Option<u64>,
+    ping_misses: Option<u32>,
+    slow_emit_threshold: Option<u64>,
+    slow_consumer_events: bool,
+    receive_buffer: Option<ReceiveBufferOptions>,
+    strict_stream_from: bool,
+    resolve_resource_conflict: bool,
+    // Notice: replaying only applies to automatic reconnections, since a \
+    //   manual reconnection creates a new connection (the previous one, \
+    //   along with its buffer, gets destroyed), thus the implementor is \
+    //   left in charge of re-sending there. Replays might cause duplicates.
+    replay_on_reconnect: bool,
+    replay_buffer_size: Option<usize>,
+    lang: Option<String>,
+    tls_min_version: TlsVersion,
+    tls_trust: Option<TlsTrustOptions>,
+    tls_identity: Option<TlsIdentityOptions>,
+    sasl_mechanisms: Vec<String>,
+    anonymous: bool,
+    label: Option<String>,
+}
 
-    // The next event did not come in due time, consider as timed out
-    warn!(
-        "Timed out waiting {}ms for next event on: #{}",
-        read_timeout.as_millis(),
-        id
-    );
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ReceiveBufferOptions {
+    size: Option<usize>,
+    overflow: ReceiveOverflowPolicy,
+}
 
-    // Abort here (timed out)
-    // Notice: the event loop has timed out, abort connection and error out.
-    emit_connection_abort(window, id, ConnectionState::ConnectionTimeout);
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct CompressionOptions {
+    threshold: Option<usize>,
+}
 
-    Err(PollInputError::TimeoutError)
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ChunkedReceiveOptions {
+    threshold: Option<usize>,
+    chunk_size: Option<usize>,
 }
 
-async fn poll_output_events<C: ServerConnector>(
-    id: &str,
-    mut client_writer: SplitSink<Client<C>, Packet>,
-    mut rx: UnboundedReceiver<Packet>,
-) -> Result<(), PollOutputError> {
-    while let Some(packet) = rx.recv().await {
-        if let Err(err) = client_writer.send(packet).await {
-            error!(
-                "Failed sending packet over connection: #{} because: {}",
-                id, err
-            );
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct TlsTrustOptions {
+    roots: Vec<String>,
+    pin: Option<String>,
+}
 
-            return Err(PollOutputError::PacketSendError);
-        }
+// Notice: this only presents a client certificate during the TLS handshake, \
+//   which the server might require (eg. on top of password authentication). \
+//   SASL EXTERNAL is not supported, as 'tokio-xmpp' only authenticates with \
+//   its own mechanisms, thus a password is still required. A server refusing \
+//   the certificate results in an authentication failure.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct TlsIdentityOptions {
+    certificate: String,
+    // Important: never serialize the private key (eg. in diagnostics dumps).
+    #[serde(skip_serializing)]
+    key: String,
+}
 
-        debug!("Sent packet over connection: #{}", id);
-    }
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ServerEndpoint {
+    host: String,
+    port: Option<u16>,
+}
 
-    Ok(())
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ResolverOptions {
+    Plain {
+        servers: Vec<IpAddr>,
+        port: Option<u16>,
+    },
+    Https {
+        servers: Vec<IpAddr>,
+        port: Option<u16>,
+        #[serde(rename = "serverName")]
+        server_name: String,
+    },
 }
 
-fn handle_next_input_event<R: Runtime>(
-    window: &Window<R>,
-    id: &str,
-    event_maybe: Option<Event>,
-) -> Option<Result<(), PollInputError>> {
-    // Any event received? (or no event?)
-    if let Some(event) = event_maybe {
-        match event {
-            Event::Disconnected(Error::Disconnected) => {
-                info!("Received disconnected event on: #{}", id);
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SendOptions {
+    reflection: bool,
+    flush_timeout: Option<u64>,
+    receipt: bool,
+}
 
-                emit_connection_abort(window, id, ConnectionState::Disconnected);
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DisconnectOptions {
+    graceful: bool,
+    grace_timeout: Option<u64>,
+}
 
-                // Abort here (success)
-                Some(Ok(()))
-            }
-            Event::Disconnected(Error::Auth(err)) => {
-                warn!(
-                    "Received disconnected event on: #{}, with authentication error: {}",
-                    id, err
-                );
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct InitialPresence {
+    show: Option<PresenceShow>,
+    status: Option<String>,
+    priority: Option<i8>,
+}
 
-                emit_connection_abort(window, id, ConnectionState::AuthenticationFailure);
+#[derive(Default)]
+pub struct ConnectionSettings {
+    pub allow_same_jid: bool,
+    pub max_connections: Option<usize>,
+    pub tor_proxy: Option<String>,
+    pub shutdown_drain_timeout: Option<u64>,
+    pub read_timeout: Option<u64>,
+    pub runtime: Option<RuntimeSettings>,
+    pub auth_cooldown: Option<AuthCooldownSettings>,
+}
 
-                // Abort here (error)
-                Some(Err(PollInputError::AuthenticationError))
-            }
-            Event::Disconnected(Error::Connection(err)) => {
-                warn!(
-                    "Received disconnected event: #{}, with connection error: {}",
-                    id, err
-                );
+#[derive(Default)]
+pub struct RuntimeSettings {
+    pub worker_threads: Option<usize>,
+    pub thread_name: Option<String>,
+}
 
-                emit_connection_abort(window, id, ConnectionState::ConnectionError);
+#[derive(Default, Clone, Copy)]
+pub struct AuthCooldownSettings {
+    pub failures: Option<u32>,
+    pub window: Option<u64>,
+    pub cooldown: Option<u64>,
+}
 
-                // Abort here (error)
-                Some(Err(PollInputError::ConnectionError))
-            }
-            Event::Disconnected(err) => {
-                warn!("Received disconnected event: #{}, with error: {}", id, err);
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionStateDetail {
+    reason: ConnectionStateReason,
+    condition: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reconnect_delay: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tried: Option<Vec<String>>,
+}
 
-                emit_connection_abort(window, id, ConnectionState::ConnectionError);
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorRecord {
+    at: String,
+    state: ConnectionState,
+    kind: Option<ConnectionErrorKind>,
+    detail: Option<ConnectionStateDetail>,
+}
 
-                // Abort here (error)
-                Some(Err(PollInputError::OtherError))
-            }
-            Event::Online { .. } => {
-                info!("Received connected event on: #{}", id);
+#[derive(Serialize, Debug, Default)]
+pub struct PingResult {
+    rtt: Option<u64>,
+    error: Option<IqError>,
+}
 
-                window
-                    .emit(
-                        EVENT_STATE,
-                        EventConnectionState {
-                            id,
-                            state: ConnectionState::Connected,
-                        },
-                    )
-                    .unwrap();
+#[derive(Serialize, Debug, Default)]
+pub struct BroadcastResult {
+    error: Option<SendError>,
+}
 
-                // Continue
-                None
-            }
-            Event::Stanza(stanza) => {
-                debug!("Received stanza event on: #{}", id);
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerProbe {
+    sasl_mechanisms: Vec<String>,
+    compression_methods: Vec<String>,
+    // Notice: this only tells whether the server advertises stream \
+    //   management, before authentication (servers usually only advertise \
+    //   it afterwards, see stream features for the enabled state).
+    stream_management: bool,
+    registration: bool,
+}
 
-                let stanza_xml = String::from(&stanza);
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamResumption {
+    id: Option<String>,
+    location: Option<String>,
+    max: Option<u64>,
+    resume: bool,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamFeatures {
+    tls_version: Option<TlsVersion>,
+    sasl_mechanisms: Vec<String>,
+    compression_methods: Vec<String>,
+    stream_management: bool,
+    stream_resumption: Option<StreamResumption>,
+    registration: bool,
+    carbons: bool,
+    bound_jid: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionMetrics {
+    stanzas_sent: u64,
+    stanzas_received: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+    connected_for: Option<u64>,
+    reconnects: u32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RosterItem {
+    jid: String,
+    name: Option<String>,
+    subscription: String,
+    groups: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct MamQuery {
+    to: Option<String>,
+    with: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+    before: Option<String>,
+    after: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MamMessage {
+    id: String,
+    stamp: Option<String>,
+    stanza: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct MamPage {
+    messages: Vec<MamMessage>,
+    first: Option<String>,
+    last: Option<String>,
+    count: Option<u32>,
+    complete: bool,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GlobalStats {
+    connections: usize,
+    bytes_sent: u64,
+    bytes_received: u64,
+    stanzas_sent: u64,
+    stanzas_received: u64,
+    stanzas_filtered: u64,
+    states: HashMap<ConnectionState, usize>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionDiagnostics {
+    id: String,
+    jid: String,
+    state: ConnectionState,
+    error: Option<ConnectionErrorKind>,
+    reconnect_attempts: u32,
+    last_received_ago: Option<u64>,
+    time_to_timeout: u64,
+    counters: CountersDiagnostics,
+    undelivered_packets: u64,
+    receive_queue_size: usize,
+    pending_iqs: usize,
+    tasks: TasksDiagnostics,
+    options: JsonValue,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CountersDiagnostics {
+    bytes_sent: u64,
+    bytes_received: u64,
+    stanzas_received: u64,
+    stanzas_filtered: u64,
+    stanzas_overflowed: u64,
+    packets_queued: u64,
+    packets_sent: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TasksDiagnostics {
+    read: bool,
+    write: bool,
+    bandwidth: Option<bool>,
+    receive: Option<bool>,
+    ping: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConnectionEntry {
+    id: String,
+    jid: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConnectionSummary {
+    id: String,
+    jid: String,
+    state: ConnectionState,
+}
+
+#[derive(Default)]
+pub struct ConnectionClientState {
+    connections: DashMap<String, ConnectionClient>,
+    admission: Mutex<()>,
+    settings: ConnectionSettings,
+    runtime: Option<TokioHandle>,
+    auth_failures: Arc<AuthFailureTracker>,
+    default_read_timeout: RwLock<Option<u64>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EventConnectionState<'a> {
+    id: &'a str,
+    label: &'a str,
+    state: ConnectionState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ConnectionErrorKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<ConnectionStateDetail>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    jid: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EventConnectionReceive<'a> {
+    id: &'a str,
+    stanza: Cow<'a, str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EventConnectionReceiveChunk<'a> {
+    id: &'a str,
+    stream: u64,
+    marker: ReceiveChunkMarker,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunk: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EventConnectionMultiplexed<'a, P> {
+    kind: &'a str,
+    id: &'a str,
+    label: &'a str,
+    payload: &'a P,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EventConnectionCertExpiring<'a> {
+    id: &'a str,
+    expires_at: &'a str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EventConnectionServerInfo<'a> {
+    id: &'a str,
+    name: Option<String>,
+    version: Option<String>,
+    os: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a IqError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EventConnectionSent<'a> {
+    id: &'a str,
+    token: &'a str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EventConnectionReceipt<'a> {
+    id: &'a str,
+    message_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EventConnectionReflected<'a> {
+    id: &'a str,
+    token: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stamp: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stanza_id: Option<&'a str>,
+    received_at: &'a str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EventConnectionSendClosed<'a> {
+    id: &'a str,
+    reason: SendClosedReason,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EventConnectionDuplicateId<'a> {
+    id: &'a str,
+    stanza_id: &'a str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EventConnectionParseError<'a> {
+    id: &'a str,
+    error: &'a str,
+    bytes: &'a str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EventConnectionStreamMismatch<'a> {
+    id: &'a str,
+    expected: &'a str,
+    advertised: &'a str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EventConnectionHeartbeat<'a> {
+    id: &'a str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EventConnectionSlowConsumer<'a> {
+    id: &'a str,
+    event: &'a str,
+    duration: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EventConnectionBandwidth<'a> {
+    id: &'a str,
+    sent: u64,
+    received: u64,
+}
+
+/**************************************************************************
+ * IMPLEMENTATIONS
+ * ************************************************************************* */
+
+impl ConnectionState {
+    fn is_connected(self) -> bool {
+        // Notice: a resumed stream is connected as well, it only tells that \
+        //   the session was kept (rather than a new one being bound).
+        matches!(self, ConnectionState::Connected | ConnectionState::Resumed)
+    }
+}
+
+impl ConnectionSettings {
+    fn shutdown_drain_timeout(&self) -> Duration {
+        Duration::from_millis(
+            self.shutdown_drain_timeout.unwrap_or(SHUTDOWN_DRAIN_TIMEOUT_MILLISECONDS),
+        )
+    }
+}
+
+impl AuthFailureTracker {
+    fn new(settings: Option<AuthCooldownSettings>) -> Self {
+        Self {
+            settings,
+            ..Default::default()
+        }
+    }
+
+    fn record(&self, jid: &BareJid) {
+        // Tracking disabled? (nothing to record)
+        let Some(settings) = self.settings else {
+            return;
+        };
+
+        let window =
+            Duration::from_millis(settings.window.unwrap_or(AUTH_FAILURES_WINDOW_MILLISECONDS));
+
+        let mut failures = self.failures.entry(jid.to_owned()).or_default();
+
+        // Start a new window? (if previous failures are too old)
+        if failures
+            .window_start
+            .map(|window_start| window_start.elapsed() >= window)
+            .unwrap_or(true)
+        {
+            failures.count = 0;
+            failures.window_start = Some(Instant::now());
+        }
+
+        failures.count += 1;
+
+        // Too many failures in window? (enforce cooldown)
+        if failures.count >= settings.failures.unwrap_or(AUTH_FAILURES_MAXIMUM) {
+            failures.count = 0;
+            failures.window_start = None;
+
+            let cooldown =
+                Duration::from_millis(settings.cooldown.unwrap_or(AUTH_COOLDOWN_MILLISECONDS));
+
+            failures.cooldown_until = Some(Instant::now() + cooldown);
+        }
+    }
+
+    fn remaining_cooldown(&self, jid: &BareJid) -> Option<Duration> {
+        self.failures
+            .get(jid)
+            .and_then(|failures| failures.cooldown_until)
+            .map(|cooldown_until| cooldown_until.saturating_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    fn clear(&self, jid: &BareJid) {
+        self.failures.remove(jid);
+    }
+}
+
+impl StreamManagement {
+    fn acknowledge(&mut self, h: u32) {
+        // Forget about acknowledged stanzas
+        let count = h.wrapping_sub(self.acked) as usize;
+        let count = count.min(self.unacked.len());
+
+        self.unacked.drain(..count);
+
+        self.acked = h;
+        self.ack_requested = false;
+    }
+}
+
+impl ConnectionShared {
+    fn id(&self) -> String {
+        self.id.read().unwrap().clone()
+    }
+
+    fn retire(&self) {
+        // Notice: once retired, an aborted task cannot emit anymore events \
+        //   (which could otherwise get attributed to a new connection that \
+        //   re-uses the same identifier).
+        self.retired.store(true, Ordering::SeqCst);
+    }
+
+    async fn settle_emissions(&self) {
+        // Wait for in-flight emissions to complete (up to a deadline)
+        // Important: never wait indefinitely there, as a task might be stuck \
+        //   emitting (eg. if the IPC is congested), which would otherwise hang \
+        //   the caller (eg. upon destroying a connection).
+        let deadline = time::Instant::now() + Duration::from_millis(RETIRE_WAIT_MILLISECONDS);
+
+        loop {
+            let emitted = self.emitted.notified();
+
+            tokio::pin!(emitted);
+
+            // Notice: register for the wake-up before checking the count, so \
+            //   that an emission completing in between does not get missed.
+            emitted.as_mut().enable();
+
+            if self.emitting.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+
+            if time::timeout_at(deadline, emitted).await.is_err() {
+                warn!("Gave up waiting for in-flight events of retired connection");
+
+                break;
+            }
+        }
+    }
+
+    async fn wait_terminated(&self, deadline: time::Instant) -> bool {
+        loop {
+            let terminated = self.terminated.notified();
+
+            tokio::pin!(terminated);
+
+            // Notice: register for the wake-up before checking the marker, so \
+            //   that a disconnection happening in between does not get missed.
+            terminated.as_mut().enable();
+
+            if self.state_terminated.load(Ordering::SeqCst) {
+                return true;
+            }
+
+            if time::timeout_at(deadline, terminated).await.is_err() {
+                return false;
+            }
+        }
+    }
+
+    fn while_live<F: FnOnce()>(&self, emit: F) {
+        // Notice: the guard releases the in-flight count even if emitting \
+        //   panics, which must not prevent the connection from settling.
+        let _emitting = EmissionGuard::enter(self);
+
+        if self.retired.load(Ordering::SeqCst) == false {
+            emit();
+        }
+    }
+
+    fn label<'a>(&'a self, id: &'a str) -> &'a str {
+        // Notice: the label is purely presentational, and defaults to the \
+        //   connection identifier if none was given.
+        self.options.label.as_deref().unwrap_or(id)
+    }
+
+    fn current_state(&self) -> ConnectionState {
+        *self.state.read().unwrap()
+    }
+
+    fn transition_state<F: FnOnce()>(&self, state: ConnectionState, emit: F) -> bool {
+        // Notice: the emission lock is held until the state gets emitted, so \
+        //   that concurrent tasks (eg. the disconnect command racing the read \
+        //   poller timing out) emit their states in the order they applied.
+        let _emission = self.state_emission.lock().unwrap();
+
+        {
+            let mut current = self.state.write().unwrap();
+            let terminated = self.state_terminated.load(Ordering::SeqCst);
+
+            // Redundant transition? (do not apply nor emit it)
+            if is_redundant_state_transition(*current, terminated, state) {
+                return false;
+            }
+
+            *current = state;
+
+            // Notice: once disconnected, only a new session can lift the \
+            //   terminated marker (ie. upon connecting or reconnecting again).
+            match state {
+                ConnectionState::Disconnected => {
+                    self.state_terminated.store(true, Ordering::SeqCst);
+                    self.terminated.notify_waiters();
+                }
+                ConnectionState::Secured
+                | ConnectionState::Connected
+                | ConnectionState::Resumed
+                | ConnectionState::Reconnecting => {
+                    self.state_terminated.store(false, Ordering::SeqCst);
+                }
+                _ => {}
+            }
+        }
+
+        emit();
+
+        true
+    }
+
+    fn can_reconnect(&self, state: ConnectionState) -> bool {
+        // Only errors that might be transient are worth reconnecting upon \
+        //   (eg. an authentication failure will never recover by itself)
+        let is_recoverable = matches!(
+            state,
+            ConnectionState::ConnectionError
+                | ConnectionState::ConnectionTimeout
+                | ConnectionState::ResolutionFailure
+        );
+
+        // Notice: some connection errors can never succeed upon retrying, as \
+        //   they come from the configuration (eg. a certificate pin mismatch), \
+        //   thus they are terminal even though reported as connection errors.
+        let is_terminal = matches!(
+            *self.error_kind.lock().unwrap(),
+            Some(
+                ConnectionErrorKind::CertificatePinMismatch
+                    | ConnectionErrorKind::AuthNoMechanism
+                    | ConnectionErrorKind::TlsVersionUnsupported
+            )
+        );
+
+        self.options.reconnect
+            && is_recoverable
+            && !is_terminal
+            && !self.reconnect_cancelled.load(Ordering::SeqCst)
+            && self.reconnect_attempts.load(Ordering::SeqCst)
+                < self
+                    .options
+                    .reconnect_attempts
+                    .unwrap_or(RECONNECT_ATTEMPTS_MAXIMUM)
+    }
+
+    fn can_resolve_conflict(&self, err: &Error) -> bool {
+        // Notice: only conflicts happening while binding are resolved, as a \
+        //   conflict on a bound stream means another session took over ours.
+        let is_conflict = self.resource_conflict.swap(false, Ordering::SeqCst)
+            || matches!(err, Error::Protocol(ProtocolError::InvalidBindResponse));
+
+        self.options.resolve_resource_conflict
+            && is_conflict
+            && !self.current_state().is_connected()
+            && self.resource_conflict_retries.fetch_add(1, Ordering::SeqCst)
+                < RESOURCE_CONFLICT_RETRIES_MAXIMUM
+    }
+
+    fn cancel_reconnect(&self) {
+        self.reconnect_cancelled.store(true, Ordering::SeqCst);
+    }
+
+    fn next_reconnect_delay(&self) -> Duration {
+        let delay = reconnect_delay(self.reconnect_attempts.fetch_add(1, Ordering::SeqCst));
+
+        // Server is shutting down? Then back off for longer (on first attempt)
+        // Notice: this avoids hammering a server that is restarting.
+        if self.system_shutdown.swap(false, Ordering::SeqCst) {
+            return delay.max(Duration::from_millis(SYSTEM_SHUTDOWN_RECONNECT_DELAY_MILLISECONDS));
+        }
+
+        delay
+    }
+
+    fn mark_received(&self) {
+        *self.last_received_at.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn time_to_timeout(&self) -> Duration {
+        match *self.last_received_at.lock().unwrap() {
+            Some(last_received_at) => self.read_timeout.saturating_sub(last_received_at.elapsed()),
+            None => self.read_timeout,
+        }
+    }
+
+    fn undelivered_packets(&self) -> u64 {
+        // Notice: this only accounts for packets queued by the implementor \
+        //   (ie. via the send command), that never made it to the wire.
+        let sent = self.counters.packets_sent.load(Ordering::Relaxed);
+
+        self.counters
+            .packets_queued
+            .load(Ordering::Relaxed)
+            .saturating_sub(sent)
+    }
+
+    fn resolve_bound(&self, result: BoundResult) {
+        // Notice: only the first terminal state resolves the waiter (if any), \
+        //   later states (eg. upon reconnecting) are reported via events only.
+        if let Some(bound_waiter) = self.bound_waiter.lock().unwrap().take() {
+            bound_waiter.send(result).ok();
+        }
+    }
+
+    fn accepts_stanza(&self, stanza: &Element) -> bool {
+        match *self.receive_filter.read().unwrap() {
+            Some(ref receive_filter) => receive_filter.matches(stanza),
+            None => true,
+        }
+    }
+
+    fn remember_stanza(&self, stanza_xml: &str) {
+        // Recent stanzas buffer is opt-in (do not buffer anything otherwise)
+        let buffer_size = self.options.recent_stanzas_size.unwrap_or(0);
+
+        if buffer_size > 0 {
+            let mut recent_stanzas = self.recent_stanzas.lock().unwrap();
+
+            while recent_stanzas.len() >= buffer_size {
+                recent_stanzas.pop_front();
+            }
+
+            recent_stanzas.push_back(stanza_xml.to_string());
+        }
+    }
+
+    fn jid(&self) -> Option<BareJid> {
+        self.jid.read().unwrap().clone()
+    }
+
+    fn record_auth_failure(&self) {
+        // Notice: anonymous connections are not tracked, as they all share \
+        //   the same placeholder JID until bound (ie. they carry no account).
+        if self.options.anonymous {
+            return;
+        }
+
+        if let Some(ref jid) = self.jid() {
+            self.auth_failures.record(jid);
+        }
+    }
+
+    fn clear_auth_failures(&self) {
+        if self.options.anonymous {
+            return;
+        }
+
+        if let Some(ref jid) = self.jid() {
+            self.auth_failures.clear(jid);
+        }
+    }
+
+    fn remember_error(&self, state: ConnectionState, detail: Option<&ConnectionStateDetail>) {
+        let record = ErrorRecord {
+            at: OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            state,
+            kind: *self.error_kind.lock().unwrap(),
+            detail: detail.cloned(),
+        };
+
+        let mut error_history = self.error_history.lock().unwrap();
+
+        while error_history.len() >= ERROR_HISTORY_SIZE {
+            error_history.pop_front();
+        }
+
+        error_history.push_back(record);
+    }
+
+    fn start_sm_outbound(&self) {
+        let mut stream_management = self.stream_management.lock().unwrap();
+
+        // Notice: outbound stanzas are counted from the moment the enable \
+        //   request gets sent, as the server counts from there.
+        *stream_management = StreamManagement {
+            outbound: true,
+            ..Default::default()
+        };
+    }
+
+    fn tracks_sm_outbound(&self, stanza: &Element) -> bool {
+        is_counted_stanza(stanza) && self.stream_management.lock().unwrap().outbound
+    }
+
+    fn track_sm_outbound(&self, stanza: Element) {
+        let mut stream_management = self.stream_management.lock().unwrap();
+
+        // Notice: if the server does not acknowledge stanzas, then the oldest \
+        //   ones get forgotten, as if they were acknowledged (this keeps the \
+        //   acknowledged count in sync with the unacknowledged queue).
+        while stream_management.unacked.len() >= SM_UNACKED_SIZE_MAXIMUM {
+            stream_management.unacked.pop_front();
+
+            stream_management.acked = stream_management.acked.wrapping_add(1);
+        }
+
+        stream_management.unacked.push_back(stanza);
+    }
+
+    fn take_sm_unacked(&self, h: Option<u32>) -> Vec<Element> {
+        let mut stream_management = self.stream_management.lock().unwrap();
+
+        // Notice: if the server told how many stanzas it handled on the \
+        //   previous stream, then only the stanzas it did not handle are \
+        //   left, otherwise all unacknowledged stanzas are.
+        if let Some(h) = h {
+            stream_management.acknowledge(h);
+        }
+
+        stream_management.outbound = false;
+        stream_management.inbound = false;
+        stream_management.resumption = None;
+
+        stream_management.unacked.drain(..).collect()
+    }
+
+    fn resume_sm(&self, h: Option<u32>) -> Vec<Element> {
+        let mut stream_management = self.stream_management.lock().unwrap();
+
+        // Notice: the resumed stream keeps counting from the previous one, \
+        //   thus stanzas that were not handled get taken out of the queue, \
+        //   as they get tracked again once re-sent.
+        if let Some(h) = h {
+            stream_management.acknowledge(h);
+        }
+
+        stream_management.ack_requested = false;
+        stream_management.unacked.drain(..).collect()
+    }
+
+    fn sm_resume_request(&self) -> Option<(ResumeRequest, FullJid)> {
+        let stream_management = self.stream_management.lock().unwrap();
+
+        // Notice: the resumed stream keeps its bound JID, thus the client \
+        //   has to be created with it (rather than with a new resource).
+        let previd = stream_management
+            .resumption
+            .as_ref()
+            .filter(|resumption| resumption.resume)
+            .and_then(|resumption| resumption.id.clone())?;
+
+        let jid = self.bound_jid.lock().unwrap().as_deref()?.parse().ok()?;
+
+        Some((
+            ResumeRequest {
+                previd,
+                handled: stream_management.handled,
+            },
+            jid,
+        ))
+    }
+
+    fn wants_sm_ack(&self) -> bool {
+        let mut stream_management = self.stream_management.lock().unwrap();
+
+        // Request an acknowledgement? (if not already requested)
+        if stream_management.outbound
+            && !stream_management.ack_requested
+            && !stream_management.unacked.is_empty()
+        {
+            stream_management.ack_requested = true;
+
+            return true;
+        }
+
+        false
+    }
+
+    fn handle_sm_stanza(&self, stanza: &Element) -> Option<Element> {
+        let mut stream_management = self.stream_management.lock().unwrap();
+
+        // Count handled inbound stanza? (once enabled)
+        if stanza.ns() != NS_SM {
+            if stream_management.inbound && is_counted_stanza(stanza) {
+                stream_management.handled = stream_management.handled.wrapping_add(1);
+            }
+
+            return None;
+        }
+
+        match stanza.name() {
+            "enabled" => {
+                stream_management.inbound = true;
+                stream_management.handled = 0;
+
+                // Record resumption state? (only if granted by the server)
+                let resume = matches!(stanza.attr("resume"), Some("true") | Some("1"));
+
+                stream_management.resumption = resume.then(|| StreamResumption {
+                    id: stanza.attr("id").map(String::from),
+                    location: stanza.attr("location").map(String::from),
+                    max: stanza.attr("max").and_then(|max| max.parse().ok()),
+                    resume,
+                });
+            }
+            "failed" => {
+                // Notice: stanzas sent meanwhile will never get acknowledged, \
+                //   thus forget about them (they were sent as usual).
+                *stream_management = StreamManagement::default();
+            }
+            "r" if stream_management.inbound => {
+                // Answer acknowledgement request (with handled stanzas count)
+                return Some(
+                    Element::builder("a", NS_SM)
+                        .attr("h", stream_management.handled.to_string())
+                        .build(),
+                );
+            }
+            "a" => {
+                if let Some(h) = stanza.attr("h").and_then(|h| h.parse::<u32>().ok()) {
+                    stream_management.acknowledge(h);
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    fn remember_presence(&self, presence: Element) {
+        // Remember last presence (re-sent upon reconnecting, if restoring)
+        // Notice: setting an explicit presence also stops appearing offline.
+        *self.last_presence.lock().unwrap() = Some(presence);
+
+        self.appearing_offline.store(false, Ordering::SeqCst);
+    }
+
+    fn track_sent_id(&self, stanza_id: &str) -> bool {
+        let mut sent_ids = self.sent_ids.lock().unwrap();
+
+        // Stanza identifier was already sent recently? (collision)
+        if sent_ids.iter().any(|sent_id| sent_id == stanza_id) {
+            return true;
+        }
+
+        while sent_ids.len() >= SENT_IDS_TRACKING_SIZE {
+            sent_ids.pop_front();
+        }
+
+        sent_ids.push_back(stanza_id.to_string());
+
+        false
+    }
+
+    fn expect_reflection(&self, stanza: &Element, stanza_id: &str, token: &str) {
+        let mut reflections = self.reflections.lock().unwrap();
+
+        // Notice: reflections that never come back get evicted, since not \
+        //   all sent stanzas get reflected back to us.
+        while reflections.len() >= REFLECTIONS_TRACKING_SIZE {
+            reflections.pop_front();
+        }
+
+        // Notice: only group chat messages get reflected by their recipient \
+        //   (ie. the MUC room), any other message can only be reflected by \
+        //   our own server (eg. as a carbon copy).
+        let room = stanza
+            .attr("to")
+            .filter(|_| stanza.attr("type") == Some("groupchat"))
+            .and_then(|to| to.parse::<Jid>().ok())
+            .map(|to| to.to_bare());
+
+        reflections.push_back(ExpectedReflection {
+            stanza_id: stanza_id.to_string(),
+            token: token.to_string(),
+            room,
+        });
+    }
+
+    fn match_reflection(&self, stanza: &Element) -> Option<String> {
+        if !stanza.is("message", NS_CLIENT) {
+            return None;
+        }
+
+        // Notice: a sent message is correlated when reflected back to us \
+        //   with its identifier, either from the MUC room it was sent to, or \
+        //   from our own bare JID. Any other sender could forge a reflection. \
+        //   Delivery receipts are not reflections (they get reported apart).
+        let stanza_id = stanza.attr("id")?;
+        let from = stanza.attr("from")?.parse::<Jid>().ok()?.to_bare();
+
+        let jid = self.jid();
+        let mut reflections = self.reflections.lock().unwrap();
+
+        let index = reflections.iter().position(|reflection| {
+            reflection.stanza_id == stanza_id
+                && (reflection.room.as_ref() == Some(&from) || jid.as_ref() == Some(&from))
+        })?;
+
+        reflections.remove(index).map(|reflection| reflection.token)
+    }
+
+    fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::SeqCst)
+    }
+
+    fn queue_received(&self, stanza: QueuedStanza) -> ReceiveQueueing {
+        // Notice: stanzas received while muted are buffered in the receive \
+        //   buffer, following its policy, or the default one if not set.
+        let buffer = self.options.receive_buffer.as_ref();
+
+        let (size, overflow) = match buffer {
+            Some(buffer) => (
+                buffer.size.unwrap_or(RECEIVE_BUFFER_SIZE_DEFAULT).max(1),
+                buffer.overflow,
+            ),
+            None => (RECEIVE_BUFFER_SIZE_DEFAULT, ReceiveOverflowPolicy::default()),
+        };
+
+        {
+            let mut receive_queue = self.receive_queue.lock().unwrap();
+
+            // Not buffered, and not muted anymore? (emit right away)
+            // Important: this is checked while holding the queue lock, so \
+            //   that a stanza cannot overtake the ones flushed upon unmuting.
+            if buffer.is_none() && !self.is_muted() {
+                return ReceiveQueueing::Emit(stanza);
+            }
+
+            // Receive buffer is full? (apply overflow policy)
+            if receive_queue.len() >= size {
+                warn!("Receive buffer is full, applying policy: {:?}", overflow);
+
+                self.counters
+                    .stanzas_overflowed
+                    .fetch_add(1, Ordering::Relaxed);
+
+                match overflow {
+                    ReceiveOverflowPolicy::DropOldest => {
+                        receive_queue.pop_front();
+                    }
+                    ReceiveOverflowPolicy::DropNewest => return ReceiveQueueing::Queued,
+                    ReceiveOverflowPolicy::Disconnect => return ReceiveQueueing::Overflowed,
+                }
+            }
+
+            receive_queue.push_back(stanza);
+        }
+
+        // Notice: this stores a permit if the receive poller is busy \
+        //   emitting, meaning that it cannot miss a stanza queued meanwhile.
+        self.receive_queued.notify_one();
+
+        ReceiveQueueing::Queued
+    }
+
+    fn next_received(&self) -> Option<QueuedStanza> {
+        // Notice: the receive poller holds on to queued stanzas while muted.
+        if self.is_muted() {
+            return None;
+        }
+
+        self.receive_queue.lock().unwrap().pop_front()
+    }
+
+    fn next_unmuted(&self) -> Option<QueuedStanza> {
+        let mut receive_queue = self.receive_queue.lock().unwrap();
+
+        // Unmute once all queued stanzas are flushed (while holding the lock)
+        let next = receive_queue.pop_front();
+
+        if next.is_none() {
+            self.muted.store(false, Ordering::SeqCst);
+        }
+
+        next
+    }
+
+    fn expect_receipt(&self, message_id: &str) {
+        let mut receipts = self.receipts.lock().unwrap();
+
+        // Notice: receipts are never guaranteed to come back (eg. if the \
+        //   recipient does not support them), thus the oldest get evicted.
+        while receipts.len() >= RECEIPTS_TRACKING_SIZE {
+            receipts.pop_front();
+        }
+
+        receipts.push_back(message_id.to_string());
+    }
+
+    fn match_receipt<'a>(&self, stanza: &'a Element) -> Option<&'a str> {
+        if !stanza.is("message", NS_CLIENT) {
+            return None;
+        }
+
+        let message_id = stanza
+            .get_child("received", NS_RECEIPTS)
+            .and_then(|received| received.attr("id"))?;
+
+        let mut receipts = self.receipts.lock().unwrap();
+
+        let index = receipts.iter().position(|sent_id| sent_id == message_id)?;
+
+        receipts.remove(index).map(|_| message_id)
+    }
+
+    fn register_iq(&self, iq_id: &str) -> oneshot::Receiver<Element> {
+        let (tx, rx) = oneshot::channel();
+
+        self.iq_waiters.lock().unwrap().insert(iq_id.to_string(), tx);
+
+        rx
+    }
+
+    fn unregister_iq(&self, iq_id: &str) {
+        self.iq_waiters.lock().unwrap().remove(iq_id);
+    }
+
+    fn cancel_iqs(&self) -> usize {
+        // Notice: dropping the waiters resolves their pending requests as \
+        //   cancelled, as their responses will never come on a closed stream.
+        let cancelled = self.iq_waiters.lock().unwrap().drain().count();
+
+        self.mam_collectors.lock().unwrap().clear();
+
+        cancelled
+    }
+
+    fn resolve_iq(&self, stanza: Element) -> Option<Element> {
+        // Only IQ responses can resolve a pending request (pass through other \
+        //   stanzas, which will get emitted as usual)
+        if !stanza.is("iq", NS_CLIENT)
+            || !matches!(stanza.attr("type"), Some("result") | Some("error"))
+        {
+            return Some(stanza);
+        }
+
+        let waiter = stanza
+            .attr("id")
+            .and_then(|iq_id| self.iq_waiters.lock().unwrap().remove(iq_id));
+
+        if let Some(waiter) = waiter {
+            // Notice: if the waiter already went away (eg. timed out), then \
+            //   the response is dropped, as nobody is expecting it anymore.
+            waiter.send(stanza).ok();
+
+            None
+        } else {
+            Some(stanza)
+        }
+    }
+
+    fn register_mam(&self, query_id: &str, archive: &str) {
+        self.mam_collectors.lock().unwrap().insert(
+            query_id.to_string(),
+            MamCollector {
+                archive: archive.to_string(),
+                messages: Vec::new(),
+            },
+        );
+    }
+
+    fn unregister_mam(&self, query_id: &str) -> Vec<MamMessage> {
+        self.mam_collectors
+            .lock()
+            .unwrap()
+            .remove(query_id)
+            .map(|collector| collector.messages)
+            .unwrap_or_default()
+    }
+
+    fn collect_mam(&self, stanza: Element) -> Option<Element> {
+        // Only archived message results can be collected (pass through other \
+        //   stanzas, which will get emitted as usual)
+        let result = match stanza.get_child("result", NS_MAM) {
+            Some(result) if stanza.is("message", NS_CLIENT) => result,
+            _ => return Some(stanza),
+        };
+
+        let mut collectors = self.mam_collectors.lock().unwrap();
+
+        // Notice: results must come from the queried archive, otherwise any \
+        //   entity aware of a query identifier could inject forged results.
+        let from = stanza.attr("from");
+
+        let collector = match result
+            .attr("queryid")
+            .and_then(|query_id| collectors.get_mut(query_id))
+        {
+            Some(collector) if from.map_or(true, |from| from == collector.archive) => collector,
+            _ => return Some(stanza),
+        };
+
+        let forwarded = result.get_child("forwarded", NS_FORWARD);
+
+        if let (Some(result_id), Some(message)) = (
+            result.attr("id"),
+            forwarded.and_then(|forwarded| forwarded.get_child("message", NS_CLIENT)),
+        ) {
+            collector.messages.push(MamMessage {
+                id: result_id.to_string(),
+                stamp: forwarded
+                    .and_then(|forwarded| forwarded.get_child("delay", NS_DELAY))
+                    .and_then(|delay| delay.attr("stamp"))
+                    .map(|stamp| stamp.to_string()),
+                stanza: String::from(message),
+            });
+        }
+
+        None
+    }
+}
+
+impl<'a> EmissionGuard<'a> {
+    fn enter(shared: &'a ConnectionShared) -> Self {
+        shared.emitting.fetch_add(1, Ordering::SeqCst);
+
+        Self { shared }
+    }
+}
+
+impl Drop for EmissionGuard<'_> {
+    fn drop(&mut self) {
+        // Wake up settling tasks? (if last in-flight emission)
+        // Notice: settling tasks register before checking the count, thus \
+        //   waking up current waiters only is enough there.
+        if self.shared.emitting.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.emitted.notify_waiters();
+        }
+    }
+}
+
+/**************************************************************************
+ * HELPERS
+ * ************************************************************************* */
+
+fn log_target(id: &str) -> String {
+    format!("{}::{}", LOG_TARGET, id)
+}
+
+fn log_target_id(target: &str) -> Option<&str> {
+    target
+        .strip_prefix(LOG_TARGET)
+        .and_then(|target| target.strip_prefix("::"))
+}
+
+pub fn log_level_for(target: &str) -> Option<LevelFilter> {
+    log_target_id(target).and_then(|id| LOG_LEVELS.get(id).map(|level| *level))
+}
+
+pub fn log_level_max() -> Option<LevelFilter> {
+    LOG_LEVELS.iter().map(|level| *level.value()).max()
+}
+
+pub fn log_label_for(target: &str) -> Option<String> {
+    log_target_id(target).and_then(|id| LOG_LABELS.get(id).map(|label| label.to_owned()))
+}
+
+fn forget_log_overrides(id: &str) {
+    LOG_LABELS.remove(id);
+
+    if LOG_LEVELS.remove(id).is_some() {
+        logger::refresh_max_level();
+    }
+}
+
+fn emit_connection_event<R: Runtime, P: Serialize + Clone>(
+    window: &Window<R>,
+    id: &str,
+    shared: &ConnectionShared,
+    event: &str,
+    payload: P,
+) {
+    // Notice: events from a destroyed connection are ignored, as they \
+    //   might come from tasks that did not fully terminate yet.
+    shared.while_live(|| {
+        let emit_started_at = Instant::now();
+
+        emit_window_event(window, id, shared, event, payload);
+
+        // Emit took too long? (the frontend is a slow consumer)
+        // Notice: emits are synchronous, meaning that a slow frontend handler \
+        //   stalls the emitting poller, which would otherwise only show up as \
+        //   mysterious timeouts.
+        let emit_duration = emit_started_at.elapsed();
+
+        let threshold = shared
+            .options
+            .slow_emit_threshold
+            .unwrap_or(SLOW_EMIT_THRESHOLD_MILLISECONDS);
+
+        if emit_duration >= Duration::from_millis(threshold) {
+            warn!(
+                target: &log_target(id),
+                "Slow consumer on: #{}, emitting '{}' took {}ms",
+                id,
+                event,
+                emit_duration.as_millis()
+            );
+
+            // Also tell the frontend about it? (opt-in)
+            if shared.options.slow_consumer_events {
+                emit_window_event(
+                    window,
+                    id,
+                    shared,
+                    EVENT_SLOW_CONSUMER,
+                    EventConnectionSlowConsumer {
+                        id,
+                        event,
+                        duration: emit_duration.as_millis() as u64,
+                    },
+                );
+            }
+        }
+    });
+}
+
+fn emit_window_event<R: Runtime, P: Serialize + Clone>(
+    window: &Window<R>,
+    id: &str,
+    shared: &ConnectionShared,
+    event: &str,
+    payload: P,
+) {
+    // Also emit on the multiplexed channel? (opt-in)
+    // Notice: this is an alternative emission format, allowing the \
+    //   client to subscribe to all events at once, then dispatch them \
+    //   based on their kind. The granular event is always emitted.
+    if shared.options.multiplex_events {
+        let result = window.emit(
+            EVENT_MULTIPLEXED,
+            EventConnectionMultiplexed {
+                kind: event.strip_prefix(EVENT_PREFIX).unwrap_or(event),
+                id,
+                label: shared.label(id),
+                payload: &payload,
+            },
+        );
+
+        if let Err(err) = result {
+            fail_window_event(id, shared, EVENT_MULTIPLEXED, err);
+        }
+    }
+
+    if let Err(err) = window.emit(event, payload) {
+        fail_window_event(id, shared, event, err);
+    }
+}
+
+fn fail_window_event(id: &str, shared: &ConnectionShared, event: &str, err: tauri::Error) {
+    // Window was not found? (otherwise this event only failed on its own)
+    // Notice: emitting might also fail for other reasons than the window \
+    //   being gone (eg. if the payload cannot be serialized), in which case \
+    //   the connection must be kept alive.
+    let window_gone = matches!(
+        err,
+        tauri::Error::WebviewNotFound | tauri::Error::WindowNotFound
+    );
+
+    if !window_gone {
+        warn!(
+            target: &log_target(id),
+            "Could not emit '{}' on: #{}, because: {}",
+            event, id, err
+        );
+
+        return;
+    }
+
+    // Notice: the window might be gone (eg. while the application quits), \
+    //   which must never bring down the connection tasks. Only the first \
+    //   failure gets logged, as all next emits are expected to fail too.
+    if !shared.window_gone.swap(true, Ordering::SeqCst) {
+        warn!(
+            target: &log_target(id),
+            "Could not emit '{}' on: #{}, because window is gone: {}",
+            event, id, err
+        );
+    }
+}
+
+fn emit_certificate_expiry<R: Runtime>(window: &Window<R>, id: &str, shared: &ConnectionShared) {
+    let Some(expires_at) = shared.report.certificate_expiry() else {
+        return;
+    };
+
+    let threshold = shared
+        .options
+        .cert_expiry_threshold
+        .unwrap_or(CERT_EXPIRY_THRESHOLD_MILLISECONDS);
+
+    // Server certificate expires soon? Warn about it (but still connect)
+    // Notice: this is a proactive signal, as users only get to know about \
+    //   an expired certificate once connections start failing.
+    let remaining = expires_at.saturating_sub(OffsetDateTime::now_utc().unix_timestamp());
+
+    if remaining.saturating_mul(1000) > threshold as i64 {
+        return;
+    }
+
+    let expires_at = OffsetDateTime::from_unix_timestamp(expires_at)
+        .ok()
+        .and_then(|expires_at| expires_at.format(&Rfc3339).ok())
+        .unwrap_or_default();
+
+    warn!(
+        target: &log_target(id),
+        "Connection #{} server certificate expires soon, at: {}",
+        id, expires_at
+    );
+
+    emit_connection_event(
+        window,
+        id,
+        shared,
+        EVENT_CERT_EXPIRING,
+        EventConnectionCertExpiring {
+            id,
+            expires_at: &expires_at,
+        },
+    );
+}
+
+fn emit_stream_mismatch<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    shared: &ConnectionShared,
+    bound_jid: &Jid,
+) {
+    // Notice: servers are not required to advertise where their stream is \
+    //   from, in which case it cannot be verified.
+    let Some(advertised) = shared.report.stream_from() else {
+        return;
+    };
+
+    let expected = bound_jid.domain().as_str();
+
+    // Server stream is from another domain? Warn about it (but still connect)
+    // Notice: this might indicate misrouting or an interception attempt, \
+    //   connections can refuse those streams altogether if strict.
+    if !advertised.eq_ignore_ascii_case(expected) {
+        warn!(
+            target: &log_target(id),
+            "Connection #{} server stream is from: {}, but expected: {}",
+            id, advertised, expected
+        );
+
+        emit_connection_event(
+            window,
+            id,
+            shared,
+            EVENT_STREAM_MISMATCH,
+            EventConnectionStreamMismatch {
+                id,
+                expected,
+                advertised: &advertised,
+            },
+        );
+    }
+}
+
+fn emit_connection_reflected<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    shared: &ConnectionShared,
+    stanza: &Element,
+    token: &str,
+) {
+    // Notice: servers are not required to stamp reflected messages, in which \
+    //   case the implementor should fall back to the local reception time.
+    let stamp = stanza
+        .get_child("delay", NS_DELAY)
+        .and_then(|delay| delay.attr("stamp"));
+
+    let stanza_id = stanza
+        .get_child("stanza-id", NS_STANZA_ID)
+        .and_then(|stanza_id| stanza_id.attr("id"));
+
+    let received_at = OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default();
+
+    debug!(
+        target: &log_target(id),
+        "Connection #{} sent stanza was reflected for token: {}",
+        id, token
+    );
+
+    emit_connection_event(
+        window,
+        id,
+        shared,
+        EVENT_REFLECTED,
+        EventConnectionReflected {
+            id,
+            token,
+            stamp,
+            stanza_id,
+            received_at: &received_at,
+        },
+    );
+}
+
+fn emit_connection_state<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    shared: &ConnectionShared,
+    state: ConnectionState,
+) {
+    emit_connection_state_detail(window, id, shared, state, None)
+}
+
+fn emit_connection_state_detail<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    shared: &ConnectionShared,
+    state: ConnectionState,
+    detail: Option<ConnectionStateDetail>,
+) {
+    // Attach the underlying error kind? (on error states only)
+    let error = match state {
+        ConnectionState::Secured
+        | ConnectionState::Connected
+        | ConnectionState::Resumed
+        | ConnectionState::Disconnected => None,
+        _ => *shared.error_kind.lock().unwrap(),
+    };
+
+    // Attach the bound JID? (on connected states only)
+    // Notice: this lets the client learn about its full JID, which might \
+    //   differ from the requested one (eg. if assigned by the server).
+    let jid = shared
+        .bound_jid
+        .lock()
+        .unwrap()
+        .clone()
+        .filter(|_| state.is_connected());
+
+    let emitted = shared.transition_state(state, || {
+        emit_connection_event(
+            window,
+            id,
+            shared,
+            EVENT_STATE,
+            EventConnectionState {
+                id,
+                label: shared.label(id),
+                state,
+                error,
+                detail,
+                jid,
+            },
+        );
+    });
+
+    if !emitted {
+        debug!(
+            target: &log_target(id),
+            "Suppressed redundant state on: #{}, state: {:?}",
+            id, state
+        );
+    }
+}
+
+fn emit_connection_abort<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    shared: &ConnectionShared,
+    state: ConnectionState,
+) {
+    emit_connection_abort_detail(window, id, shared, state, None)
+}
+
+fn emit_connection_abort_detail<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    shared: &ConnectionShared,
+    state: ConnectionState,
+    detail: Option<ConnectionStateDetail>,
+) {
+    // Remember error in history? (if an error state)
+    // Notice: this is done before checking for reconnection, so that \
+    //   transient errors also get remembered (they are not emitted).
+    if state != ConnectionState::Disconnected {
+        shared.remember_error(state, detail.as_ref());
+    }
+
+    // Will reconnect? Then only emit a reconnecting state
+    // Notice: this tells the client that the connection is only temporarily \
+    //   interrupted, thus the underlying error state, and the disconnected \
+    //   state that usually follows, are suppressed until all reconnection \
+    //   attempts run out. Do not re-emit the reconnecting state if current \
+    //   state already was 'reconnecting' (ie. a reconnection attempt failed).
+    if shared.can_reconnect(state) {
+        if shared.current_state() != ConnectionState::Reconnecting {
+            emit_connection_state_detail(window, id, shared, ConnectionState::Reconnecting, detail);
+        }
+
+        return;
+    }
+
+    // Flush queued stanzas (before the terminal state)
+    // Notice: this lets the implementor receive all stanzas that it was \
+    //   sent before the connection went away, as the receive poller gets \
+    //   aborted upon teardown. Stanzas held back while muted are dropped.
+    flush_received_stanzas(window, shared);
+
+    // Emit connection abort state
+    emit_connection_state_detail(window, id, shared, state, detail);
+
+    shared.resolve_bound(Err(state));
+
+    // Cancel pending requests (as they would otherwise hang until timeout)
+    let cancelled = shared.cancel_iqs();
+
+    if cancelled > 0 {
+        debug!(
+            target: &log_target(id),
+            "Connection #{} cancelled {} pending requests",
+            id, cancelled
+        );
+    }
+
+    // Also emit a disconnected event
+    // Notice: this informs the client that the connection is effectively \
+    //   disconnected, whether we encountered an error or not. Do not \
+    //   re-emit the disconnected state twice if current state already \
+    //   was 'disconnected' (racing tasks get deduplicated when emitting).
+    if state != ConnectionState::Disconnected {
+        emit_connection_state(window, id, shared, ConnectionState::Disconnected);
+    }
+}
+
+fn is_redundant_state_transition(
+    current: ConnectionState,
+    terminated: bool,
+    next: ConnectionState,
+) -> bool {
+    let is_session = |state| {
+        matches!(
+            state,
+            ConnectionState::Secured
+                | ConnectionState::Connected
+                | ConnectionState::Resumed
+                | ConnectionState::Reconnecting
+        )
+    };
+    let is_error = |state| !is_session(state) && state != ConnectionState::Disconnected;
+
+    match next {
+        // Nothing but a new session can follow a disconnection
+        _ if terminated => !is_session(next),
+        // An error state cannot follow another error state (the first one \
+        //   wins, as a disconnected state is about to follow)
+        _ if is_error(current) && is_error(next) => true,
+        // Notice: the initial state is also 'disconnected', which does not \
+        //   make a disconnected state redundant (as it is not terminated).
+        ConnectionState::Disconnected => false,
+        _ => next == current,
+    }
+}
+
+fn reconnect_delay(attempt: u32) -> Duration {
+    // Exponential backoff (1s, 2s, 4s, etc.), capped to a maximum delay
+    let delay = RECONNECT_DELAY_INITIAL_MILLISECONDS.saturating_mul(1 << attempt.min(16));
+
+    Duration::from_millis(delay.min(RECONNECT_DELAY_MAXIMUM_MILLISECONDS))
+}
+
+fn make_authentication_failure_detail(error: &AuthError) -> ConnectionStateDetail {
+    match error {
+        AuthError::Fail(condition) => {
+            let reason = match condition {
+                SaslDefinedCondition::NotAuthorized
+                | SaslDefinedCondition::InvalidAuthzid => {
+                    ConnectionStateReason::BadCredentials
+                }
+                SaslDefinedCondition::AccountDisabled => {
+                    ConnectionStateReason::AccountDisabled
+                }
+                SaslDefinedCondition::CredentialsExpired => {
+                    ConnectionStateReason::CredentialsExpired
+                }
+                SaslDefinedCondition::TemporaryAuthFailure => {
+                    ConnectionStateReason::TemporaryFailure
+                }
+                SaslDefinedCondition::EncryptionRequired => {
+                    ConnectionStateReason::EncryptionRequired
+                }
+                SaslDefinedCondition::InvalidMechanism
+                | SaslDefinedCondition::MechanismTooWeak => {
+                    ConnectionStateReason::MechanismUnsupported
+                }
+                SaslDefinedCondition::Aborted => ConnectionStateReason::Aborted,
+                _ => ConnectionStateReason::Other,
+            };
+
+            // Notice: also pass the raw SASL condition, so that the client \
+            //   can still tell conditions mapped to the same reason apart.
+            ConnectionStateDetail {
+                reason,
+                condition: Some(Element::from(condition.clone()).name().to_string()),
+                reconnect_delay: None,
+                tried: None,
+            }
+        }
+        AuthError::NoMechanism => ConnectionStateDetail {
+            reason: ConnectionStateReason::MechanismUnsupported,
+            condition: None,
+            reconnect_delay: None,
+            tried: None,
+        },
+        _ => ConnectionStateDetail {
+            reason: ConnectionStateReason::Other,
+            condition: None,
+            reconnect_delay: None,
+            tried: None,
+        },
+    }
+}
+
+fn make_client(
+    jid: &FullJid,
+    password: &str,
+    connector: ConnectionConnector,
+) -> Client<ConnectionConnector> {
+    // Notice: use our own server connector, which reports on the connection \
+    //   step that failed (if any), so that eg. DNS resolution failures can be \
+    //   told apart from other network errors.
+    let mut client = Client::new_with_config(AsyncConfig {
+        jid: jid.clone().into(),
+        password: password.to_string(),
+        server: connector,
+    });
+
+    // Clients are single-use only
+    // Notice: reconnections are handled on our end (with a backoff), using a \
+    //   new client for each attempt.
+    client.set_reconnect(false);
+
+    client
+}
+
+fn make_tls_trust(options: Option<&TlsTrustOptions>) -> Result<TlsTrust, ConnectError> {
+    let Some(options) = options else {
+        return Ok(TlsTrust::default());
+    };
+
+    // Notice: each root is a PEM document, which may hold multiple \
+    //   certificates (eg. a full certificate authority chain).
+    let mut roots = Vec::new();
+
+    for pem in &options.roots {
+        roots.extend(connector::parse_certificates(pem).ok_or(ConnectError::InvalidCertificate)?);
+    }
+
+    let pin = options
+        .pin
+        .as_deref()
+        .map(|pin| connector::parse_fingerprint(pin).ok_or(ConnectError::InvalidCertificatePin))
+        .transpose()?;
+
+    Ok(TlsTrust { roots, pin })
+}
+
+fn make_error_kind(err: &Error) -> ConnectionErrorKind {
+    match err {
+        Error::Io(err) => match err.kind() {
+            IoErrorKind::ConnectionRefused => ConnectionErrorKind::IoConnectionRefused,
+            IoErrorKind::ConnectionReset => ConnectionErrorKind::IoConnectionReset,
+            IoErrorKind::ConnectionAborted => ConnectionErrorKind::IoConnectionAborted,
+            IoErrorKind::TimedOut => ConnectionErrorKind::IoTimedOut,
+            IoErrorKind::UnexpectedEof => ConnectionErrorKind::IoUnexpectedEof,
+            IoErrorKind::InvalidData => ConnectionErrorKind::ProtocolParse,
+            _ => ConnectionErrorKind::IoOther,
+        },
+        Error::Connection(err) => {
+            // Notice: connector errors are type-erased by 'tokio-xmpp', thus \
+            //   the original error has to be downcast back from there.
+            let err: &(dyn std::error::Error + 'static) = err.as_ref();
+
+            match err.downcast_ref::<ConnectorError>() {
+                Some(ConnectorError::ResolverUnavailable) => {
+                    ConnectionErrorKind::ResolverUnavailable
+                }
+                Some(ConnectorError::ResolveFailed(_)) => ConnectionErrorKind::ResolveFailed,
+                Some(ConnectorError::ConnectFailed) => ConnectionErrorKind::ConnectFailed,
+                Some(ConnectorError::ConnectTimeout) => ConnectionErrorKind::ConnectTimeout,
+                Some(ConnectorError::TorProxyMissing) => ConnectionErrorKind::TorProxyMissing,
+                Some(ConnectorError::ProxyFailed(_)) => ConnectionErrorKind::ProxyFailed,
+                Some(ConnectorError::InvalidServerName) => {
+                    ConnectionErrorKind::TlsInvalidServerName
+                }
+                Some(ConnectorError::TlsFailed(_)) => ConnectionErrorKind::TlsFailed,
+                Some(ConnectorError::TlsVersionUnsupported(_)) => {
+                    ConnectionErrorKind::TlsVersionUnsupported
+                }
+                Some(ConnectorError::CertificatePinMismatch(_)) => {
+                    ConnectionErrorKind::CertificatePinMismatch
+                }
+                Some(ConnectorError::StreamMismatch(_)) => ConnectionErrorKind::StreamMismatch,
+                Some(ConnectorError::NoAcceptableSaslMechanism) => {
+                    ConnectionErrorKind::AuthNoMechanism
+                }
+                Some(ConnectorError::CertificateRejected(_)) => {
+                    ConnectionErrorKind::AuthCertificateRejected
+                }
+                Some(ConnectorError::WebSocketNotDiscovered(_)) => {
+                    ConnectionErrorKind::WebSocketNotDiscovered
+                }
+                Some(ConnectorError::WebSocketFailed(_)) => ConnectionErrorKind::WebSocketFailed,
+                None => ConnectionErrorKind::ConnectorOther,
+            }
+        }
+        Error::Protocol(ProtocolError::NoTls) => ConnectionErrorKind::ProtocolNoTls,
+        Error::Protocol(ProtocolError::Parser(_)) => ConnectionErrorKind::ProtocolParse,
+        Error::Protocol(_) => ConnectionErrorKind::ProtocolOther,
+        Error::Auth(AuthError::NoMechanism) => ConnectionErrorKind::AuthNoMechanism,
+        Error::Auth(AuthError::Fail(_)) => ConnectionErrorKind::AuthFailure,
+        Error::Auth(_) => ConnectionErrorKind::AuthOther,
+        _ => ConnectionErrorKind::Other,
+    }
+}
+
+fn is_parse_error(err: &Error) -> bool {
+    match err {
+        Error::Protocol(ProtocolError::Parser(_)) => true,
+        Error::Io(err) => err.kind() == IoErrorKind::InvalidData,
+        _ => false,
+    }
+}
+
+fn check_stanza_structure(stanza: &Element) -> Result<(), String> {
+    // Root must be a known stanza type
+    if stanza.ns() != NS_CLIENT {
+        return Err(format!("unexpected namespace: {}", stanza.ns()));
+    }
+
+    let allowed_types: &[&str] = match stanza.name() {
+        "message" => &["chat", "error", "groupchat", "headline", "normal"],
+        "presence" => &[
+            "error",
+            "probe",
+            "subscribe",
+            "subscribed",
+            "unavailable",
+            "unsubscribe",
+            "unsubscribed",
+        ],
+        "iq" => &["error", "get", "result", "set"],
+        name => return Err(format!("unexpected root element: {}", name)),
+    };
+
+    // Type (if any) must be allowed for this stanza type
+    // Notice: IQs must always have a type and an identifier.
+    match stanza.attr("type") {
+        Some(kind) if !allowed_types.contains(&kind) => {
+            return Err(format!("unexpected type: {}", kind));
+        }
+        None if stanza.name() == "iq" => return Err("missing type".to_string()),
+        _ => {}
+    }
+
+    if stanza.name() == "iq" && stanza.attr("id").is_none() {
+        return Err("missing identifier".to_string());
+    }
+
+    // JID attributes (if any) must parse
+    for attribute in ["to", "from"] {
+        if let Some(value) = stanza.attr(attribute) {
+            if Jid::new(value).is_err() {
+                return Err(format!("invalid JID in '{}': {}", attribute, value));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn make_initial_presence(initial_presence: &InitialPresence) -> Element {
+    make_presence(
+        initial_presence.show,
+        initial_presence.status.as_deref(),
+        initial_presence.priority,
+        false,
+    )
+}
+
+fn make_presence(
+    show: Option<PresenceShow>,
+    status: Option<&str>,
+    priority: Option<i8>,
+    unavailable: bool,
+) -> Element {
+    let mut presence = Element::builder("presence", NS_CLIENT);
+
+    if unavailable {
+        presence = presence.attr("type", "unavailable");
+    }
+
+    if let Some(show) = show {
+        let show = match show {
+            PresenceShow::Away => "away",
+            PresenceShow::Chat => "chat",
+            PresenceShow::Dnd => "dnd",
+            PresenceShow::Xa => "xa",
+        };
+
+        presence = presence.append(Element::builder("show", NS_CLIENT).append(show).build());
+    }
+
+    if let Some(status) = status {
+        presence = presence.append(Element::builder("status", NS_CLIENT).append(status).build());
+    }
+
+    if let Some(priority) = priority {
+        presence = presence.append(
+            Element::builder("priority", NS_CLIENT)
+                .append(priority.to_string())
+                .build(),
+        );
+    }
+
+    presence.build()
+}
+
+fn make_connector_transport(options: &ConnectOptions) -> Result<ConnectorTransport, ConnectError> {
+    match (options.transport, options.websocket_url.as_deref()) {
+        (ConnectionTransport::Tcp, None) => Ok(ConnectorTransport::Tcp),
+        (ConnectionTransport::Tcp, Some(_)) => Err(ConnectError::InvalidWebSocketUrl),
+        // Notice: server endpoints cannot be used over WebSocket, as the \
+        //   WebSocket URL already tells where to connect.
+        (ConnectionTransport::WebSocket, _) if !options.servers.is_empty() => {
+            Err(ConnectError::InvalidServerEndpoint)
+        }
+        (ConnectionTransport::WebSocket, url) => Ok(ConnectorTransport::WebSocket {
+            url: url
+                .map(|url| parse_websocket_url(url).ok_or(ConnectError::InvalidWebSocketUrl))
+                .transpose()?,
+        }),
+    }
+}
+
+fn make_connector_resolver(resolver: Option<&ResolverOptions>) -> ConnectorResolver {
+    match resolver {
+        None => ConnectorResolver::System,
+        Some(ResolverOptions::Plain { servers, port }) => ConnectorResolver::Plain {
+            addresses: servers.clone(),
+            port: *port,
+        },
+        Some(ResolverOptions::Https {
+            servers,
+            port,
+            server_name,
+        }) => ConnectorResolver::Https {
+            addresses: servers.clone(),
+            port: *port,
+            server_name: server_name.clone(),
+        },
+    }
+}
+
+fn make_stanza_payload<'a>(
+    target: &str,
+    stanza_xml: &'a str,
+    compression: Option<&CompressionOptions>,
+) -> (Cow<'a, str>, Option<&'static str>) {
+    // Compress stanza? (only if large enough, as compressing small stanzas \
+    //   costs more than what it saves)
+    if let Some(compression) = compression {
+        let threshold = compression.threshold.unwrap_or(COMPRESSION_THRESHOLD_DEFAULT);
+
+        if stanza_xml.len() >= threshold {
+            match compress::compress(stanza_xml) {
+                Ok(compressed) => return (Cow::Owned(compressed), Some(compress::ENCODING)),
+                Err(err) => warn!(target: target, "Could not compress stanza: {}", err),
+            }
+        }
+    }
+
+    (Cow::Borrowed(stanza_xml), None)
+}
+
+fn make_stanza_chunks(stanza: &str, chunk_size: usize) -> Vec<&str> {
+    let mut chunks = Vec::with_capacity(stanza.len() / chunk_size.max(1) + 1);
+    let mut start = 0;
+
+    while start < stanza.len() {
+        let mut end = (start + chunk_size.max(1)).min(stanza.len());
+
+        // Notice: chunks must not split a multi-byte character, thus move \
+        //   the chunk end back to the nearest character boundary, or forward \
+        //   if the chunk size is smaller than the character.
+        while !stanza.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        if end == start {
+            end = start + stanza[start..].chars().next().map_or(1, char::len_utf8);
+        }
+
+        chunks.push(&stanza[start..end]);
+
+        start = end;
+    }
+
+    chunks
+}
+
+fn is_valid_lang(lang: &str) -> bool {
+    !lang.is_empty()
+        && lang.len() <= LANG_SIZE_MAXIMUM
+        && lang
+            .split('-')
+            .all(|subtag| !subtag.is_empty() && subtag.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+fn is_valid_server_endpoint(server: &ServerEndpoint) -> bool {
+    !server.host.is_empty()
+        && !server.host.contains(char::is_whitespace)
+        && server.port != Some(0)
+}
+
+fn make_resource(resource_prefix: &str) -> String {
+    let suffix = Uuid::new_v4().simple().to_string();
+
+    format!("{}{}", resource_prefix, &suffix[..8])
+}
+
+fn make_full_jid(jid: &str, resource_prefix: Option<&str>) -> Result<FullJid, ConnectError> {
+    let jid = Jid::new(jid).or(Err(ConnectError::InvalidJid))?;
+
+    match (jid.try_into_full(), resource_prefix) {
+        // Resource is set, and no resource policy (accept as-is)
+        (Ok(jid_full), None) => Ok(jid_full),
+        // Resource is set, it must follow the resource policy
+        (Ok(jid_full), Some(resource_prefix)) => {
+            if jid_full.resource().as_str().starts_with(resource_prefix) {
+                Ok(jid_full)
+            } else {
+                Err(ConnectError::InvalidResource)
+            }
+        }
+        // Resource is not set, generate one following the resource policy
+        // Notice: the generated resource is random, but always starts with \
+        //   the prefix, so that resources stay consistent across devices.
+        (Err(jid_bare), Some(resource_prefix)) => jid_bare
+            .with_resource_str(&make_resource(resource_prefix))
+            .or(Err(ConnectError::InvalidResource)),
+        // Resource is not set, and no resource policy
+        (Err(_), None) => Err(ConnectError::InvalidJid),
+    }
+}
+
+fn make_anonymous_jid(jid: &str, resource_prefix: Option<&str>) -> Result<String, ConnectError> {
+    let jid = Jid::new(jid).or(Err(ConnectError::InvalidJid))?;
+
+    // Notice: a placeholder local part is used, as the credentials are \
+    //   ignored by the SASL ANONYMOUS mechanism anyway.
+    Ok(format!(
+        "{}@{}/{}",
+        ANONYMOUS_NODE,
+        jid.domain(),
+        make_resource(resource_prefix.unwrap_or_default())
+    ))
+}
+
+fn make_sasl_mechanisms(options: &ConnectOptions) -> Vec<String> {
+    // Notice: anonymous connections must authenticate anonymously, as any \
+    //   other mechanism would authenticate with the placeholder JID.
+    if options.anonymous {
+        vec![ANONYMOUS_SASL_MECHANISM.to_string()]
+    } else {
+        options.sasl_mechanisms.clone()
+    }
+}
+
+fn clone_outgoing_packet(outgoing: &OutgoingPacket) -> Option<OutgoingPacket> {
+    let packet = match outgoing.packet {
+        Packet::Stanza(ref stanza) => Packet::Stanza(stanza.clone()),
+        Packet::StreamEnd => Packet::StreamEnd,
+        _ => return None,
+    };
+
+    Some(OutgoingPacket {
+        packet,
+        size: outgoing.size,
+        token: outgoing.token.clone(),
+        tracked: outgoing.tracked,
+        flushed: outgoing.flushed.clone(),
+    })
+}
+
+fn make_replay_packet(
+    shared: &ConnectionShared,
+    outgoing: &OutgoingPacket,
+) -> Option<OutgoingPacket> {
+    // Only stanzas sent by the implementor are worth replaying
+    if shared.options.replay_on_reconnect == false || !outgoing.tracked {
+        return None;
+    }
+
+    // Stanza is tracked by stream management? (do not replay it)
+    // Notice: unacknowledged stanzas already get re-sent by stream \
+    //   management upon reconnecting, thus replaying them would send them \
+    //   twice. Replays only apply if stream management is not enabled.
+    if let Packet::Stanza(ref stanza) = outgoing.packet {
+        if shared.tracks_sm_outbound(stanza) {
+            return None;
+        }
+    }
+
+    // Notice: replayed packets are not correlated again, as the implementor \
+    //   already got told about them the first time they got sent.
+    clone_outgoing_packet(outgoing).map(|replay| OutgoingPacket {
+        token: None,
+        tracked: false,
+        flushed: None,
+        ..replay
+    })
+}
+
+fn buffer_replay_packet(
+    shared: &ConnectionShared,
+    replay: &mut VecDeque<OutgoingPacket>,
+    outgoing: OutgoingPacket,
+) {
+    let buffer_size = shared
+        .options
+        .replay_buffer_size
+        .unwrap_or(REPLAY_BUFFER_SIZE_DEFAULT);
+
+    while !replay.is_empty() && replay.len() >= buffer_size {
+        replay.pop_front();
+    }
+
+    if buffer_size > 0 {
+        replay.push_back(outgoing);
+    }
+}
+
+fn buffer_pending_packet(
+    target: &str,
+    id: &str,
+    shared: &ConnectionShared,
+    pending: &mut VecDeque<OutgoingPacket>,
+    outgoing: OutgoingPacket,
+) {
+    // Notice: if the send queue is bounded, then stanzas queued by the \
+    //   implementor never get dropped there, since they still count towards \
+    //   the send queue size (which refuses new stanzas once full). Only \
+    //   internal packets can get dropped in such a case.
+    let bounded = shared.options.send_queue_size.is_some();
+
+    while pending.len() >= RECONNECT_BUFFER_SIZE {
+        let index = if bounded {
+            pending.iter().position(|pending| !pending.tracked)
+        } else {
+            Some(0)
+        };
+
+        let Some(dropped) = index.and_then(|index| pending.remove(index)) else {
+            break;
+        };
+
+        warn!(
+            target: target,
+            "Dropped oldest pending packet on connection: #{}, as buffer is full",
+            id
+        );
+
+        if dropped.tracked {
+            shared.queue_depth.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    pending.push_back(outgoing);
+}
+
+fn send_internal_stanza(sender: &WeakUnboundedSender<OutgoingPacket>, stanza: Element) {
+    let size = String::from(&stanza).len();
+
+    if let Some(sender) = sender.upgrade() {
+        sender
+            .send(OutgoingPacket {
+                packet: Packet::Stanza(stanza),
+                size,
+                token: None,
+                tracked: false,
+                flushed: None,
+            })
+            .ok();
+    }
+}
+
+fn is_counted_stanza(stanza: &Element) -> bool {
+    stanza.ns() == NS_CLIENT && matches!(stanza.name(), "message" | "presence" | "iq")
+}
+
+fn is_broadcast_presence(stanza: &Element) -> bool {
+    stanza.is("presence", NS_CLIENT)
+        && stanza.attr("to").is_none()
+        && matches!(stanza.attr("type"), None | Some("unavailable"))
+}
+
+fn make_iq_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+async fn request_iq(
+    sender: &UnboundedSender<OutgoingPacket>,
+    shared: &ConnectionShared,
+    iq: Element,
+    request_timeout: Duration,
+) -> Result<Element, IqError> {
+    let iq_id = iq.attr("id").ok_or(IqError::CannotBuild)?.to_string();
+
+    // Register response waiter (before sending, so that the response \
+    //   cannot race the registration)
+    let waiter = shared.register_iq(&iq_id);
+
+    let size = String::from(&iq).len();
+
+    if sender
+        .send(OutgoingPacket {
+            packet: Packet::Stanza(iq),
+            size,
+            token: None,
+            tracked: false,
+            flushed: None,
+        })
+        .is_err()
+    {
+        shared.unregister_iq(&iq_id);
+
+        return Err(IqError::CannotWrite);
+    }
+
+    // Wait for response (or time out)
+    match timeout(request_timeout, waiter).await {
+        Ok(Ok(response)) => Ok(response),
+        Ok(Err(_)) => Err(IqError::Cancelled),
+        Err(_) => {
+            shared.unregister_iq(&iq_id);
+
+            Err(IqError::Timeout)
+        }
+    }
+}
+
+async fn ping_connection(
+    sender: &UnboundedSender<OutgoingPacket>,
+    shared: &ConnectionShared,
+    domain: &str,
+    ping_timeout: Duration,
+) -> PingResult {
+    let ping = Element::builder("iq", NS_CLIENT)
+        .attr("type", "get")
+        .attr("id", make_iq_id())
+        .attr("to", domain)
+        .append(Element::builder("ping", NS_PING).build())
+        .build();
+
+    let ping_at = Instant::now();
+
+    // Notice: any response counts as a pong, even an error one (eg. if the \
+    //   server does not support pings, it still proves the stream is alive).
+    match request_iq(sender, shared, ping, ping_timeout).await {
+        Ok(_) => PingResult {
+            rtt: Some(ping_at.elapsed().as_millis() as u64),
+            ..Default::default()
+        },
+        Err(err) => PingResult {
+            error: Some(err),
+            ..Default::default()
+        },
+    }
+}
+
+fn send_initial_presence(
+    shared: &ConnectionShared,
+    sender: &UnboundedSender<OutgoingPacket>,
+    presence: Element,
+) {
+    let size = String::from(&presence).len();
+
+    let result = sender.send(OutgoingPacket {
+        packet: Packet::Stanza(presence),
+        size,
+        token: None,
+        tracked: false,
+        flushed: None,
+    });
+
+    if result.is_err() {
+        let id = &shared.id();
+
+        warn!(
+            target: &log_target(id),
+            "Could not send initial presence on: #{}, ignoring",
+            id
+        );
+    }
+}
+
+async fn enable_carbons(shared: &ConnectionShared, sender: &UnboundedSender<OutgoingPacket>) {
+    let carbons_enable = Element::builder("iq", NS_CLIENT)
+        .attr("type", "set")
+        .attr("id", make_iq_id())
+        .append(Element::builder("enable", NS_CARBONS).build())
+        .build();
+
+    let result = request_iq(
+        sender,
+        shared,
+        carbons_enable,
+        Duration::from_millis(CARBONS_TIMEOUT_MILLISECONDS),
+    )
+    .await;
+
+    let id = &shared.id();
+
+    match result {
+        Ok(response) if response.attr("type") == Some("result") => {
+            debug!(target: &log_target(id), "Enabled message carbons on: #{}", id);
+
+            shared.carbons_enabled.store(true, Ordering::SeqCst);
+        }
+        Ok(response) => {
+            warn!(
+                target: &log_target(id),
+                "Could not enable message carbons on: #{}, because: {}",
+                id,
+                make_stanza_error_condition(&response)
+            );
+        }
+        Err(err) => {
+            warn!(
+                target: &log_target(id),
+                "Could not enable message carbons on: #{}, because: {}",
+                id, err
+            );
+        }
+    }
+}
+
+fn has_disco_feature(response: &Element, feature: &str) -> bool {
+    let is_feature =
+        |child: &Element| child.is("feature", NS_DISCO_INFO) && child.attr("var") == Some(feature);
+
+    response.attr("type") == Some("result")
+        && response
+            .get_child("query", NS_DISCO_INFO)
+            .map(|query| query.children().any(is_feature))
+            .unwrap_or(false)
+}
+
+async fn emit_server_info<R: Runtime>(
+    window: &Window<R>,
+    shared: &ConnectionShared,
+    sender: &UnboundedSender<OutgoingPacket>,
+    domain: &str,
+) {
+    let version_get = Element::builder("iq", NS_CLIENT)
+        .attr("type", "get")
+        .attr("id", make_iq_id())
+        .attr("to", domain)
+        .append(Element::builder("query", NS_VERSION).build())
+        .build();
+
+    let result = request_iq(
+        sender,
+        shared,
+        version_get,
+        Duration::from_millis(SERVER_INFO_TIMEOUT_MILLISECONDS),
+    )
+    .await;
+
+    let id = &shared.id();
+
+    if let Err(ref err) = result {
+        warn!(
+            target: &log_target(id),
+            "Could not query server software on: #{}, because: {}",
+            id, err
+        );
+    }
+
+    // Acquire server software fields (if any)
+    // Notice: servers that do not support software version queries answer \
+    //   with an error, in which case all fields are left empty.
+    let query = result
+        .as_ref()
+        .ok()
+        .and_then(|response| response.get_child("query", NS_VERSION));
+
+    let field = |name: &str| {
+        query
+            .and_then(|query| query.get_child(name, NS_VERSION))
+            .map(|field| field.text())
+    };
+
+    emit_connection_event(
+        window,
+        id,
+        shared,
+        EVENT_SERVER_INFO,
+        EventConnectionServerInfo {
+            id,
+            name: field("name"),
+            version: field("version"),
+            os: field("os"),
+            error: result.as_ref().err(),
+        },
+    );
+}
+
+fn make_roster_items(response: &Element) -> Result<Vec<RosterItem>, RosterError> {
+    match response.attr("type") {
+        Some("result") => {
+            // Notice: a result with no query payload is valid, and gets \
+            //   treated as an empty roster.
+            let query = match response.get_child("query", NS_ROSTER) {
+                Some(query) => query,
+                None => return Ok(Vec::new()),
+            };
+
+            Ok(query
+                .children()
+                .filter(|item| item.is("item", NS_ROSTER))
+                .filter_map(|item| {
+                    Some(RosterItem {
+                        jid: item.attr("jid")?.to_string(),
+                        name: item.attr("name").map(|name| name.to_string()),
+                        subscription: item.attr("subscription").unwrap_or("none").to_string(),
+                        groups: item
+                            .children()
+                            .filter(|group| group.is("group", NS_ROSTER))
+                            .map(|group| group.text())
+                            .collect(),
+                    })
+                })
+                .collect())
+        }
+        Some("error") => Err(RosterError::ServerError(make_stanza_error_condition(response))),
+        _ => Err(RosterError::InvalidResponse),
+    }
+}
+
+fn make_stanza_error_condition(response: &Element) -> String {
+    // Acquire the stanza error condition (eg. 'service-unavailable')
+    response
+        .get_child("error", NS_CLIENT)
+        .and_then(|error| error.children().find(|child| child.ns() == NS_STANZAS))
+        .map(|condition| condition.name().to_string())
+        .unwrap_or_else(|| "undefined-condition".to_string())
+}
+
+fn make_mam_query(query: &MamQuery, query_id: &str) -> Element {
+    let make_field = |var: &str, field_type: Option<&str>, value: &str| {
+        let mut field = Element::builder("field", NS_DATA_FORMS).attr("var", var);
+
+        if let Some(field_type) = field_type {
+            field = field.attr("type", field_type);
+        }
+
+        field
+            .append(Element::builder("value", NS_DATA_FORMS).append(value).build())
+            .build()
+    };
+
+    // Build filter form (with the form type field first)
+    let mut form = Element::builder("x", NS_DATA_FORMS)
+        .attr("type", "submit")
+        .append(make_field("FORM_TYPE", Some("hidden"), NS_MAM));
+
+    for (var, value) in [("with", &query.with), ("start", &query.start), ("end", &query.end)] {
+        if let Some(value) = value {
+            form = form.append(make_field(var, None, value));
+        }
+    }
+
+    // Build result set management (ie. paging)
+    // Notice: an empty 'before' value is valid, and requests the last page.
+    let mut set = Element::builder("set", NS_RSM);
+
+    if let Some(limit) = query.limit {
+        set = set.append(Element::builder("max", NS_RSM).append(limit.to_string()).build());
+    }
+
+    for (name, value) in [("before", &query.before), ("after", &query.after)] {
+        if let Some(value) = value {
+            set = set.append(Element::builder(name, NS_RSM).append(value.as_str()).build());
+        }
+    }
+
+    let mut iq = Element::builder("iq", NS_CLIENT)
+        .attr("type", "set")
+        .attr("id", make_iq_id());
+
+    if let Some(ref to) = query.to {
+        iq = iq.attr("to", to.as_str());
+    }
+
+    iq.append(
+        Element::builder("query", NS_MAM)
+            .attr("queryid", query_id)
+            .append(form.build())
+            .append(set.build())
+            .build(),
+    )
+    .build()
+}
+
+fn make_mam_page(response: &Element, messages: Vec<MamMessage>) -> Result<MamPage, MamError> {
+    match response.attr("type") {
+        Some("result") => {
+            let fin = response.get_child("fin", NS_MAM).ok_or(MamError::InvalidResponse)?;
+
+            let set = fin.get_child("set", NS_RSM);
+            let set_text = |name: &str| {
+                set.and_then(|set| set.get_child(name, NS_RSM)).map(|child| child.text())
+            };
+
+            Ok(MamPage {
+                messages,
+                first: set_text("first"),
+                last: set_text("last"),
+                count: set_text("count").and_then(|count| count.parse().ok()),
+                complete: matches!(fin.attr("complete"), Some("true") | Some("1")),
+            })
+        }
+        Some("error") => Err(MamError::ServerError(make_stanza_error_condition(response))),
+        _ => Err(MamError::InvalidResponse),
+    }
+}
+
+fn make_server_probe(features: &Element) -> ServerProbe {
+    let child_texts = |name: &str, namespace: &str, child_name: &str| {
+        features
+            .get_child(name, namespace)
+            .map(|element| {
+                element
+                    .children()
+                    .filter(|child| child.is(child_name, namespace))
+                    .map(|child| child.text())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+
+    ServerProbe {
+        sasl_mechanisms: child_texts("mechanisms", NS_SASL, "mechanism"),
+        compression_methods: child_texts("compression", NS_FEATURE_COMPRESS, "method"),
+        stream_management: features.has_child("sm", NS_SM),
+        registration: features.has_child("register", NS_FEATURE_REGISTER),
+    }
+}
+
+fn spawn_poller<F>(tier: ConnectionTier, runtime: Option<&TokioHandle>, poller: F) -> JoinHandle<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    // Spawn on dedicated runtime? (if high-tier)
+    // Notice: if the dedicated runtime could not be built, then fall back \
+    //   to the application runtime, as the connection can still work there.
+    if tier == ConnectionTier::High {
+        if let Some(runtime) = HIGH_TIER_RUNTIME.as_ref() {
+            return runtime.spawn(poller);
+        }
+    }
+
+    // Spawn on the plugin runtime? (if configured)
+    if let Some(runtime) = runtime {
+        return runtime.spawn(poller);
+    }
+
+    task::spawn(poller)
+}
+
+fn make_dedicated_runtime(settings: &RuntimeSettings) -> Option<TokioHandle> {
+    let mut builder = TokioRuntimeBuilder::new_multi_thread();
+
+    if let Some(worker_threads) = settings.worker_threads {
+        builder.worker_threads(worker_threads.max(1));
+    }
+
+    let runtime = builder
+        .thread_name(
+            settings
+                .thread_name
+                .as_deref()
+                .unwrap_or(DEDICATED_THREAD_NAME_DEFAULT),
+        )
+        .enable_all()
+        .build()
+        .inspect_err(|err| error!("Failed to build dedicated connection runtime: {}", err))
+        .ok()?;
+
+    // Notice: the runtime is leaked on purpose, as it must outlive all the \
+    //   connections (ie. the application itself), and since dropping a \
+    //   runtime from an asynchronous context panics.
+    Some(Box::leak(Box::new(runtime)).handle().clone())
+}
+
+fn kill_event_handlers(connection: &ConnectionClient) {
+    connection.write_handle.abort();
+
+    kill_read_handlers(connection);
+}
+
+fn kill_read_handlers(connection: &ConnectionClient) {
+    connection.read_handle.abort();
+
+    if let Some(ref bandwidth_handle) = connection.bandwidth_handle {
+        bandwidth_handle.abort();
+    }
+
+    if let Some(ref receive_handle) = connection.receive_handle {
+        receive_handle.abort();
+    }
+
+    if let Some(ref ping_handle) = connection.ping_handle {
+        ping_handle.abort();
+    }
+}
+
+fn take_over_connection<R: Runtime>(window: &Window<R>, state: &ConnectionClientState, id: &str) {
+    let Some((_, connection)) = state.connections.remove(id) else {
+        return;
+    };
+
+    // Stop reading (this also cancels any pending reconnection)
+    connection.shared.cancel_reconnect();
+
+    kill_read_handlers(&connection);
+
+    // Tell the implementor that the existing connection is gone
+    emit_connection_abort(window, id, &connection.shared, ConnectionState::Disconnected);
+
+    // Notice: in-flight emissions are not waited for there, since the new \
+    //   connection uses another identifier (thus they cannot be mixed up).
+    connection.shared.retire();
+    connection.shared.cancel_iqs();
+
+    forget_log_overrides(id);
+
+    // Request a clean disconnection of the existing stream
+    // Notice: this is done in the background since the new connection does \
+    //   not depend on it, and the write poller gets aborted if it does not \
+    //   flush in due time (eg. the existing stream is dead).
+    task::spawn(drain_connections(
+        vec![(id.to_owned(), connection)],
+        state.settings.shutdown_drain_timeout(),
+    ));
+}
+
+fn connection_handles(
+    state: &ConnectionClientState,
+    id: &str,
+) -> Option<(UnboundedSender<OutgoingPacket>, Arc<ConnectionShared>)> {
+    // Notice: handles get cloned, so that the state lock is released right \
+    //   away, as it cannot be held across await points (eg. when requesting).
+    state
+        .connections
+        .get(id)
+        .map(|connection| (connection.sender.clone(), connection.shared.clone()))
+}
+
+fn connection_targets(
+    state: &ConnectionClientState,
+    filter: impl Fn(&ConnectionShared) -> bool,
+) -> Vec<(String, String, UnboundedSender<OutgoingPacket>, Arc<ConnectionShared>)> {
+    let ids = state
+        .connections
+        .iter()
+        .map(|connection| connection.key().to_owned())
+        .collect::<Vec<_>>();
+
+    ids.into_iter()
+        .filter_map(|id| {
+            let (sender, shared) = connection_handles(state, &id)?;
+            let domain = shared.jid()?.domain().to_string();
+
+            filter(&shared).then_some((id, domain, sender, shared))
+        })
+        .collect()
+}
+
+fn take_connections(state: &ConnectionClientState) -> Vec<(String, ConnectionClient)> {
+    let ids = state
+        .connections
+        .iter()
+        .map(|connection| connection.key().to_owned())
+        .collect::<Vec<_>>();
+
+    ids.into_iter()
+        .filter_map(|id| {
+            forget_log_overrides(&id);
+
+            state.connections.remove(&id)
+        })
+        .collect()
+}
+
+async fn drain_connections(
+    connections: Vec<(String, ConnectionClient)>,
+    drain_timeout: Duration,
+) {
+    let mut write_handles = Vec::with_capacity(connections.len());
+
+    for (id, connection) in connections {
+        // Stop reading (this also cancels any pending reconnection)
+        connection.shared.cancel_reconnect();
+
+        kill_read_handlers(&connection);
+
+        // Emit end-of-stream packet (requesting a clean disconnection)
+        // Notice: the sender gets dropped right after, so that the write \
+        //   poller stops by itself once all pending packets are flushed.
+        if connection
+            .sender
+            .send(OutgoingPacket {
+                packet: Packet::StreamEnd,
+                size: 0,
+                token: None,
+                tracked: false,
+                flushed: None,
+            })
+            .is_err()
+        {
+            warn!(
+                target: &log_target(&id),
+                "Connection #{} could not be drained, as it is closed",
+                id
+            );
+        }
+
+        drop(connection.sender);
+
+        write_handles.push(connection.write_handle);
+    }
+
+    // Wait for all write pollers to flush (up to the drain deadline)
+    // Notice: this bounds the worst-case shutdown latency, as a dead \
+    //   connection might otherwise never flush, thus stalling shutdown.
+    let abort_handles = write_handles
+        .iter()
+        .map(|write_handle| write_handle.abort_handle())
+        .collect::<Vec<_>>();
+
+    if timeout(drain_timeout, future::join_all(write_handles)).await.is_err() {
+        warn!(
+            "Connections did not drain in due time (after {}ms), aborting",
+            drain_timeout.as_millis()
+        );
+
+        for abort_handle in abort_handles {
+            abort_handle.abort();
+        }
+    }
+}
+
+fn parse_stanza(id: &str, shared: &ConnectionShared, stanza: &str) -> Result<Element, SendError> {
+    let stanza_root: Element = stanza.parse().or(Err(SendError::CannotParse))?;
+
+    // Validate stanza structure? (opt-in)
+    // Notice: this is opt-in, since valid but unusual stanzas might be \
+    //   rejected there.
+    if shared.options.validate_stanzas {
+        check_stanza_structure(&stanza_root).map_err(|detail| {
+            warn!(
+                target: &log_target(id),
+                "Connection #{} send request rejected, as stanza is invalid: {}",
+                id, detail
+            );
+
+            SendError::InvalidStanza(detail)
+        })?;
+    }
+
+    Ok(stanza_root)
+}
+
+fn track_stanza_id<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    shared: &ConnectionShared,
+    stanza: &Element,
+) {
+    // Check for duplicate stanza identifiers? (opt-in)
+    // Notice: this is a developer aid, meaning that the stanza still gets \
+    //   sent even if a collision is detected.
+    if shared.options.detect_duplicate_ids {
+        if let Some(stanza_id) = stanza.attr("id") {
+            if shared.track_sent_id(stanza_id) {
+                warn!(
+                    target: &log_target(id),
+                    "Connection #{} sending stanza with duplicate identifier: {}",
+                    id, stanza_id
+                );
+
+                emit_connection_event(
+                    window,
+                    id,
+                    shared,
+                    EVENT_DUPLICATE_ID,
+                    EventConnectionDuplicateId { id, stanza_id },
+                );
+            }
+        }
+    }
+}
+
+fn prepare_stanza<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    shared: &ConnectionShared,
+    stanza: &str,
+) -> Result<Element, SendError> {
+    let stanza_root = parse_stanza(id, shared, stanza)?;
+
+    track_stanza_id(window, id, shared, &stanza_root);
+
+    Ok(stanza_root)
+}
+
+fn queue_stanza<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    connection: &ConnectionClient,
+    stanza: Element,
+    size: usize,
+    token: Option<String>,
+    flushed: Option<FlushSender>,
+) -> Result<(), SendError> {
+    // Notice: the queueing lock is held while pushing, so that stanzas \
+    //   pushed from a batch cannot get interleaved with other stanzas.
+    let _queueing = connection.shared.queueing.lock().unwrap();
+
+    push_stanza(window, id, connection, stanza, size, token, flushed)
+}
+
+fn push_stanza<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    connection: &ConnectionClient,
+    stanza: Element,
+    size: usize,
+    token: Option<String>,
+    flushed: Option<FlushSender>,
+) -> Result<(), SendError> {
+    let result = enqueue_stanza(id, connection, stanza, size, token, flushed);
+
+    // Recover from closed sender channel state (implicitly disconnect)
+    if let Err(SendError::CannotWrite) = result {
+        recover_closed_sender_channel(window, id, connection);
+    }
+
+    result
+}
+
+fn enqueue_stanza(
+    id: &str,
+    connection: &ConnectionClient,
+    stanza: Element,
+    size: usize,
+    token: Option<String>,
+    flushed: Option<FlushSender>,
+) -> Result<(), SendError> {
+    // Connection is draining? (refuse new stanzas)
+    if connection.shared.draining.load(Ordering::SeqCst) {
+        warn!(
+            target: &log_target(id),
+            "Connection #{} send request refused, as connection is draining",
+            id
+        );
+
+        return Err(SendError::Draining);
+    }
+
+    // Send queue is full? (refuse new stanzas, if the queue is bounded)
+    // Notice: the queue only fills up if the write poller cannot keep up \
+    //   (eg. upon a network stall), thus the implementor is expected to \
+    //   throttle and retry later, instead of having the queue grow forever. \
+    //   Stanzas count towards the queue until they are written to the \
+    //   stream (including while buffered for a reconnection).
+    let queue_depth = &connection.shared.queue_depth;
+
+    if let Some(send_queue_size) = connection.shared.options.send_queue_size {
+        if queue_depth.load(Ordering::SeqCst) >= send_queue_size {
+            warn!(
+                target: &log_target(id),
+                "Connection #{} send request refused, as send queue is full (size: {})",
+                id, send_queue_size
+            );
+
+            return Err(SendError::QueueFull);
+        }
+    }
+
+    // Account for queued packet (before it gets queued, as the write \
+    //   poller might send it right away)
+    let counters = &connection.shared.counters;
+
+    counters.packets_queued.fetch_add(1, Ordering::Relaxed);
+    queue_depth.fetch_add(1, Ordering::SeqCst);
+
+    match connection.sender.send(OutgoingPacket {
+        packet: Packet::Stanza(stanza),
+        size,
+        token,
+        tracked: true,
+        flushed,
+    }) {
+        Ok(_) => {
+            debug!(
+                target: &log_target(id),
+                "Connection #{} send request complete (XMPP stanza was sent)",
+                id
+            );
+
+            Ok(())
+        }
+        Err(err) => {
+            error!(
+                target: &log_target(id),
+                "Connection #{} send request failed, because: {}",
+                id, err
+            );
+
+            counters.packets_queued.fetch_sub(1, Ordering::Relaxed);
+            queue_depth.fetch_sub(1, Ordering::SeqCst);
+
+            Err(SendError::CannotWrite)
+        }
+    }
+}
+
+fn recover_closed_sender_channel<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    connection: &ConnectionClient,
+) {
+    // Recover from dangling state: emit an implicit disconnected event
+    // Notice: this will prompt the implementor to destroy the client.
+    info!(
+        target: &log_target(id),
+        "Recovering: raising an implicit disconnected event for connection #{}",
+        id
+    );
+
+    // Abort both task handles (so that no other IPC gets sent)
+    connection.shared.cancel_reconnect();
+
+    kill_event_handlers(connection);
+
+    // Emit connection error event
+    emit_connection_abort(window, id, &connection.shared, ConnectionState::ConnectionError);
+
+    schedule_auto_destroy(window, &connection.shared);
+}
+
+fn schedule_auto_destroy<R: Runtime>(window: &Window<R>, shared: &Arc<ConnectionShared>) {
+    // Auto-destroy is opt-in, as by default the implementor is expected to \
+    //   explicitly destroy the connection upon receiving a terminal state.
+    if shared.options.auto_destroy_after_disconnect == false {
+        return;
+    }
+
+    let window = window.clone();
+    let shared = shared.clone();
+
+    // Notice: wait for a grace delay before destroying the connection, so \
+    //   that the implementor has a chance to read its final status first.
+    task::spawn(async move {
+        time::sleep(Duration::from_millis(AUTO_DESTROY_GRACE_MILLISECONDS)).await;
+
+        // Acquire identifier (after the grace delay, as it might have been \
+        //   renamed in the meantime)
+        let id = shared.id();
+
+        // Important: only remove the connection if it still is the one that \
+        //   got disconnected, as the identifier might have been re-used for a \
+        //   new connection in the meantime.
+        let state = window.state::<ConnectionClientState>();
+
+        if let Some((_, connection)) = state
+            .connections
+            .remove_if(&id, |_, connection| Arc::ptr_eq(&connection.shared, &shared))
+        {
+            kill_event_handlers(&connection);
+
+            connection.shared.retire();
+            connection.shared.settle_emissions().await;
+
+            forget_log_overrides(&id);
+
+            info!(target: &log_target(&id), "Connection #{} was automatically destroyed", id);
+        }
+    });
+}
+
+fn rebind_connection_jid<R: Runtime>(
+    window: &Window<R>,
+    shared: &Arc<ConnectionShared>,
+    jid: BareJid,
+) {
+    // Bound on the requested JID? (nothing to update)
+    if shared.jid().as_ref() == Some(&jid) {
+        return;
+    }
+
+    // Notice: the bound JID might differ from the requested one (eg. the \
+    //   server assigns it for anonymous connections), thus the connection \
+    //   gets updated with the bound JID, so that JID conflict checks and \
+    //   connection listings use the actual JID.
+    *shared.jid.write().unwrap() = Some(jid.clone());
+
+    let state = window.state::<ConnectionClientState>();
+
+    if let Some(mut connection) = state.connections.get_mut(&shared.id()) {
+        if Arc::ptr_eq(&connection.shared, shared) {
+            connection.jid = jid;
+        }
+    }
+}
+
+fn destroy_orphaned_connection<R: Runtime>(window: &Window<R>, shared: &Arc<ConnectionShared>) {
+    let window = window.clone();
+    let shared = shared.clone();
+
+    // Notice: the window is gone, meaning that nobody is left to destroy \
+    //   the connection (as its states cannot be emitted anymore). This is \
+    //   done from another task, as the caller is one of the aborted tasks.
+    task::spawn(async move {
+        let id = shared.id();
+
+        // Important: only remove the connection if it still is the one that \
+        //   lost its window, as the identifier might have been re-used.
+        let state = window.state::<ConnectionClientState>();
+
+        if let Some((_, connection)) = state
+            .connections
+            .remove_if(&id, |_, connection| Arc::ptr_eq(&connection.shared, &shared))
+        {
+            kill_event_handlers(&connection);
+
+            connection.shared.retire();
+
+            forget_log_overrides(&id);
+
+            info!(
+                target: &log_target(&id),
+                "Connection #{} was destroyed, as its window is gone",
+                id
+            );
+        }
+    });
+}
+
+async fn poll_input_events<R: Runtime, C: ServerConnector>(
+    window: &Window<R>,
+    shared: &Arc<ConnectionShared>,
+    sender: &WeakUnboundedSender<OutgoingPacket>,
+    read_timeout: Duration,
+    mut client_reader: SplitStream<Client<C>>,
+) -> Result<(), PollInputError> {
+    // Wrap client reader in a timeout task; this is especially important \
+    //   since the underlying 'tokio-xmpp' does not implement any kind of \
+    //   timeout whatsoever. This timeout duration is served from the \
+    //   connection initiator, and will most likely depend on the PING \
+    //   interval set by the client.
+    shared.mark_received();
+
+    let mut deadline = time::Instant::now() + read_timeout;
+
+    // Emit heartbeats? (opt-in)
+    // Notice: heartbeats get emitted from the read poller itself, meaning \
+    //   that a stuck read poller can be told apart from a quiet connection.
+    let mut heartbeat = shared.options.heartbeat_interval.map(|interval| {
+        let interval = Duration::from_millis(interval);
+
+        time::interval_at(time::Instant::now() + interval, interval)
+    });
+
+    loop {
+        // Notice: the stream can also be considered as stale from the outside \
+        //   (eg. if it did not respond to a ping upon resuming), which then \
+        //   gets handled the same way as a timeout.
+        let event_maybe = tokio::select! {
+            event_maybe = time::timeout_at(deadline, client_reader.next()) => event_maybe,
+            _ = shared.stale.notified() => break,
+            _ = shared.report.secured() => {
+                emit_connection_secured(window, shared);
+
+                continue;
+            }
+            _ = tick_heartbeat(&mut heartbeat) => {
+                emit_heartbeat(window, shared);
+
+                continue;
+            }
+        };
+
+        let Ok(event_maybe) = event_maybe else {
+            break;
+        };
+
+        // Notice: any event resets the read timeout, thus remember when the \
+        //   last one was received (used to tell our time to timeout).
+        shared.mark_received();
+
+        deadline = time::Instant::now() + read_timeout;
+
+        // Handle next event
+        // Notice: the identifier is acquired for each event, as the \
+        //   connection might have been renamed in the meantime.
+        let id = &shared.id();
+
+        if let Some(result) = handle_next_input_event(window, id, shared, sender, event_maybe) {
+            // We received a non-empty result: we have to stop the loop there!
+            return result;
+        }
+
+        // Window is gone? (stop there, as events cannot be emitted anymore)
+        if shared.window_gone.load(Ordering::SeqCst) {
+            return Err(PollInputError::WindowGone);
+        }
+    }
+
+    let id = &shared.id();
+    let target = &log_target(id);
+
+    // Synthetic disconnect injected? (abort with the injected state)
+    // Notice: this lets test builds go through the same abort path as a \
+    //   real disconnection, including reconnection if enabled.
+    #[cfg(feature = "test-commands")]
+    if let Some(injected_state) = shared.injected_state.lock().unwrap().take() {
+        warn!(target: target, "Injecting synthetic disconnect on: #{}", id);
+
+        emit_connection_abort(window, id, shared, injected_state);
+
+        return Err(PollInputError::OtherError);
+    }
+
+    // The next event did not come in due time, consider as timed out
+    warn!(
+        target: target,
+        "Timed out waiting {}ms for next event on: #{}",
+        read_timeout.as_millis(),
+        id
+    );
+
+    // Abort here (timed out)
+    // Notice: the event loop has timed out, abort connection and error out.
+    *shared.error_kind.lock().unwrap() = Some(ConnectionErrorKind::ReadTimeout);
+
+    emit_connection_abort(window, id, shared, ConnectionState::ConnectionTimeout);
+
+    Err(PollInputError::TimeoutError)
+}
+
+fn emit_connection_secured<R: Runtime>(window: &Window<R>, shared: &ConnectionShared) {
+    // Notice: the handshake completes while the client reader gets polled, \
+    //   meaning that the bound event might get handled first. Never go back \
+    //   from connected to secured, in such a case.
+    if !shared.current_state().is_connected() {
+        let id = &shared.id();
+
+        emit_connection_state(window, id, shared, ConnectionState::Secured);
+    }
+}
+
+async fn tick_heartbeat(heartbeat: &mut Option<time::Interval>) {
+    match heartbeat {
+        Some(heartbeat) => {
+            heartbeat.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+fn emit_heartbeat<R: Runtime>(window: &Window<R>, shared: &ConnectionShared) {
+    // Only emit heartbeats while connected (no liveness to tell otherwise)
+    if shared.current_state().is_connected() {
+        let id = &shared.id();
+
+        emit_connection_event(window, id, shared, EVENT_HEARTBEAT, EventConnectionHeartbeat { id });
+    }
+}
+
+async fn send_outgoing_packet<R: Runtime, C: ServerConnector>(
+    window: &Window<R>,
+    id: &str,
+    shared: &ConnectionShared,
+    client_writer: &mut SplitSink<Client<C>, Packet>,
+    outgoing: OutgoingPacket,
+) -> Result<bool, Option<OutgoingPacket>> {
+    let target = &log_target(id);
+
+    // Keep a copy of the packet around? (if it might need to be replayed)
+    let outgoing_retry = if shared.options.reconnect {
+        clone_outgoing_packet(&outgoing)
+    } else {
+        None
+    };
+
+    // Flush the packet right away? (if someone needs to know that it went \
+    //   on the wire, otherwise it gets flushed once the queue is empty)
+    // Notice: feeding the sink only puts the packet in the stream write \
+    //   buffer, which reaches the socket upon flushing. Sending always \
+    //   flushes, which costs a socket write per packet for bursts.
+    let flush = outgoing.flushed.is_some() || outgoing.token.is_some();
+
+    // Acquire stream management role of packet (if any)
+    // Notice: stanzas are tracked once sent only, as a failed send gets \
+    //   retried with the very same packet.
+    let (sm_enable, sm_tracked) = match outgoing.packet {
+        Packet::Stanza(ref stanza) if stanza.is("enable", NS_SM) => (true, None),
+        Packet::Stanza(ref stanza) if shared.tracks_sm_outbound(stanza) => {
+            (false, Some(stanza.clone()))
+        }
+        _ => (false, None),
+    };
+
+    let result = if flush {
+        client_writer.send(outgoing.packet).await
+    } else {
+        client_writer.feed(outgoing.packet).await
+    };
+
+    if let Err(err) = result {
+        error!(
+            target: target,
+            "Failed sending packet over connection: #{} because: {}",
+            id, err
+        );
+
+        return Err(outgoing_retry);
+    }
+
+    debug!(target: target, "Sent packet over connection: #{}", id);
+
+    if sm_enable {
+        shared.start_sm_outbound();
+    } else if let Some(stanza) = sm_tracked {
+        shared.track_sm_outbound(stanza);
+    }
+
+    shared
+        .counters
+        .bytes_sent
+        .fetch_add(outgoing.size as u64, Ordering::Relaxed);
+
+    // Account for sent packet (if queued by the implementor)
+    // Notice: the send queue depth only decreases there, so that packets \
+    //   buffered while reconnecting still count towards the queue size.
+    if outgoing.tracked {
+        shared.counters.packets_sent.fetch_add(1, Ordering::Relaxed);
+        shared.queue_depth.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    // Tell that the packet was flushed? (if someone is waiting for it)
+    // Notice: the sender is shared between copies of the packet (eg. when \
+    //   it gets retried), thus only the first successful write tells.
+    if let Some(ref flushed) = outgoing.flushed {
+        if let Some(flushed) = flushed.lock().unwrap().take() {
+            flushed.send(()).ok();
+        }
+    }
+
+    // Confirm that the packet left the writer? (if correlated)
+    // Notice: this lets the implementor distinguish between a packet that \
+    //   was merely queued, and a packet that effectively went on the wire.
+    if let Some(ref token) = outgoing.token {
+        emit_connection_event(window, id, shared, EVENT_SENT, EventConnectionSent { id, token });
+    }
+
+    Ok(flush)
+}
+
+async fn flush_client_writer<C: ServerConnector>(
+    id: &str,
+    client_writer: &mut SplitSink<Client<C>, Packet>,
+) {
+    if let Err(err) = client_writer.flush().await {
+        error!(
+            target: &log_target(id),
+            "Failed flushing packets over connection: #{} because: {}",
+            id, err
+        );
+    } else {
+        debug!(target: &log_target(id), "Flushed packets over connection: #{}", id);
+    }
+}
+
+async fn poll_output_events<R: Runtime, C: ServerConnector>(
+    window: &Window<R>,
+    shared: &ConnectionShared,
+    mut client_writers: UnboundedReceiver<SplitSink<Client<C>, Packet>>,
+    mut rx: UnboundedReceiver<OutgoingPacket>,
+) -> Result<(), PollOutputError> {
+    // Acquire initial client writer
+    let mut client_writer = match client_writers.recv().await {
+        Some(client_writer) => client_writer,
+        None => return Ok(()),
+    };
+
+    let mut client_writers_open = true;
+
+    // Notice: packets sent while reconnecting get buffered there, and then \
+    //   replayed in order once connected again (up to a maximum amount of \
+    //   packets, dropping the oldest ones first).
+    let mut pending: VecDeque<OutgoingPacket> = VecDeque::new();
+
+    // Notice: the last sent stanzas get kept there, so that they can be sent \
+    //   again once reconnected (opt-in). This is a best-effort redelivery \
+    //   that does not rely on stream management, meaning that recipients \
+    //   might get duplicates. Also, this only applies to automatic \
+    //   reconnections, as any other connection starts with an empty buffer.
+    let mut replay: VecDeque<OutgoingPacket> = VecDeque::new();
+    let mut replay_due = false;
+
+    // Notice: packets are fed to the client writer without flushing while \
+    //   more packets are queued, and then flushed at once when the queue is \
+    //   momentarily empty. This balances latency and throughput.
+    let mut unflushed = false;
+
+    loop {
+        // Notice: the identifier is acquired for each iteration, as the \
+        //   connection might have been renamed in the meantime.
+        let id = &shared.id();
+        let target = &log_target(id);
+
+        // Window is gone? (stop there, as events cannot be emitted anymore)
+        if shared.window_gone.load(Ordering::SeqCst) {
+            return Err(PollOutputError::WindowGone);
+        }
+
+        // Replay sent packets? (once connected again, before pending ones)
+        if replay_due && shared.current_state().is_connected() {
+            debug!(
+                target: target,
+                "Replaying {} sent packets over connection: #{}",
+                replay.len(),
+                id
+            );
+
+            // Notice: the replay buffer gets emptied there, so that stanzas \
+            //   only get replayed once, in the order they were first sent.
+            let mut replayed = replay.drain(..).collect::<VecDeque<_>>();
+
+            replayed.append(&mut pending);
+
+            pending = replayed;
+            replay_due = false;
+        }
+
+        // Replay pending packets? (once connected again)
+        if !pending.is_empty() && shared.current_state().is_connected() {
+            debug!(
+                target: target,
+                "Replaying {} pending packets over connection: #{}",
+                pending.len(),
+                id
+            );
+
+            while let Some(outgoing) = pending.pop_front() {
+                let replay_packet = make_replay_packet(shared, &outgoing);
+
+                match send_outgoing_packet(window, id, shared, &mut client_writer, outgoing).await {
+                    Ok(flushed) => {
+                        unflushed = !flushed;
+
+                        if let Some(replay_packet) = replay_packet {
+                            buffer_replay_packet(shared, &mut replay, replay_packet);
+                        }
+                    }
+                    Err(outgoing_retry) => {
+                        if let Some(outgoing_retry) = outgoing_retry {
+                            pending.push_front(outgoing_retry);
+                        }
+
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Request acknowledgement of sent stanzas? (if the queue is \
+        //   momentarily empty, and stream management is enabled)
+        if rx.is_empty() && shared.wants_sm_ack() {
+            let ack_request = Element::builder("r", NS_SM).build();
+
+            if let Err(err) = client_writer.feed(Packet::Stanza(ack_request)).await {
+                warn!(
+                    target: target,
+                    "Failed requesting acknowledgement over connection: #{} because: {}",
+                    id, err
+                );
+            } else {
+                unflushed = true;
+            }
+        }
+
+        // Flush client writer? (if the queue is momentarily empty)
+        if unflushed && rx.is_empty() {
+            flush_client_writer(id, &mut client_writer).await;
+
+            unflushed = false;
+        }
+
+        tokio::select! {
+            client_writer_maybe = client_writers.recv(), if client_writers_open => {
+                // Swap client writer (a new stream was established, eg. upon \
+                //   reconnecting)
+                // Notice: the client writers channel closes when the read \
+                //   poller stops, but we still need to flush pending packets \
+                //   (eg. the end-of-stream packet sent on disconnect).
+                if let Some(next_client_writer) = client_writer_maybe {
+                    debug!(target: target, "Swapped client writer on connection: #{}", id);
+
+                    client_writer = next_client_writer;
+
+                    replay_due = !replay.is_empty();
+                } else {
+                    client_writers_open = false;
+                }
+            }
+            _ = shared.connected.notified(), if !pending.is_empty() || replay_due => {
+                // Connected again (pending packets get replayed next)
+            }
+            outgoing_maybe = rx.recv() => {
+                let Some(outgoing) = outgoing_maybe else {
+                    // Flush last packets? (eg. the end-of-stream packet)
+                    if unflushed {
+                        flush_client_writer(id, &mut client_writer).await;
+                    }
+
+                    return Ok(());
+                };
+
+                // Reconnecting? (or still having packets to replay first)
+                let state = shared.current_state();
+
+                if shared.options.reconnect
+                    && (state == ConnectionState::Reconnecting
+                        || state == ConnectionState::Secured
+                        || (state.is_connected() && !pending.is_empty()))
+                {
+                    buffer_pending_packet(target, id, shared, &mut pending, outgoing);
+
+                    continue;
+                }
+
+                let replay_packet = make_replay_packet(shared, &outgoing);
+
+                match send_outgoing_packet(window, id, shared, &mut client_writer, outgoing).await {
+                    Ok(flushed) => {
+                        unflushed = !flushed;
+
+                        if let Some(replay_packet) = replay_packet {
+                            buffer_replay_packet(shared, &mut replay, replay_packet);
+                        }
+                    }
+                    Err(outgoing_retry) => {
+                        // Notice: if reconnection is enabled, then keep the \
+                        //   write poller going, as a new client writer will \
+                        //   be provided.
+                        if shared.options.reconnect == false {
+                            return Err(PollOutputError::PacketSendError);
+                        }
+
+                        if let Some(outgoing_retry) = outgoing_retry {
+                            buffer_pending_packet(target, id, shared, &mut pending, outgoing_retry);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn poll_bandwidth_events<R: Runtime>(
+    window: &Window<R>,
+    shared: &ConnectionShared,
+    interval: Duration,
+) {
+    let mut ticker = time::interval(interval);
+
+    let (mut last_sent, mut last_received) = (0, 0);
+
+    // Skip first tick (as it completes immediately)
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        let id = &shared.id();
+        let sent = shared.counters.bytes_sent.load(Ordering::Relaxed);
+        let received = shared.counters.bytes_received.load(Ordering::Relaxed);
+
+        // Emit bandwidth used since last tick
+        emit_connection_event(
+            window,
+            id,
+            shared,
+            EVENT_BANDWIDTH,
+            EventConnectionBandwidth {
+                id,
+                sent: sent - last_sent,
+                received: received - last_received,
+            },
+        );
+
+        (last_sent, last_received) = (sent, received);
+    }
+}
+
+async fn poll_ping_events(
+    shared: &ConnectionShared,
+    sender: &WeakUnboundedSender<OutgoingPacket>,
+    domain: &str,
+    interval: Duration,
+) {
+    let mut ticker = time::interval(interval);
+    let mut misses = 0;
+
+    let misses_maximum = shared.options.ping_misses.unwrap_or(PING_MISSES_MAXIMUM);
+
+    // Notice: the ping timeout cannot exceed the interval, so that a ping \
+    //   is always answered (or missed) before the next one is sent.
+    let ping_timeout = interval.min(Duration::from_millis(PING_TIMEOUT_MILLISECONDS));
+
+    // Skip first tick (as it completes immediately)
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        // Only ping while connected (nothing to keep alive otherwise)
+        if !shared.current_state().is_connected() {
+            misses = 0;
+
+            continue;
+        }
+
+        let Some(sender) = sender.upgrade() else {
+            break;
+        };
+
+        let result = ping_connection(&sender, shared, domain, ping_timeout).await;
+
+        let id = &shared.id();
+
+        // Notice: only timeouts count as misses, as any other error means \
+        //   that a response was received, or that the connection is gone.
+        if let Some(IqError::Timeout) = result.error {
+            misses += 1;
+
+            warn!(
+                target: &log_target(id),
+                "Ping unanswered on: #{} ({}/{} misses)",
+                id, misses, misses_maximum
+            );
+        } else {
+            misses = 0;
+        }
+
+        // Too many consecutive pings unanswered? (consider as stale)
+        // Notice: this makes the read poller time out right away, which \
+        //   then gets handled as a regular read timeout (ie. reconnecting).
+        if misses >= misses_maximum {
+            warn!(
+                target: &log_target(id),
+                "Connection is stale after {} unanswered pings on: #{}",
+                misses, id
+            );
+
+            // Notice: a permit is stored if the read poller is busy handling \
+            //   an event, so that the wake-up cannot get lost.
+            shared.stale.notify_one();
+
+            misses = 0;
+        }
+    }
+}
+
+async fn poll_receive_events<R: Runtime>(window: &Window<R>, shared: &ConnectionShared) {
+    loop {
+        shared.receive_queued.notified().await;
+
+        flush_received_stanzas(window, shared);
+    }
+}
+
+fn flush_received_stanzas<R: Runtime>(window: &Window<R>, shared: &ConnectionShared) {
+    // Emit all queued stanzas (in order)
+    // Important: this holds the flushing lock, as stanzas might be flushed \
+    //   from elsewhere concurrently (eg. before emitting a terminal state), \
+    //   which could otherwise re-order them.
+    let _flushing = shared.receive_flushing.lock().unwrap();
+
+    while let Some(queued) = shared.next_received() {
+        emit_queued_stanza(window, shared, queued);
+    }
+}
+
+fn make_receive_event(shared: &ConnectionShared, stanza: &Element) -> &'static str {
+    // Emit on a stanza type channel? (opt-in)
+    // Notice: this lets the implementor subscribe to the stanza types it \
+    //   handles only, instead of having to inspect each received stanza. \
+    //   Anything that is not a regular stanza still goes to the unified \
+    //   channel.
+    if shared.options.split_receive_events && stanza.ns() == NS_CLIENT {
+        match stanza.name() {
+            "message" => return EVENT_RECEIVE_MESSAGE,
+            "presence" => return EVENT_RECEIVE_PRESENCE,
+            "iq" => return EVENT_RECEIVE_IQ,
+            _ => {}
+        }
+    }
+
+    EVENT_RECEIVE
+}
+
+fn emit_queued_stanza<R: Runtime>(
+    window: &Window<R>,
+    shared: &ConnectionShared,
+    queued: QueuedStanza,
+) {
+    let id = &shared.id();
+
+    emit_received_stanza(
+        window,
+        id,
+        shared,
+        queued.event,
+        EventConnectionReceive {
+            id,
+            stanza: Cow::Owned(queued.stanza),
+            encoding: queued.encoding,
+            sequence: queued.sequence,
+        },
+    );
+}
+
+fn emit_received_stanza<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    shared: &ConnectionShared,
+    event: &str,
+    receive: EventConnectionReceive,
+) {
+    // Stream stanza in chunks? (opt-in, only if large enough)
+    // Notice: this avoids copying a huge stanza into a single IPC message, \
+    //   which would double peak memory for pathological stanza sizes. The \
+    //   stanza is still fully parsed before being streamed, since parsing \
+    //   happens in the underlying 'tokio-xmpp' stream.
+    if let Some(chunked) = shared.options.chunked_receive.as_ref() {
+        let threshold = chunked.threshold.unwrap_or(CHUNKED_RECEIVE_THRESHOLD_DEFAULT);
+
+        if receive.stanza.len() >= threshold {
+            let chunk_size = chunked
+                .chunk_size
+                .unwrap_or(CHUNKED_RECEIVE_CHUNK_SIZE_DEFAULT);
+
+            emit_stanza_chunks(window, id, shared, event, receive, chunk_size);
+
+            return;
+        }
+    }
+
+    emit_connection_event(window, id, shared, event, receive);
+}
+
+fn make_receive_chunk_event(event: &str) -> (&'static str, Option<&'static str>) {
+    // Notice: chunks follow the channel the stanza would have been emitted \
+    //   on, so that stanza type channels also get large stanzas.
+    match event {
+        EVENT_RECEIVE_MESSAGE => (EVENT_RECEIVE_MESSAGE_CHUNK, Some("message")),
+        EVENT_RECEIVE_PRESENCE => (EVENT_RECEIVE_PRESENCE_CHUNK, Some("presence")),
+        EVENT_RECEIVE_IQ => (EVENT_RECEIVE_IQ_CHUNK, Some("iq")),
+        _ => (EVENT_RECEIVE_CHUNK, None),
+    }
+}
+
+fn emit_stanza_chunks<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    shared: &ConnectionShared,
+    event: &str,
+    receive: EventConnectionReceive,
+    chunk_size: usize,
+) {
+    // Acquire stream identifier (correlates all chunks of this stanza)
+    let stream = shared.receive_streams.fetch_add(1, Ordering::Relaxed);
+
+    let (event, kind) = make_receive_chunk_event(event);
+
+    let make_chunk = |marker, chunk| EventConnectionReceiveChunk {
+        id,
+        stream,
+        marker,
+        kind: None,
+        chunk,
+        size: None,
+        encoding: None,
+        sequence: None,
+    };
+
+    debug!(
+        target: &log_target(id),
+        "Streaming received stanza in chunks on: #{}, stream: {}, size: {}",
+        id,
+        stream,
+        receive.stanza.len()
+    );
+
+    // Emit start marker (holds all stanza metadata, including its kind)
+    emit_connection_event(
+        window,
+        id,
+        shared,
+        event,
+        EventConnectionReceiveChunk {
+            kind,
+            size: Some(receive.stanza.len()),
+            encoding: receive.encoding,
+            sequence: receive.sequence,
+            ..make_chunk(ReceiveChunkMarker::Start, None)
+        },
+    );
+
+    // Emit all chunks in order, then end marker
+    for chunk in make_stanza_chunks(&receive.stanza, chunk_size) {
+        emit_connection_event(
+            window,
+            id,
+            shared,
+            event,
+            make_chunk(ReceiveChunkMarker::Data, Some(chunk)),
+        );
+    }
+
+    emit_connection_event(
+        window,
+        id,
+        shared,
+        event,
+        make_chunk(ReceiveChunkMarker::End, None),
+    );
+}
+
+fn handle_next_input_event<R: Runtime>(
+    window: &Window<R>,
+    id: &str,
+    shared: &Arc<ConnectionShared>,
+    sender: &WeakUnboundedSender<OutgoingPacket>,
+    event_maybe: Option<Event>,
+) -> Option<Result<(), PollInputError>> {
+    let target = &log_target(id);
+
+    // Any event received? (or no event?)
+    if let Some(event) = event_maybe {
+        // Remember the underlying error kind (attached to error states)
+        if let Event::Disconnected(ref err) = event {
+            *shared.error_kind.lock().unwrap() = Some(make_error_kind(err));
+        }
+
+        match event {
+            Event::Disconnected(err) if shared.can_resolve_conflict(&err) => {
+                warn!(
+                    target: target,
+                    "Received disconnected event: #{}, with resource conflict: {}",
+                    id, err
+                );
+
+                // Notice: no state is emitted there, as the connection will be \
+                //   retried right away with another resource.
+                Some(Err(PollInputError::ResourceConflictError))
+            }
+            Event::Disconnected(err) if shared.system_shutdown.load(Ordering::SeqCst) => {
+                warn!(
+                    target: target,
+                    "Received disconnected event: #{}, after system shutdown: {}",
+                    id, err
+                );
+
+                // Notice: the server asked us to reconnect later, thus this is \
+                //   considered as a recoverable connection error.
+                emit_connection_abort_detail(
+                    window,
+                    id,
+                    shared,
+                    ConnectionState::ConnectionError,
+                    Some(ConnectionStateDetail {
+                        reason: ConnectionStateReason::SystemShutdown,
+                        condition: Some("system-shutdown".to_string()),
+                        reconnect_delay: Some(SYSTEM_SHUTDOWN_RECONNECT_DELAY_MILLISECONDS),
+                        tried: None,
+                    }),
+                );
+
+                // Abort here (error)
+                Some(Err(PollInputError::ConnectionError))
+            }
+            Event::Disconnected(err) if shared.reauth_required.load(Ordering::SeqCst) => {
+                warn!(
+                    target: target,
+                    "Received disconnected event: #{}, after re-authentication request: {}",
+                    id, err
+                );
+
+                // Notice: this is not an error per-se, as the implementor can \
+                //   silently connect again with the credentials it holds.
+                emit_connection_abort(window, id, shared, ConnectionState::ReauthRequired);
+
+                // Abort here (error)
+                Some(Err(PollInputError::AuthenticationError))
+            }
+            Event::Disconnected(err) if shared.idle_closed.swap(false, Ordering::SeqCst) => {
+                warn!(
+                    target: target,
+                    "Received disconnected event: #{}, after server idle close: {}",
+                    id, err
+                );
+
+                // Notice: the server closed the stream as it considered it \
+                //   idle, which the implementor might want to prevent from \
+                //   happening again (eg. by pinging more often).
+                emit_connection_abort_detail(
+                    window,
+                    id,
+                    shared,
+                    ConnectionState::Disconnected,
+                    Some(ConnectionStateDetail {
+                        reason: ConnectionStateReason::IdleTimeout,
+                        condition: shared.idle_condition.lock().unwrap().take(),
+                        reconnect_delay: None,
+                        tried: None,
+                    }),
+                );
+
+                // Abort here (success)
+                Some(Ok(()))
+            }
+            Event::Disconnected(Error::Disconnected) => {
+                info!(target: target, "Received disconnected event on: #{}", id);
+
+                emit_connection_abort(window, id, shared, ConnectionState::Disconnected);
+
+                // Abort here (success)
+                Some(Ok(()))
+            }
+            Event::Disconnected(Error::Auth(err)) => {
+                warn!(
+                    target: target,
+                    "Received disconnected event on: #{}, with authentication error: {}",
+                    id, err
+                );
+
+                // Track authentication failure (for cooldown purposes)
+                shared.record_auth_failure();
+
+                emit_connection_abort_detail(
+                    window,
+                    id,
+                    shared,
+                    ConnectionState::AuthenticationFailure,
+                    Some(make_authentication_failure_detail(&err)),
+                );
+
+                // Abort here (error)
+                Some(Err(PollInputError::AuthenticationError))
+            }
+            Event::Disconnected(err)
+                if *shared.error_kind.lock().unwrap()
+                    == Some(ConnectionErrorKind::AuthCertificateRejected) =>
+            {
+                warn!(
+                    target: target,
+                    "Received disconnected event on: #{}, as client certificate was rejected: {}",
+                    id, err
+                );
+
+                // Track authentication failure (for cooldown purposes)
+                // Notice: the certificate identity got refused by the server, \
+                //   which is an authentication failure, not a network error.
+                shared.record_auth_failure();
+
+                emit_connection_abort(window, id, shared, ConnectionState::AuthenticationFailure);
+
+                // Abort here (error)
+                Some(Err(PollInputError::AuthenticationError))
+            }
+            Event::Disconnected(Error::Connection(err))
+                if shared.report.failure() == Some(ConnectorStage::Resolve) =>
+            {
+                warn!(
+                    target: target,
+                    "Received disconnected event: #{}, with resolution error: {}",
+                    id, err
+                );
+
+                emit_connection_abort(window, id, shared, ConnectionState::ResolutionFailure);
+
+                // Abort here (error)
+                Some(Err(PollInputError::ResolutionError))
+            }
+            Event::Disconnected(err)
+                if !shared.options.servers.is_empty()
+                    && shared.report.failure() == Some(ConnectorStage::Connect) =>
+            {
+                warn!(
+                    target: target,
+                    "Received disconnected event: #{}, as no server could be connected: {}",
+                    id, err
+                );
+
+                // Notice: list all server candidates that were tried, in order.
+                emit_connection_abort_detail(
+                    window,
+                    id,
+                    shared,
+                    ConnectionState::ConnectionError,
+                    Some(ConnectionStateDetail {
+                        reason: ConnectionStateReason::ServersUnreachable,
+                        condition: None,
+                        reconnect_delay: None,
+                        tried: Some(shared.report.tried()),
+                    }),
+                );
+
+                // Abort here (error)
+                Some(Err(PollInputError::ConnectionError))
+            }
+            Event::Disconnected(Error::Connection(err)) => {
+                warn!(
+                    target: target,
+                    "Received disconnected event: #{}, with connection error: {}",
+                    id, err
+                );
+
+                emit_connection_abort(window, id, shared, ConnectionState::ConnectionError);
+
+                // Abort here (error)
+                Some(Err(PollInputError::ConnectionError))
+            }
+            Event::Disconnected(err) if is_parse_error(&err) => {
+                warn!(
+                    target: target,
+                    "Received disconnected event: #{}, with parse error: {}",
+                    id, err
+                );
+
+                // Surface parse error details to the frontend
+                // Notice: a malformed inbound stanza breaks the underlying XML \
+                //   stream, which cannot be recovered from (the 'tokio-xmpp' \
+                //   client stops there). The best we can do is to let the \
+                //   implementor know about the parse failure, for debugging \
+                //   purposes, before going through the regular abort path. \
+                //   The parser does not tell the error position, thus the \
+                //   last received bytes are attached instead (truncated), as \
+                //   they hold the offending bytes.
+                let bytes = shared.report.received_tail();
+
+                emit_connection_event(
+                    window,
+                    id,
+                    shared,
+                    EVENT_PARSE_ERROR,
+                    EventConnectionParseError {
+                        id,
+                        error: &err.to_string(),
+                        bytes: &String::from_utf8_lossy(&bytes),
+                    },
+                );
+
+                emit_connection_abort(window, id, shared, ConnectionState::ConnectionError);
+
+                // Abort here (error)
+                Some(Err(PollInputError::ParseError))
+            }
+            Event::Disconnected(err) => {
+                warn!(
+                    target: target,
+                    "Received disconnected event: #{}, with error: {}",
+                    id, err
+                );
+
+                emit_connection_abort(window, id, shared, ConnectionState::ConnectionError);
+
+                // Abort here (error)
+                Some(Err(PollInputError::OtherError))
+            }
+            Event::Online { bound_jid, .. } => {
+                info!(
+                    target: target,
+                    "Received connected event on: #{}, bound on JID: {}",
+                    id, bound_jid
+                );
+
+                // Log negotiated SASL mechanism (for audit purposes)
+                if let Some(mechanism) = shared.report.sasl_mechanism() {
+                    info!(
+                        target: target,
+                        "Authenticated on: #{}, with SASL mechanism: {}",
+                        id, mechanism
+                    );
+                }
+
+                *shared.error_kind.lock().unwrap() = None;
+                *shared.idle_condition.lock().unwrap() = None;
+
+                shared.idle_closed.store(false, Ordering::SeqCst);
+                shared.resource_conflict_retries.store(0, Ordering::SeqCst);
+                shared.clear_auth_failures();
+
+                // Reset reconnection attempts (we are connected again)
+                shared.reconnect_attempts.store(0, Ordering::SeqCst);
+
+                // Acquire whether the session should be restored (upon \
+                //   reconnecting only, if requested)
+                let reconnected = shared.bound_before.swap(true, Ordering::SeqCst);
+                let restoring = reconnected && shared.options.restore_session;
+
+                // Acquire whether the previous stream was resumed (if requested)
+                let resumption = shared.report.resume_outcome();
+                let resumed = matches!(resumption, Some(ResumeOutcome::Resumed(_)));
+
+                // Remember when bound (and count reconnections, for metrics)
+                *shared.bound_at.lock().unwrap() = Some(Instant::now());
+
+                if reconnected {
+                    shared.reconnects.fetch_add(1, Ordering::Relaxed);
+                }
+
+                *shared.bound_jid.lock().unwrap() = Some(bound_jid.to_string());
+
+                rebind_connection_jid(window, shared, bound_jid.to_bare());
+
+                // Previous stream resumed? (keep its session)
+                // Notice: the session state is kept by the server (eg. our \
+                //   presence and carbons), thus nothing gets negotiated again. \
+                //   Stanzas that the server did not handle are re-sent only.
+                if let Some(ResumeOutcome::Resumed(handled)) = resumption {
+                    let unacked = shared.resume_sm(handled);
+
+                    info!(
+                        target: target,
+                        "Resumed previous stream on: #{}, re-sending {} unhandled stanzas",
+                        id,
+                        unacked.len()
+                    );
+
+                    for stanza in unacked {
+                        send_internal_stanza(sender, stanza);
+                    }
+                } else {
+                    shared.carbons_enabled.store(false, Ordering::SeqCst);
+
+                    // Enable stream management? (if requested)
+                    // Notice: stanzas that were left unacknowledged by the previous \
+                    //   stream get redelivered in the new stream (after enabling, so \
+                    //   that they get tracked again). If the previous stream could \
+                    //   not be resumed, then the server might have told how many \
+                    //   stanzas it handled, in which case only the other ones are \
+                    //   redelivered.
+                    if shared.options.stream_management {
+                        let handled = match resumption {
+                            Some(ResumeOutcome::Failed(handled)) => handled,
+                            _ => None,
+                        };
+
+                        let unacked = shared.take_sm_unacked(handled);
+
+                        if !unacked.is_empty() {
+                            info!(
+                                target: target,
+                                "Redelivering {} unacknowledged stanzas on: #{}",
+                                unacked.len(),
+                                id
+                            );
+                        }
+
+                        // Notice: resumption is requested, so that the stream \
+                        //   can be resumed upon reconnecting (the server keeps \
+                        //   the session around for a while after disconnecting).
+                        send_internal_stanza(
+                            sender,
+                            Element::builder("enable", NS_SM)
+                                .attr("resume", "true")
+                                .build(),
+                        );
+
+                        for stanza in unacked {
+                            send_internal_stanza(sender, stanza);
+                        }
+                    }
+
+                    // Acquire initial presence to send (if requested)
+                    // Notice: this is re-sent upon reconnecting, as the new \
+                    //   stream starts unavailable. If restoring the session, \
+                    //   then the last presence that was set gets re-sent \
+                    //   instead (if any). Nothing gets sent while appearing \
+                    //   offline.
+                    let initial_presence = shared.options.initial_presence.as_ref();
+
+                    let presence = restoring
+                        .then(|| shared.last_presence.lock().unwrap().clone())
+                        .flatten()
+                        .or_else(|| initial_presence.map(make_initial_presence))
+                        .filter(|_| !shared.appearing_offline.load(Ordering::SeqCst));
+
+                    // Enable message carbons? (if requested)
+                    // Notice: carbons are scoped to the stream, thus they only \
+                    //   get enabled again upon reconnecting if restoring the \
+                    //   session.
+                    let carbons = shared.options.carbons && (!reconnected || restoring);
+
+                    // Send initial presence (once carbons are enabled)
+                    // Notice: presence goes after enabling stream management, \
+                    //   so that it gets tracked, and after carbons resolve, so \
+                    //   that messages sent to us in reply to our presence also \
+                    //   get copied. Without carbons, it is queued before the \
+                    //   connected state gets emitted, so that it goes before \
+                    //   any stanza that the client sends once connected.
+                    if let Some(sender) = sender.upgrade() {
+                        if carbons {
+                            let (tier, runtime) = (shared.options.tier, shared.runtime.clone());
+                            let shared = shared.clone();
+
+                            spawn_poller(tier, runtime.as_ref(), async move {
+                                enable_carbons(&shared, &sender).await;
+
+                                if let Some(presence) = presence {
+                                    send_initial_presence(&shared, &sender, presence);
+                                }
+                            });
+                        } else if let Some(presence) = presence {
+                            send_initial_presence(shared, &sender, presence);
+                        }
+                    } else if presence.is_some() {
+                        warn!(
+                            target: target,
+                            "Could not send initial presence on: #{}, ignoring",
+                            id
+                        );
+                    }
+                }
+
+                let state = if resumed {
+                    ConnectionState::Resumed
+                } else {
+                    ConnectionState::Connected
+                };
+
+                emit_connection_state(window, id, shared, state);
+                emit_certificate_expiry(window, id, shared);
+                emit_stream_mismatch(window, id, shared, &bound_jid);
+
+                // Query server software? (opt-in)
+                // Notice: this is done in the background, as the response \
+                //   gets received by this very read poller.
+                if shared.options.server_info {
+                    if let Some(sender) = sender.upgrade() {
+                        let (tier, runtime) = (shared.options.tier, shared.runtime.clone());
+                        let (window, shared) = (window.clone(), shared.clone());
+                        let domain = bound_jid.domain().to_string();
+
+                        spawn_poller(tier, runtime.as_ref(), async move {
+                            emit_server_info(&window, &shared, &sender, &domain).await;
+                        });
+                    }
+                }
+
+                // Notice: this wakes up the write poller, which might have \
+                //   packets to replay (buffered while reconnecting).
+                shared.connected.notify_one();
+
+                shared.resolve_bound(Ok(bound_jid));
+
+                // Continue
+                None
+            }
+            Event::Stanza(stanza) => {
+                debug!(target: target, "Received stanza event on: #{}", id);
+
+                // Server is shutting down? (remember it, as it is about to \
+                //   close the stream)
+                if stanza.is("error", NS_STREAM)
+                    && stanza.has_child("system-shutdown", NS_STREAM_ERRORS)
+                {
+                    warn!(target: target, "Server is shutting down on: #{}", id);
+
+                    shared.system_shutdown.store(true, Ordering::SeqCst);
+                }
+
+                // Server requests to authenticate again? (remember it, as it \
+                //   is about to close the stream)
+                // Notice: servers close the stream with either of those when \
+                //   credentials or the security context expired or changed \
+                //   during the life of the stream.
+                if stanza.is("error", NS_STREAM)
+                    && (stanza.has_child("reset", NS_STREAM_ERRORS)
+                        || stanza.has_child("not-authorized", NS_STREAM_ERRORS))
+                {
+                    warn!(target: target, "Server requests re-authentication on: #{}", id);
+
+                    shared.reauth_required.store(true, Ordering::SeqCst);
+                }
+
+                // Server closes our stream as idle? (remember it, as it is \
+                //   about to close the stream)
+                if stanza.is("error", NS_STREAM) {
+                    let idle_condition = IDLE_CLOSE_CONDITIONS
+                        .into_iter()
+                        .find(|condition| stanza.has_child(condition, NS_STREAM_ERRORS));
+
+                    if let Some(idle_condition) = idle_condition {
+                        warn!(
+                            target: target,
+                            "Server closes idle stream on: #{} ({})",
+                            id, idle_condition
+                        );
+
+                        *shared.idle_condition.lock().unwrap() = Some(idle_condition.to_string());
+
+                        shared.idle_closed.store(true, Ordering::SeqCst);
+                    }
+                }
+
+                // Server refuses our resource? (remember it, as it is about to \
+                //   close the stream)
+                if stanza.is("error", NS_STREAM)
+                    && stanza.has_child("conflict", NS_STREAM_ERRORS)
+                    && !shared.current_state().is_connected()
+                {
+                    warn!(target: target, "Server refuses resource on: #{}", id);
+
+                    shared.resource_conflict.store(true, Ordering::SeqCst);
+                }
+
+                let stanza_xml = String::from(&stanza);
+
+                shared
+                    .counters
+                    .bytes_received
+                    .fetch_add(stanza_xml.len() as u64, Ordering::Relaxed);
+
+                // Stream management nonza? (handle it, and do not emit it)
+                // Notice: this also counts handled stanzas, which must happen \
+                //   before any stanza gets consumed internally.
+                if shared.options.stream_management {
+                    if let Some(answer) = shared.handle_sm_stanza(&stanza) {
+                        send_internal_stanza(sender, answer);
+                    }
+
+                    if stanza.ns() == NS_SM {
+                        return None;
+                    }
+                }
+
+                // Response to a pending request? (do not emit it)
+                let Some(stanza) = shared.resolve_iq(stanza) else {
+                    return None;
+                };
+
+                // Result to a pending archive query? (do not emit it)
+                let Some(stanza) = shared.collect_mam(stanza) else {
+                    return None;
+                };
+
+                // Reflection of a sent stanza? (confirm it, then emit as usual)
+                if let Some(token) = shared.match_reflection(&stanza) {
+                    emit_connection_reflected(window, id, shared, &stanza, &token);
+                }
+
+                // Receipt for a sent message? (confirm it, then emit as usual)
+                if let Some(message_id) = shared.match_receipt(&stanza) {
+                    emit_connection_event(
+                        window,
+                        id,
+                        shared,
+                        EVENT_RECEIPT,
+                        EventConnectionReceipt {
+                            id,
+                            message_id,
+                            from: stanza.attr("from"),
+                        },
+                    );
+                }
+
+                // Stanza not matching receive filter? (drop it, but count it)
+                // Notice: this happens after internal stanza handling, so that \
+                //   a filter cannot break pending requests.
+                if !shared.accepts_stanza(&stanza) {
+                    shared
+                        .counters
+                        .stanzas_filtered
+                        .fetch_add(1, Ordering::Relaxed);
+
+                    return None;
+                }
+
+                // Remember stanza (so that late subscribers can backfill)
+                shared.remember_stanza(&stanza_xml);
+
+                // Number stanza? (opt-in)
+                // Notice: the sequence is kept across reconnections, so that \
+                //   the implementor can detect gaps or reordering around them.
+                let sequence = shared
+                    .counters
+                    .stanzas_received
+                    .fetch_add(1, Ordering::Relaxed);
+                let sequence = shared.options.sequence_numbers.then_some(sequence);
+
+                // Notice: the stanza is remembered uncompressed, as it is \
+                //   compressed for the IPC transfer only.
+                let (stanza_payload, encoding) =
+                    make_stanza_payload(target, &stanza_xml, shared.options.compression.as_ref());
+
+                let receive_event = make_receive_event(shared, &stanza);
+
+                // Queue stanza for the receive poller? (if buffered, or muted)
+                // Notice: this decouples reads from emits, so that a slow \
+                //   frontend cannot stall the read poller (and time it out).
+                if shared.options.receive_buffer.is_some() || shared.is_muted() {
+                    let queueing = shared.queue_received(QueuedStanza {
+                        event: receive_event,
+                        stanza: stanza_payload.into_owned(),
+                        encoding,
+                        sequence,
+                    });
+
+                    match queueing {
+                        ReceiveQueueing::Queued => {}
+                        ReceiveQueueing::Emit(queued) => emit_queued_stanza(window, shared, queued),
+                        ReceiveQueueing::Overflowed => {
+                            warn!(target: target, "Receive buffer overflowed on: #{}", id);
+
+                            *shared.error_kind.lock().unwrap() =
+                                Some(ConnectionErrorKind::ReceiveOverflow);
+
+                            emit_connection_abort(
+                                window,
+                                id,
+                                shared,
+                                ConnectionState::ConnectionError,
+                            );
+
+                            // Abort here (overflow)
+                            return Some(Err(PollInputError::OverflowError));
+                        }
+                    }
+
+                    // Continue
+                    return None;
+                }
+
+                emit_received_stanza(
+                    window,
+                    id,
+                    shared,
+                    receive_event,
+                    EventConnectionReceive {
+                        id,
+                        stanza: stanza_payload,
+                        encoding,
+                        sequence,
+                    },
+                );
+
+                // Continue
+                None
+            }
+        }
+    } else {
+        // Abort here (no more events)
+        Some(Ok(()))
+    }
+}
+
+/**************************************************************************
+ * COMMANDS
+ * ************************************************************************* */
+
+#[tauri::command]
+pub async fn connect<R: Runtime>(
+    window: Window<R>,
+    state: State<'_, ConnectionClientState>,
+    id: &str,
+    jid: &str,
+    password: &str,
+    timeout: Option<u64>,
+    options: Option<ConnectOptions>,
+) -> Result<Option<String>, ConnectError> {
+    // Start connection, then wait for it to be bound? (if requested)
+    // Notice: if not waiting, then this only starts the connection, and its \
+    //   states get reported via events only (fire-and-forget).
+    let bound_waiter = start_connection(window, &state, id, jid, password, timeout, options)?;
+
+    let Some(bound_waiter) = bound_waiter else {
+        return Ok(None);
+    };
+
+    match bound_waiter.await {
+        Ok(Ok(bound_jid)) => {
+            info!(target: &log_target(id), "Connection #{} is now bound on JID: {}", id, bound_jid);
+
+            Ok(Some(bound_jid.to_string()))
+        }
+        Ok(Err(terminal_state)) => {
+            warn!(
+                target: &log_target(id),
+                "Connection #{} could not be bound, got state: {:?}",
+                id, terminal_state
+            );
+
+            // Acquire underlying error kind (to tell some errors apart, eg. \
+            //   certificate pin mismatches)
+            let error_kind = state
+                .connections
+                .get(id)
+                .and_then(|connection| *connection.shared.error_kind.lock().unwrap());
+
+            Err(match (terminal_state, error_kind) {
+                (ConnectionState::AuthenticationFailure, _) => ConnectError::AuthenticationFailure,
+                (ConnectionState::ResolutionFailure, _) => ConnectError::ResolutionFailure,
+                (ConnectionState::ConnectionTimeout, _) => ConnectError::ConnectionTimeout,
+                (_, Some(ConnectionErrorKind::CertificatePinMismatch)) => {
+                    ConnectError::CertificatePinMismatch
+                }
+                (_, Some(ConnectionErrorKind::AuthNoMechanism)) => {
+                    ConnectError::NoAcceptableSaslMechanism
+                }
+                _ => ConnectError::ConnectionError,
+            })
+        }
+        Err(_) => Err(ConnectError::ConnectionError),
+    }
+}
+
+fn start_connection<R: Runtime>(
+    window: Window<R>,
+    state: &ConnectionClientState,
+    id: &str,
+    jid: &str,
+    password: &str,
+    timeout: Option<u64>,
+    options: Option<ConnectOptions>,
+) -> Result<Option<oneshot::Receiver<BoundResult>>, ConnectError> {
+    info!(target: &log_target(id), "Connection #{} connect requested on JID: {}", id, jid);
+
+    let options = options.unwrap_or_default();
+
+    // Parse JID (and apply resource policy, if any)
+    // Notice: anonymous connections only use the domain of the JID, as the \
+    //   server assigns the final JID upon binding.
+    let jid = if options.anonymous {
+        make_anonymous_jid(jid, options.resource_prefix.as_deref())?
+    } else {
+        jid.to_string()
+    };
+
+    let jid_full = make_full_jid(&jid, options.resource_prefix.as_deref())?;
+    let jid_bare = jid_full.to_bare();
+
+    // Parse receive filter (if any)
+    let receive_filter = options
+        .receive_filter
+        .as_deref()
+        .map(|expression| expression.parse::<StanzaFilter>())
+        .transpose()
+        .or(Err(ConnectError::InvalidFilter))?;
+
+    // Validate stream language (if any)
+    // Notice: this is injected as-is in the stream header, thus it must be a \
+    //   well-formed language tag (eg. 'en' or 'pt-BR').
+    if let Some(ref lang) = options.lang {
+        if !is_valid_lang(lang) {
+            return Err(ConnectError::InvalidLang);
+        }
+    }
+
+    // Validate server endpoints (if any)
+    // Notice: server endpoints bypass SRV resolution, meaning that the exact \
+    //   host and port get dialed, while the JID domain is still used for the \
+    //   stream and for authentication (eg. local servers on custom ports).
+    if !options.servers.iter().all(is_valid_server_endpoint) {
+        return Err(ConnectError::InvalidServerEndpoint);
+    }
+
+    // Acquire transport (validating the WebSocket URL, if any)
+    let transport = make_connector_transport(&options)?;
+
+    // Validate heartbeat interval (if any)
+    // Notice: a zero interval is rejected, as the heartbeat timer cannot \
+    //   tick on a zero period (this would bring down the read poller).
+    if options.heartbeat_interval == Some(0) {
+        return Err(ConnectError::InvalidHeartbeatInterval);
+    }
+
+    // Validate bandwidth interval (if any)
+    if options.bandwidth_interval == Some(0) {
+        return Err(ConnectError::InvalidBandwidthInterval);
+    }
+
+    // Validate ping interval and misses (if any)
+    // Notice: zero misses are rejected as well, as the connection would then \
+    //   be considered stale upon every ping (even answered ones).
+    if options.ping_interval == Some(0) || options.ping_misses == Some(0) {
+        return Err(ConnectError::InvalidPing);
+    }
+
+    // Parse TLS trust (trusted roots and certificate pin, if any)
+    let tls_trust = make_tls_trust(options.tls_trust.as_ref())?;
+
+    // Parse TLS identity (client certificate and its key, if any)
+    let tls_identity = options
+        .tls_identity
+        .as_ref()
+        .map(|identity| {
+            connector::parse_identity(&identity.certificate, &identity.key)
+                .ok_or(ConnectError::InvalidIdentity)
+        })
+        .transpose()?;
+
+    // Acquire admission lock (held until the new connection gets inserted)
+    // Notice: the connections map is sharded and thus has no global lock, \
+    //   this makes sure that concurrent connect requests cannot both pass \
+    //   the checks below before either of them inserts its connection.
+    let _admission = state.admission.lock().unwrap();
+
+    // Assert that connection identifier does not already exist
+    if state.connections.contains_key(id) {
+        return Err(ConnectError::ConnectionAlreadyExists);
+    }
+
+    // Assert that adding a connection would not exceed the maximum amount \
+    //   of connections (if any). This is a safety rail against implementor \
+    //   clients spawning connections without bounds.
+    if let Some(max_connections) = state.settings.max_connections {
+        if state.connections.len() >= max_connections {
+            error!(
+                target: &log_target(id),
+                "Connection #{} connect request refused, as there are already {} connections",
+                id,
+                state.connections.len()
+            );
+
+            return Err(ConnectError::TooManyConnections);
+        }
+    }
+
+    // Assert that another connection with this JID does not already exist in \
+    //   the global state. This prevents connection manager mis-uses where the \
+    //   implementor client would request multiple parallel connections on the \
+    //   same JID.
+    // Notice: this guard can be lifted globally from the plugin settings, \
+    //   for implementors that legitimately run multiple connections on the \
+    //   same JID (eg. multi-account development tools), or per-connection \
+    //   using the JID conflict policy. Anonymous connections never conflict, \
+    //   as their JIDs get assigned by the server upon binding.
+    let jid_conflict = if state.settings.allow_same_jid || options.anonymous {
+        JidConflictPolicy::AllowMultiple
+    } else {
+        options.jid_conflict
+    };
+
+    if jid_conflict != JidConflictPolicy::AllowMultiple {
+        // Scan all connections in the state
+        // Notice: conflicting identifiers are collected first, since the \
+        //   connections map cannot be mutated while iterating over it.
+        let conflicting_ids = state
+            .connections
+            .iter()
+            .filter(|connection| jid_bare == connection.jid)
+            .map(|connection| connection.key().to_owned())
+            .collect::<Vec<_>>();
+
+        for conflicting_id in conflicting_ids {
+            // Found another active connection in the state on the same JID?
+            if jid_conflict == JidConflictPolicy::Reject {
+                error!(
+                    target: &log_target(id),
+                    "Connection #{} connect request found to conflict with: #{}",
+                    id, conflicting_id
+                );
+
+                return Err(ConnectError::AnotherConnectionBound);
+            }
+
+            info!(
+                target: &log_target(id),
+                "Connection #{} connect request takes over conflicting: #{}",
+                id, conflicting_id
+            );
+
+            take_over_connection(&window, state, &conflicting_id);
+        }
+    }
+
+    // Assert that the JID is not cooling down after repeated authentication \
+    //   failures (if enabled). This avoids hammering the server with bad \
+    //   credentials, which could get the account locked out.
+    if let Some(remaining) = state.auth_failures.remaining_cooldown(&jid_bare) {
+        error!(
+            target: &log_target(id),
+            "Connection #{} connect request refused, as JID is cooling down for: {}ms",
+            id,
+            remaining.as_millis()
+        );
+
+        return Err(ConnectError::AuthCooldown(remaining.as_millis() as u64));
+    }
+
+    // Acquire read timeout (falls back to the default one, if set)
+    let read_timeout = timeout
+        .or(*state.default_read_timeout.read().unwrap())
+        .unwrap_or(READ_TIMEOUT_MILLISECONDS);
+
+    // Create new client
+    let shared = Arc::new(ConnectionShared {
+        id: RwLock::new(id.to_owned()),
+        jid: RwLock::new(Some(jid_bare.clone())),
+        options,
+        runtime: state.runtime.clone(),
+        auth_failures: state.auth_failures.clone(),
+        receive_filter: RwLock::new(receive_filter),
+        read_timeout: Duration::from_millis(read_timeout),
+        ..Default::default()
+    });
+
+    // Register bound waiter? (before spawning tasks, so that the first \
+    //   terminal state cannot race the registration)
+    let bound_waiter = if shared.options.wait_bound {
+        let (bound_tx, bound_rx) = oneshot::channel();
+
+        *shared.bound_waiter.lock().unwrap() = Some(bound_tx);
+
+        Some(bound_rx)
+    } else {
+        None
+    };
+
+    // Notice: the connector is kept around, as it gets re-used by each \
+    //   reconnection attempt. If server candidates are provided, then they \
+    //   get tried in order (each with its own timeout), instead of the \
+    //   servers resolved from the JID domain.
+    let server_timeout = (!shared.options.servers.is_empty()).then(|| {
+        Duration::from_millis(
+            shared
+                .options
+                .server_timeout
+                .unwrap_or(SERVER_TIMEOUT_MILLISECONDS),
+        )
+    });
+
+    let connector = ConnectionConnector::new(shared.report.clone())
+        .with_tor_proxy(state.settings.tor_proxy.clone())
+        .with_resolver(make_connector_resolver(shared.options.resolver.as_ref()))
+        .with_transport(transport)
+        .with_endpoints(
+            shared
+                .options
+                .servers
+                .iter()
+                .map(|server| (server.host.clone(), server.port))
+                .collect(),
+        )
+        .with_endpoint_timeout(server_timeout)
+        .with_rate_limits(shared.options.read_rate_limit, shared.options.write_rate_limit)
+        .with_strict_stream_from(shared.options.strict_stream_from)
+        .with_lang(shared.options.lang.clone())
+        .with_tls_min_version(shared.options.tls_min_version)
+        .with_tls_trust(tls_trust)
+        .with_tls_identity(tls_identity)
+        .with_sasl_mechanisms(make_sasl_mechanisms(&shared.options));
+
+    let client = make_client(&jid_full, password, connector.clone());
+
+    // Split client into RX (for writer) and TX (for reader)
+    // Notice: the client writer gets handed over to the write poller via a \
+    //   channel, since a new client writer gets provided by the read poller \
+    //   whenever it reconnects.
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (writers_tx, writers_rx) = mpsc::unbounded_channel();
+    let (writer, reader) = client.split();
+
+    writers_tx.send(writer).ok();
+
+    // Label connection logs? (if a label is set)
+    if let Some(ref label) = shared.options.label {
+        LOG_LABELS.insert(id.to_owned(), label.to_owned());
+    }
+
+    // Spawn all tasks
+    let (tier, runtime) = (shared.options.tier, shared.runtime.clone());
+
+    let write_handle = {
+        let window = window.clone();
+        let shared = shared.clone();
+
+        spawn_poller(tier, runtime.as_ref(), async move {
+            info!(
+                target: &log_target(&shared.id()),
+                "Connection #{} write poller has started",
+                shared.id()
+            );
+
+            // Poll for output events
+            let result = poll_output_events(&window, &shared, writers_rx, rx).await;
+
+            // Notice: acquire identifier once done polling, as the \
+            //   connection might have been renamed in the meantime.
+            let id = shared.id();
+
+            // Window is gone? (clean up the connection, then stop there)
+            if let Err(PollOutputError::WindowGone) = result {
+                warn!(
+                    target: &log_target(&id),
+                    "Connection #{} write poller stopped, as window is gone",
+                    id
+                );
+
+                destroy_orphaned_connection(&window, &shared);
+
+                return;
+            }
+
+            let reason = if let Err(err) = result {
+                warn!(
+                    target: &log_target(&id),
+                    "Connection #{} write poller terminated with error: {}",
+                    id, err
+                );
+
+                SendClosedReason::PacketSendError
+            } else {
+                info!(target: &log_target(&id), "Connection #{} write poller was stopped", id);
+
+                SendClosedReason::ChannelClosed
+            };
+
+            // Tell that nothing can be sent anymore
+            // Notice: this is not emitted if the task gets aborted (ie. upon \
+            //   destroying the connection), as the client already knows.
+            emit_connection_event(
+                &window,
+                &id,
+                &shared,
+                EVENT_SEND_CLOSED,
+                EventConnectionSendClosed { id: &id, reason },
+            );
+        })
+    };
+
+    let bandwidth_handle = shared.options.bandwidth_interval.map(|interval| {
+        let window = window.clone();
+        let shared = shared.clone();
+        let interval = Duration::from_millis(interval);
+
+        spawn_poller(tier, runtime.as_ref(), async move {
+            info!(
+                target: &log_target(&shared.id()),
+                "Connection #{} bandwidth poller has started (with interval: {}ms)",
+                shared.id(),
+                interval.as_millis()
+            );
+
+            poll_bandwidth_events(&window, &shared, interval).await;
+        })
+    });
+
+    let receive_handle = shared.options.receive_buffer.as_ref().map(|_| {
+        let window = window.clone();
+        let shared = shared.clone();
+
+        spawn_poller(tier, runtime.as_ref(), async move {
+            info!(
+                target: &log_target(&shared.id()),
+                "Connection #{} receive poller has started",
+                shared.id()
+            );
+
+            poll_receive_events(&window, &shared).await;
+        })
+    });
+
+    let ping_handle = shared.options.ping_interval.map(|interval| {
+        let shared = shared.clone();
+        let interval = Duration::from_millis(interval);
+        let domain = jid_bare.domain().to_string();
+
+        // Notice: only hold a weak sender there, as the connection is \
+        //   considered closed once all strong senders are dropped.
+        let sender = tx.downgrade();
+
+        spawn_poller(tier, runtime.as_ref(), async move {
+            info!(
+                target: &log_target(&shared.id()),
+                "Connection #{} ping poller has started (with interval: {}ms)",
+                shared.id(),
+                interval.as_millis()
+            );
+
+            poll_ping_events(&shared, &sender, &domain, interval).await;
+        })
+    });
+
+    let read_handle = {
+        let shared = shared.clone();
+        let password = password.to_string();
+        let read_timeout = shared.read_timeout;
+
+        // Notice: only hold a weak sender there, as the connection is \
+        //   considered closed once all strong senders are dropped.
+        let sender = tx.downgrade();
+
+        spawn_poller(tier, runtime.as_ref(), async move {
+            info!(
+                target: &log_target(&shared.id()),
+                "Connection #{} read poller has started (with timeout: {}ms)",
+                shared.id(),
+                read_timeout.as_millis()
+            );
+
+            let mut reader = reader;
+            let mut jid_full = jid_full;
+
+            loop {
+                // Poll for input events
+                let result =
+                    poll_input_events(&window, &shared, &sender, read_timeout, reader).await;
+
+                // Notice: acquire identifier once done polling, as the \
+                //   connection might have been renamed in the meantime.
+                let id = shared.id();
+
+                if let Err(ref err) = result {
+                    warn!(
+                        target: &log_target(&id),
+                        "Connection #{} read poller terminated with error: {}",
+                        id, err
+                    );
+                } else {
+                    info!(target: &log_target(&id), "Connection #{} read poller was stopped", id);
+                }
+
+                if let Err(PollInputError::WindowGone) = result {
+                    // Stop there (nowhere to report to anymore)
+                    destroy_orphaned_connection(&window, &shared);
+
+                    break;
+                } else if let Err(PollInputError::ResourceConflictError) = result {
+                    // Retry right away with another resource (same bare JID)
+                    // Notice: the resource prefix policy is still followed, \
+                    //   and the final resource is told once the JID is bound.
+                    let resource_prefix = shared.options.resource_prefix.as_deref();
+
+                    jid_full = jid_full
+                        .to_bare()
+                        .with_resource_str(&make_resource(resource_prefix.unwrap_or_default()))
+                        .unwrap_or(jid_full);
+
+                    info!(
+                        target: &log_target(&id),
+                        "Connection #{} will retry with resource: {}",
+                        id,
+                        jid_full.resource()
+                    );
+                } else if shared.current_state() != ConnectionState::Reconnecting {
+                    // Stop there (not reconnecting)
+                    break;
+                } else {
+                    // Wait for backoff delay, then reconnect with a new client
+                    // Notice: an explicit disconnect aborts this task, which \
+                    //   also cancels any pending reconnection.
+                    let delay = shared.next_reconnect_delay();
+
+                    info!(
+                        target: &log_target(&id),
+                        "Connection #{} will reconnect in {}ms",
+                        id,
+                        delay.as_millis()
+                    );
+
+                    time::sleep(delay).await;
+                }
+
+                // Request resuming the previous stream? (if resumable)
+                // Notice: a stream cannot be resumed when retrying with \
+                //   another resource, as it was never bound.
+                let resume = match result {
+                    Err(PollInputError::ResourceConflictError) => None,
+                    _ => shared.sm_resume_request(),
+                };
+
+                let client_jid = resume.as_ref().map(|(_, jid)| jid).unwrap_or(&jid_full);
+
+                if let Some((ref request, _)) = resume {
+                    info!(
+                        target: &log_target(&id),
+                        "Connection #{} will request resuming stream: {}",
+                        id, request.previd
+                    );
+                }
+
+                connector.request_resume(resume.as_ref().map(|(request, _)| request.clone()));
+
+                let (next_writer, next_reader) =
+                    make_client(client_jid, &password, connector.clone()).split();
+
+                if writers_tx.send(next_writer).is_err() {
+                    warn!(
+                        target: &log_target(&id),
+                        "Connection #{} cannot reconnect, as write poller is gone",
+                        id
+                    );
+
+                    shared.cancel_reconnect();
+
+                    emit_connection_abort(&window, &id, &shared, ConnectionState::ConnectionError);
+
+                    break;
+                }
+
+                reader = next_reader;
+            }
+
+            schedule_auto_destroy(&window, &shared);
+        })
+    };
+
+    // Add new connection in state
+    // Notice: the connections map is sharded, meaning that inserting here \
+    //   does not block operations on other connections (eg. sends).
+    state.connections.insert(
+        id.to_string(),
+        ConnectionClient {
+            jid: shared.jid().unwrap_or(jid_bare),
+            sender: tx,
+            shared,
+            read_handle,
+            write_handle,
+            bandwidth_handle,
+            receive_handle,
+            ping_handle,
+        },
+    );
+
+    info!(
+        "There are now {} connections in the global state: {}",
+        state.connections.len(),
+        state
+            .connections
+            .iter()
+            .map(|connection| format!("#{}", connection.key()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    info!(target: &log_target(id), "Connection #{} connect request complete", id);
+
+    Ok(bound_waiter)
+}
+
+#[tauri::command]
+pub async fn disconnect<R: Runtime>(
+    window: Window<R>,
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+    options: Option<DisconnectOptions>,
+) -> Result<u64, DisconnectError> {
+    info!(target: &log_target(id), "Connection #{} disconnect requested", id);
+
+    let options = options.unwrap_or_default();
+
+    // Send stream end?
+    // Notice: release the state lock before waiting for the server to close \
+    //   the stream, as it cannot be held across await points.
+    let (shared, undelivered) = if let Some(connection) = state.connections.get(id) {
+        // Wait for server to close the stream? (only if connected)
+        let graceful =
+            options.graceful && connection.shared.current_state().is_connected();
+
+        // Abort read task handle? (so that no other IPC gets sent)
+        // Notice: this also cancels any pending reconnection. If graceful, \
+        //   the read poller is kept until the server closes the stream.
+        connection.shared.cancel_reconnect();
+
+        if !graceful {
+            connection.read_handle.abort();
+        }
+
+        // Emit end-of-stream packet (requesting a clean disconnection)
+        match connection.sender.send(OutgoingPacket {
+            packet: Packet::StreamEnd,
+            size: 0,
+            token: None,
+            tracked: false,
+            flushed: None,
+        }) {
+            Ok(_) => {
+                // Notice: packets still queued ahead of the end-of-stream \
+                //   packet might still get flushed, but the implementor is \
+                //   told about them since there is no guarantee that they will.
+                let undelivered = connection.shared.undelivered_packets();
+
+                if graceful {
+                    (connection.shared.clone(), undelivered)
+                } else {
+                    info!(
+                        target: &log_target(id),
+                        "Connection #{} disconnect request complete (with {} packets still queued)",
+                        id, undelivered
+                    );
+
+                    // Consider as disconnected immediately
+                    // Notice: this saves some time, instead of waiting for stream end \
+                    //   acknowledgement from server which may never come in case of a \
+                    //   disconnect request following network issues (thus we would be \
+                    //   waiting a long time for the TCP timeout to trigger).
+                    emit_connection_abort(
+                        &window,
+                        id,
+                        &connection.shared,
+                        ConnectionState::Disconnected,
+                    );
+
+                    schedule_auto_destroy(&window, &connection.shared);
+
+                    return Ok(undelivered);
+                }
+            }
+            Err(err) => {
+                error!(
+                    target: &log_target(id),
+                    "Connection #{} disconnect request failed, because: {}",
+                    id, err
+                );
+
+                // Recover from closed sender channel state (implicitly disconnect)
+                recover_closed_sender_channel(&window, id, &connection);
+
+                return Err(DisconnectError::CannotWrite);
+            }
+        }
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} disconnect request failed, as connection does not exist",
+            id
+        );
+
+        return Err(DisconnectError::ConnectionDoesNotExist);
+    };
+
+    // Wait for server to close the stream (up to the grace deadline)
+    // Notice: the read poller emits the disconnected state once the server \
+    //   closes the stream, while stanzas received in the meantime still get \
+    //   emitted (eg. our own unavailable presence, as reflected by the server).
+    let grace_timeout = Duration::from_millis(
+        options
+            .grace_timeout
+            .unwrap_or(DISCONNECT_GRACE_TIMEOUT_MILLISECONDS),
+    );
+    let deadline = time::Instant::now() + grace_timeout;
+
+    if !shared.wait_terminated(deadline).await {
+        warn!(
+            target: &log_target(id),
+            "Connection #{} disconnect request timed out waiting for stream close",
+            id
+        );
+
+        // Abort read task handle, then consider as disconnected (as the \
+        //   server did not close the stream in due time)
+        if let Some(connection) = state.connections.get(&shared.id()) {
+            connection.read_handle.abort();
+        }
+
+        emit_connection_abort(&window, id, &shared, ConnectionState::Disconnected);
+
+        schedule_auto_destroy(&window, &shared);
+
+        return Ok(undelivered);
+    }
+
+    info!(
+        target: &log_target(id),
+        "Connection #{} disconnect request complete (stream closed by server)",
+        id
+    );
+
+    Ok(undelivered)
+}
+
+#[tauri::command]
+pub async fn disconnect_all<R: Runtime>(
+    window: Window<R>,
+    state: State<'_, ConnectionClientState>,
+) -> Result<(), ()> {
+    info!("Connection disconnect all requested");
+
+    let connections = take_connections(&state);
+
+    // Consider all connections as disconnected immediately
+    // Notice: retire them right after, as their identifiers can be re-used \
+    //   while they are being drained.
+    for (id, connection) in connections.iter() {
+        emit_connection_abort(&window, id, &connection.shared, ConnectionState::Disconnected);
+
+        connection.shared.retire();
+    }
+
+    future::join_all(
+        connections
+            .iter()
+            .map(|(_, connection)| connection.shared.settle_emissions()),
+    )
+    .await;
+
+    drain_connections(connections, state.settings.shutdown_drain_timeout()).await;
+
+    info!("Connection disconnect all request complete");
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn drain(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+    timeout: Option<u64>,
+) -> Result<(), DrainError> {
+    info!(target: &log_target(id), "Connection #{} drain requested", id);
+
+    // Stop accepting new stanzas
+    // Notice: release the state lock right after, as it cannot be held \
+    //   across await points.
+    let shared = if let Some(connection) = state.connections.get(id) {
+        connection.shared.draining.store(true, Ordering::SeqCst);
+
+        connection.shared.clone()
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} drain request failed, as connection does not exist",
+            id
+        );
+
+        return Err(DrainError::ConnectionDoesNotExist);
+    };
+
+    // Wait for all queued stanzas to be flushed (up to the drain deadline)
+    // Notice: stanzas dropped from the reconnect buffer never get flushed, \
+    //   in which case this times out (the caller can disconnect anyway).
+    let drain_timeout = Duration::from_millis(timeout.unwrap_or(DRAIN_TIMEOUT_MILLISECONDS));
+    let deadline = Instant::now() + drain_timeout;
+
+    while shared.undelivered_packets() > 0 {
+        if Instant::now() >= deadline {
+            warn!(
+                target: &log_target(id),
+                "Connection #{} drain request timed out, with {} packets still queued",
+                id,
+                shared.undelivered_packets()
+            );
+
+            return Err(DrainError::FlushTimeout);
+        }
+
+        time::sleep(Duration::from_millis(DRAIN_POLL_INTERVAL_MILLISECONDS)).await;
+    }
+
+    info!(target: &log_target(id), "Connection #{} drain request complete", id);
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn destroy(id: &str, state: State<'_, ConnectionClientState>) -> Result<u64, ()> {
+    info!(target: &log_target(id), "Connection #{} destroy requested", id);
+
+    // Remove existing connection?
+    // Important: this does not disconnect the XMPP stream! Please make sure to call \
+    //   the destroy command whenever the frontend is certain that the connection \
+    //   has been disconnected, that is, following an explicit or implicit \
+    //   disconnection connection state event. The destroy command is solely \
+    //   used for garbage collection purposes (ie. stopping background tasks).
+    if let Some((_, connection)) = state.connections.remove(id) {
+        // Abort both task handles
+        // Notice: aborted tasks might still be running until their next \
+        //   await point, thus retire the connection so that they go silent.
+        kill_event_handlers(&connection);
+
+        connection.shared.retire();
+        connection.shared.settle_emissions().await;
+        connection.shared.cancel_iqs();
+
+        // Drop connection sender
+        drop(connection.sender);
+
+        // Forget about log level and label overrides (if any)
+        forget_log_overrides(id);
+
+        // Tell about packets that were never sent (as they got dropped)
+        // Notice: the implementor got a successful result when sending \
+        //   those, thus it must be told that they were not delivered.
+        let undelivered = connection.shared.undelivered_packets();
+
+        if undelivered > 0 {
+            warn!(
+                target: &log_target(id),
+                "Connection #{} destroyed with {} undelivered packets",
+                id, undelivered
+            );
+        }
+
+        info!(target: &log_target(id), "Connection #{} destroy request complete", id);
+
+        Ok(undelivered)
+    } else {
+        warn!(
+            target: &log_target(id),
+            "Connection #{} destroy request complete, but was already destroyed",
+            id
+        );
+
+        Ok(0)
+    }
+}
+
+#[tauri::command]
+pub fn rename_connection(
+    old_id: &str,
+    new_id: &str,
+    state: State<'_, ConnectionClientState>,
+) -> Result<(), RenameError> {
+    info!(target: &log_target(old_id), "Connection #{} rename requested to: #{}", old_id, new_id);
+
+    // Acquire admission lock (so that no connection can be inserted with \
+    //   the new identifier while renaming)
+    let _admission = state.admission.lock().unwrap();
+
+    // Assert that new connection identifier does not already exist
+    if state.connections.contains_key(new_id) {
+        error!(
+            target: &log_target(old_id),
+            "Connection #{} rename request failed, as connection already exists: #{}",
+            old_id, new_id
+        );
+
+        return Err(RenameError::ConnectionAlreadyExists);
+    }
+
+    // Move existing connection to its new identifier
+    // Notice: the underlying stream is left untouched, only the identifier \
+    //   used by tasks for logs and events gets swapped.
+    if let Some((_, connection)) = state.connections.remove(old_id) {
+        *connection.shared.id.write().unwrap() = new_id.to_owned();
+
+        // Move log level and label overrides (if any)
+        if let Some((_, level)) = LOG_LEVELS.remove(old_id) {
+            LOG_LEVELS.insert(new_id.to_owned(), level);
+        }
+
+        if let Some((_, label)) = LOG_LABELS.remove(old_id) {
+            LOG_LABELS.insert(new_id.to_owned(), label);
+        }
+
+        state.connections.insert(new_id.to_owned(), connection);
+
+        info!(
+            target: &log_target(old_id),
+            "Connection #{} rename request complete to: #{}",
+            old_id, new_id
+        );
+
+        Ok(())
+    } else {
+        error!(
+            target: &log_target(old_id),
+            "Connection #{} rename request failed, as connection does not exist",
+            old_id
+        );
+
+        Err(RenameError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn validate_stanza(stanza: String, strict: Option<bool>) -> Result<(), ValidateError> {
+    debug!("Stanza validation requested");
+
+    // Notice: this parses the stanza the same way the send command does, \
+    //   meaning that a stanza that validates there will not be rejected \
+    //   upon sending (no connection is required).
+    let stanza_root: Element = stanza
+        .parse()
+        .map_err(|err: minidom::Error| ValidateError::CannotParse(err.to_string()))?;
+
+    // Also validate stanza structure? (opt-in, as done upon sending)
+    if strict.unwrap_or(false) {
+        check_stanza_structure(&stanza_root).map_err(ValidateError::InvalidStanza)?;
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn send<R: Runtime>(
+    window: Window<R>,
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+    stanza: String,
+    token: Option<String>,
+    options: Option<SendOptions>,
+) -> Result<(), SendError> {
+    debug!(target: &log_target(id), "Connection #{} send requested (will send XMPP stanza)", id);
+
+    let options = options.unwrap_or_default();
+
+    // Notice: the connection reference gets released before waiting for the \
+    //   stanza to be flushed, as holding it would block its map shard.
+    let flush_waiter = if let Some(connection) = state.connections.get(id) {
+        let mut stanza_root = prepare_stanza(&window, id, &connection.shared, &stanza)?;
+        let mut stanza_size = stanza.len();
+
+        // Wait for stanza reflection? (opt-in)
+        // Notice: this is opt-in, since not all sent stanzas get reflected \
+        //   back, in which case their correlation eventually gets evicted.
+        if options.reflection {
+            match (stanza_root.attr("id"), token.as_deref()) {
+                (Some(stanza_id), Some(token)) => {
+                    connection.shared.expect_reflection(&stanza_root, stanza_id, token)
+                }
+                _ => return Err(SendError::CannotCorrelate),
+            }
+        }
+
+        // Request a delivery receipt? (opt-in)
+        // Notice: the request element is injected there if missing, and the \
+        //   receipt gets correlated back to the message identifier.
+        if options.receipt {
+            let message_id = stanza_root
+                .attr("id")
+                .filter(|_| stanza_root.is("message", NS_CLIENT))
+                .ok_or(SendError::CannotRequestReceipt)?
+                .to_string();
+
+            if !stanza_root.has_child("request", NS_RECEIPTS) {
+                stanza_root.append_child(Element::builder("request", NS_RECEIPTS).build());
+
+                stanza_size = String::from(&stanza_root).len();
+            }
+
+            connection.shared.expect_receipt(&message_id);
+        }
+
+        let (flushed, flush_waiter) = match options.flush_timeout {
+            Some(flush_timeout) => {
+                let (flushed, flush_waiter) = oneshot::channel();
+
+                (
+                    Some(Arc::new(Mutex::new(Some(flushed)))),
+                    Some((flush_waiter, flush_timeout)),
+                )
+            }
+            None => (None, None),
+        };
+
+        // Notice: broadcast presences sent as raw stanzas also get remembered \
+        //   (once queued), as they replace the presence that was last set.
+        let presence = is_broadcast_presence(&stanza_root).then(|| stanza_root.clone());
+
+        queue_stanza(&window, id, &connection, stanza_root, stanza_size, token, flushed)?;
+
+        if let Some(presence) = presence {
+            connection.shared.remember_presence(presence);
+        }
+
+        flush_waiter
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} send request failed, as connection does not exist",
+            id
+        );
+
+        return Err(SendError::ConnectionDoesNotExist);
+    };
+
+    // Wait for stanza to be flushed? (if flush timeout is set)
+    // Notice: the stanza might still get sent later on, even if this timed \
+    //   out, since it cannot be pulled back from the send queue.
+    if let Some((flush_waiter, flush_timeout)) = flush_waiter {
+        match timeout(Duration::from_millis(flush_timeout), flush_waiter).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(_)) => return Err(SendError::CannotWrite),
+            Err(_) => {
+                warn!(
+                    target: &log_target(id),
+                    "Connection #{} send request timed out waiting for stanza to be flushed",
+                    id
+                );
+
+                return Err(SendError::FlushTimeout);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn send_batch<R: Runtime>(
+    window: Window<R>,
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+    stanzas: Vec<String>,
+) -> Result<(), SendError> {
+    debug!(
+        target: &log_target(id),
+        "Connection #{} send batch requested (will send {} XMPP stanzas)",
+        id,
+        stanzas.len()
+    );
+
+    if let Some(connection) = state.connections.get(id) {
+        // Parse all stanzas first (all-or-nothing)
+        // Notice: none of the stanzas get sent if any of them is invalid, \
+        //   since the implementor might rely on the stanzas being in order.
+        let mut stanza_roots = Vec::with_capacity(stanzas.len());
+
+        for (index, stanza) in stanzas.iter().enumerate() {
+            let stanza_root = parse_stanza(id, &connection.shared, stanza).map_err(|err| match err {
+                SendError::CannotParse => SendError::CannotParseAt(index),
+                err => err,
+            })?;
+
+            stanza_roots.push((stanza_root, stanza.len()));
+        }
+
+        // Push all stanzas in order (holding the queueing lock)
+        // Notice: duplicate identifiers are only tracked for stanzas that \
+        //   get pushed. If pushing fails midway, the implementor gets told \
+        //   how many stanzas were queued, so that it can retry the rest.
+        let _queueing = connection.shared.queueing.lock().unwrap();
+
+        for (queued, (stanza_root, stanza_size)) in stanza_roots.into_iter().enumerate() {
+            track_stanza_id(&window, id, &connection.shared, &stanza_root);
+
+            push_stanza(&window, id, &connection, stanza_root, stanza_size, None, None).map_err(
+                |err| SendError::PartiallyQueued {
+                    queued,
+                    error: Box::new(err),
+                },
+            )?;
+        }
+
+        Ok(())
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} send batch failed, as connection does not exist",
+            id
+        );
+
+        Err(SendError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn set_presence<R: Runtime>(
+    window: Window<R>,
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+    show: Option<PresenceShow>,
+    status: Option<String>,
+    priority: Option<i8>,
+    unavailable: Option<bool>,
+) -> Result<(), SendError> {
+    debug!(target: &log_target(id), "Connection #{} presence set requested", id);
+
+    if let Some(connection) = state.connections.get(id) {
+        // Notice: an unavailable presence lets the implementor go offline \
+        //   without having to disconnect.
+        let unavailable = unavailable.unwrap_or(false);
+        let presence = make_presence(show, status.as_deref(), priority, unavailable);
+        let size = String::from(&presence).len();
+
+        // Notice: the presence only gets remembered once queued, so that a \
+        //   presence that could not be sent never gets restored.
+        queue_stanza(&window, id, &connection, presence.clone(), size, None, None)?;
+
+        connection.shared.remember_presence(presence);
+
+        Ok(())
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} presence set failed, as connection does not exist",
+            id
+        );
+
+        Err(SendError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn appear_offline<R: Runtime>(
+    window: Window<R>,
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+) -> Result<(), SendError> {
+    debug!(target: &log_target(id), "Connection #{} appear offline requested", id);
+
+    if let Some(connection) = state.connections.get(id) {
+        // Notice: this tells contacts that we went offline, while keeping \
+        //   the stream open (eg. for background sync). The last presence is \
+        //   kept as-is, so that it can be restored when appearing online.
+        let presence = make_presence(None, None, None, true);
+        let size = String::from(&presence).len();
+
+        connection.shared.appearing_offline.store(true, Ordering::SeqCst);
+
+        queue_stanza(&window, id, &connection, presence, size, None, None)
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} appear offline failed, as connection does not exist",
+            id
+        );
+
+        Err(SendError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn appear_online<R: Runtime>(
+    window: Window<R>,
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+) -> Result<(), SendError> {
+    debug!(target: &log_target(id), "Connection #{} appear online requested", id);
+
+    if let Some(connection) = state.connections.get(id) {
+        let shared = &connection.shared;
+
+        // Restore last presence (or fall back to the initial presence, if any)
+        let initial_presence = shared.options.initial_presence.as_ref();
+
+        let presence = shared
+            .last_presence
+            .lock()
+            .unwrap()
+            .clone()
+            .or_else(|| initial_presence.map(make_initial_presence))
+            .unwrap_or_else(|| make_presence(None, None, None, false));
+        let size = String::from(&presence).len();
+
+        shared.appearing_offline.store(false, Ordering::SeqCst);
+
+        queue_stanza(&window, id, &connection, presence, size, None, None)
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} appear online failed, as connection does not exist",
+            id
+        );
+
+        Err(SendError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn set_receive_filter(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+    expression: Option<String>,
+) -> Result<(), FilterError> {
+    debug!(target: &log_target(id), "Connection #{} receive filter set requested", id);
+
+    if let Some(connection) = state.connections.get(id) {
+        // Parse filter expression (or clear filter)
+        let receive_filter = expression
+            .as_deref()
+            .map(|expression| expression.parse::<StanzaFilter>())
+            .transpose()
+            .map_err(|err| {
+                warn!(
+                    target: &log_target(id),
+                    "Connection #{} receive filter set failed, because: {}",
+                    id, err
+                );
+
+                FilterError::InvalidExpression(err.to_string())
+            })?;
+
+        *connection.shared.receive_filter.write().unwrap() = receive_filter;
+
+        debug!(target: &log_target(id), "Connection #{} receive filter set request complete", id);
+
+        Ok(())
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} receive filter set failed, as connection does not exist",
+            id
+        );
+
+        Err(FilterError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn mute_receive(id: &str, state: State<'_, ConnectionClientState>) -> Result<(), QueryError> {
+    info!(target: &log_target(id), "Connection #{} mute receive requested", id);
+
+    if let Some(connection) = state.connections.get(id) {
+        // Notice: stanzas keep being received, they only get buffered until \
+        //   the connection gets unmuted.
+        connection.shared.muted.store(true, Ordering::SeqCst);
+
+        Ok(())
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} mute receive request failed, as connection does not exist",
+            id
+        );
+
+        Err(QueryError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn unmute_receive<R: Runtime>(
+    window: Window<R>,
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+) -> Result<(), QueryError> {
+    info!(target: &log_target(id), "Connection #{} unmute receive requested", id);
+
+    if let Some(connection) = state.connections.get(id) {
+        let shared = &connection.shared;
+
+        if shared.options.receive_buffer.is_some() {
+            // Resume the receive poller (which flushes buffered stanzas)
+            shared.muted.store(false, Ordering::SeqCst);
+            shared.receive_queued.notify_one();
+        } else {
+            // Flush buffered stanzas (in order), then resume emitting
+            let _flushing = shared.receive_flushing.lock().unwrap();
+
+            while let Some(queued) = shared.next_unmuted() {
+                emit_queued_stanza(&window, shared, queued);
+            }
+        }
+
+        Ok(())
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} unmute receive request failed, as connection does not exist",
+            id
+        );
+
+        Err(QueryError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn set_connection_log_level(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+    level: Option<String>,
+) -> Result<(), LogLevelError> {
+    info!(target: &log_target(id), "Connection #{} log level change requested", id);
+
+    if !state.connections.contains_key(id) {
+        error!(
+            target: &log_target(id),
+            "Connection #{} log level change failed, as connection does not exist",
+            id
+        );
+
+        return Err(LogLevelError::ConnectionDoesNotExist);
+    }
+
+    // Set or reset log level override
+    // Notice: this applies to all logs emitted for the connection, which \
+    //   are all logged on the connection log target.
+    if let Some(level) = level {
+        let level = level
+            .parse::<LevelFilter>()
+            .or(Err(LogLevelError::InvalidLevel))?;
+
+        LOG_LEVELS.insert(id.to_string(), level);
+    } else {
+        LOG_LEVELS.remove(id);
+    }
+
+    logger::refresh_max_level();
+
+    info!(target: &log_target(id), "Connection #{} log level change complete", id);
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn time_to_timeout(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+) -> Result<u64, QueryError> {
+    debug!(target: &log_target(id), "Connection #{} time to timeout requested", id);
+
+    if let Some(connection) = state.connections.get(id) {
+        Ok(connection.shared.time_to_timeout().as_millis() as u64)
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} time to timeout request failed, as connection does not exist",
+            id
+        );
+
+        Err(QueryError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn get_state(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+) -> Result<ConnectionState, QueryError> {
+    debug!(target: &log_target(id), "Connection #{} state requested", id);
+
+    // Notice: this lets the implementor recover the current state of its \
+    //   connections (eg. after a webview reload, as events got missed).
+    if let Some(connection) = state.connections.get(id) {
+        Ok(connection.shared.current_state())
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} state request failed, as connection does not exist",
+            id
+        );
+
+        Err(QueryError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn get_stream_features(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+) -> Result<StreamFeatures, QueryError> {
+    debug!(target: &log_target(id), "Connection #{} stream features requested", id);
+
+    if let Some(connection) = state.connections.get(id) {
+        let shared = &connection.shared;
+
+        // Notice: features reflect the last stream negotiated by the \
+        //   connection, as advertised by the server once TLS is established \
+        //   (ie. before authentication, since the underlying 'tokio-xmpp' \
+        //   client does not expose the features of the bound stream). Stream \
+        //   management is only advertised after authentication, thus it gets \
+        //   reported from whether the server enabled it instead.
+        let probe = shared
+            .report
+            .stream_features()
+            .as_ref()
+            .map(make_server_probe)
+            .unwrap_or_default();
+
+        let stream_management = shared.stream_management.lock().unwrap();
+
+        Ok(StreamFeatures {
+            tls_version: shared.report.tls_version(),
+            sasl_mechanisms: probe.sasl_mechanisms,
+            compression_methods: probe.compression_methods,
+            stream_management: stream_management.inbound,
+            stream_resumption: stream_management.resumption.clone(),
+            registration: probe.registration,
+            carbons: shared.carbons_enabled.load(Ordering::SeqCst),
+            bound_jid: shared.bound_jid.lock().unwrap().clone(),
+        })
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} stream features request failed, as connection does not exist",
+            id
+        );
+
+        Err(QueryError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn get_metrics(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+) -> Result<ConnectionMetrics, QueryError> {
+    debug!(target: &log_target(id), "Connection #{} metrics requested", id);
+
+    if let Some(connection) = state.connections.get(id) {
+        let shared = &connection.shared;
+        let counters = &shared.counters;
+
+        // Notice: the connected time only accounts for the current session, \
+        //   meaning that it restarts from zero upon reconnecting.
+        let bound_at = *shared.bound_at.lock().unwrap();
+
+        let connected_for = bound_at
+            .filter(|_| shared.current_state().is_connected())
+            .map(|bound_at| bound_at.elapsed().as_millis() as u64);
+
+        Ok(ConnectionMetrics {
+            stanzas_sent: counters.packets_sent.load(Ordering::Relaxed),
+            stanzas_received: counters.stanzas_received.load(Ordering::Relaxed),
+            bytes_sent: counters.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: counters.bytes_received.load(Ordering::Relaxed),
+            connected_for,
+            reconnects: shared.reconnects.load(Ordering::Relaxed),
+        })
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} metrics request failed, as connection does not exist",
+            id
+        );
+
+        Err(QueryError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn set_default_read_timeout(state: State<'_, ConnectionClientState>, timeout: Option<u64>) {
+    info!("Connection default read timeout change requested: {:?}", timeout);
+
+    // Set or reset default read timeout
+    // Notice: this only applies to connections created afterwards, and \
+    //   only if their connect request does not provide its own timeout.
+    *state.default_read_timeout.write().unwrap() = timeout;
+}
+
+#[tauri::command]
+pub fn get_global_stats(state: State<'_, ConnectionClientState>) -> Result<GlobalStats, ()> {
+    debug!("Connection global stats requested");
+
+    let mut stats = GlobalStats::default();
+
+    // Aggregate counters and states from all connections
+    // Notice: the connections map is sharded, thus this is not an atomic \
+    //   snapshot (connections might come and go while iterating).
+    for connection in state.connections.iter() {
+        let counters = &connection.shared.counters;
+
+        stats.connections += 1;
+        stats.bytes_sent += counters.bytes_sent.load(Ordering::Relaxed);
+        stats.bytes_received += counters.bytes_received.load(Ordering::Relaxed);
+        stats.stanzas_sent += counters.packets_sent.load(Ordering::Relaxed);
+        stats.stanzas_received += counters.stanzas_received.load(Ordering::Relaxed);
+        stats.stanzas_filtered += counters.stanzas_filtered.load(Ordering::Relaxed);
+
+        *stats
+            .states
+            .entry(connection.shared.current_state())
+            .or_default() += 1;
+    }
+
+    Ok(stats)
+}
+
+#[tauri::command]
+pub fn dump_diagnostics(
+    state: State<'_, ConnectionClientState>,
+) -> Result<Vec<ConnectionDiagnostics>, ()> {
+    debug!("Connection diagnostics dump requested");
+
+    // Snapshot everything known about each connection
+    // Notice: connection options do not hold any credential (the password \
+    //   is only ever held by the client), thus they can be dumped as-is.
+    let mut diagnostics: Vec<ConnectionDiagnostics> = state
+        .connections
+        .iter()
+        .map(|connection| {
+            let shared = &connection.shared;
+            let counters = &shared.counters;
+
+            let last_received_ago = shared
+                .last_received_at
+                .lock()
+                .unwrap()
+                .map(|last_received_at| last_received_at.elapsed().as_millis() as u64);
 
-                window
-                    .emit(
-                        EVENT_RECEIVE,
-                        EventConnectionReceive {
-                            id,
-                            stanza: &stanza_xml,
-                        },
-                    )
-                    .unwrap();
+            ConnectionDiagnostics {
+                id: connection.key().to_owned(),
+                jid: connection.jid.to_string(),
+                state: shared.current_state(),
+                error: *shared.error_kind.lock().unwrap(),
+                reconnect_attempts: shared.reconnect_attempts.load(Ordering::SeqCst),
+                last_received_ago,
+                time_to_timeout: shared.time_to_timeout().as_millis() as u64,
+                counters: CountersDiagnostics {
+                    bytes_sent: counters.bytes_sent.load(Ordering::Relaxed),
+                    bytes_received: counters.bytes_received.load(Ordering::Relaxed),
+                    stanzas_received: counters.stanzas_received.load(Ordering::Relaxed),
+                    stanzas_filtered: counters.stanzas_filtered.load(Ordering::Relaxed),
+                    stanzas_overflowed: counters.stanzas_overflowed.load(Ordering::Relaxed),
+                    packets_queued: counters.packets_queued.load(Ordering::Relaxed),
+                    packets_sent: counters.packets_sent.load(Ordering::Relaxed),
+                },
+                undelivered_packets: shared.undelivered_packets(),
+                receive_queue_size: shared.receive_queue.lock().unwrap().len(),
+                pending_iqs: shared.iq_waiters.lock().unwrap().len(),
+                tasks: TasksDiagnostics {
+                    read: !connection.read_handle.is_finished(),
+                    write: !connection.write_handle.is_finished(),
+                    bandwidth: connection
+                        .bandwidth_handle
+                        .as_ref()
+                        .map(|handle| !handle.is_finished()),
+                    receive: connection
+                        .receive_handle
+                        .as_ref()
+                        .map(|handle| !handle.is_finished()),
+                    ping: connection.ping_handle.as_ref().map(|handle| !handle.is_finished()),
+                },
+                options: serde_json::to_value(&shared.options).unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    diagnostics.sort_by(|diagnostic, other| diagnostic.id.cmp(&other.id));
+
+    Ok(diagnostics)
+}
+
+#[tauri::command]
+pub fn list_connections(
+    state: State<'_, ConnectionClientState>,
+) -> Result<Vec<ConnectionSummary>, ()> {
+    debug!("Connection list requested");
+
+    // Notice: this lets the implementor reconcile its view of connections \
+    //   (eg. after a webview crash), and detect leaked connections.
+    let mut summaries: Vec<ConnectionSummary> = state
+        .connections
+        .iter()
+        .map(|connection| ConnectionSummary {
+            id: connection.key().to_owned(),
+            jid: connection.jid.to_string(),
+            state: connection.shared.current_state(),
+        })
+        .collect();
+
+    summaries.sort_by(|summary, other| summary.id.cmp(&other.id));
+
+    Ok(summaries)
+}
+
+#[tauri::command]
+pub fn list_connections_by_state(
+    state: State<'_, ConnectionClientState>,
+    state_filter: ConnectionState,
+) -> Result<Vec<ConnectionEntry>, ()> {
+    debug!("Connection list by state requested: {:?}", state_filter);
+
+    let mut entries: Vec<ConnectionEntry> = state
+        .connections
+        .iter()
+        .filter(|connection| connection.shared.current_state() == state_filter)
+        .map(|connection| ConnectionEntry {
+            id: connection.key().to_owned(),
+            jid: connection.jid.to_string(),
+        })
+        .collect();
+
+    entries.sort_by(|entry, other| entry.id.cmp(&other.id));
+
+    Ok(entries)
+}
+
+#[tauri::command]
+pub fn get_recent_stanzas(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+    count: Option<usize>,
+) -> Result<Vec<String>, QueryError> {
+    debug!(target: &log_target(id), "Connection #{} recent stanzas requested", id);
+
+    if let Some(connection) = state.connections.get(id) {
+        let recent_stanzas = connection.shared.recent_stanzas.lock().unwrap();
+
+        // Acquire the N most recent stanzas (ordered from oldest to newest)
+        let count = count.unwrap_or(recent_stanzas.len()).min(recent_stanzas.len());
+
+        Ok(recent_stanzas
+            .iter()
+            .skip(recent_stanzas.len() - count)
+            .cloned()
+            .collect())
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} recent stanzas request failed, as connection does not exist",
+            id
+        );
+
+        Err(QueryError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn get_error_history(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+) -> Result<Vec<ErrorRecord>, QueryError> {
+    debug!(target: &log_target(id), "Connection #{} error history requested", id);
+
+    if let Some(connection) = state.connections.get(id) {
+        // Acquire all remembered errors (ordered from oldest to newest)
+        Ok(connection
+            .shared
+            .error_history
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect())
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} error history request failed, as connection does not exist",
+            id
+        );
+
+        Err(QueryError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn clear_error_history(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+) -> Result<(), QueryError> {
+    debug!(target: &log_target(id), "Connection #{} error history clear requested", id);
+
+    if let Some(connection) = state.connections.get(id) {
+        connection.shared.error_history.lock().unwrap().clear();
+
+        Ok(())
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} error history clear failed, as connection does not exist",
+            id
+        );
+
+        Err(QueryError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub fn list_pending_iqs(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+) -> Result<Vec<String>, QueryError> {
+    debug!(target: &log_target(id), "Connection #{} pending requests requested", id);
+
+    if let Some(connection) = state.connections.get(id) {
+        let mut iq_ids: Vec<String> = connection
+            .shared
+            .iq_waiters
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+
+        iq_ids.sort();
+
+        Ok(iq_ids)
+    } else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} pending requests request failed, as connection does not exist",
+            id
+        );
+
+        Err(QueryError::ConnectionDoesNotExist)
+    }
+}
+
+#[tauri::command]
+pub async fn ping_all(
+    state: State<'_, ConnectionClientState>,
+    timeout: Option<u64>,
+) -> Result<HashMap<String, PingResult>, ()> {
+    debug!("Connection ping all requested");
+
+    let ping_timeout = Duration::from_millis(timeout.unwrap_or(PING_TIMEOUT_MILLISECONDS));
+
+    // Acquire all connections to ping
+    let targets = connection_targets(&state, |_| true);
+
+    // Ping all connections concurrently
+    let results = future::join_all(targets.into_iter().map(
+        |(id, domain, sender, shared)| async move {
+            let result = ping_connection(&sender, &shared, &domain, ping_timeout).await;
+
+            (id, result)
+        },
+    ))
+    .await;
+
+    debug!("Connection ping all request complete");
+
+    Ok(results.into_iter().collect())
+}
+
+#[tauri::command]
+pub async fn resume_all(
+    state: State<'_, ConnectionClientState>,
+    timeout: Option<u64>,
+) -> Result<HashMap<String, PingResult>, ()> {
+    info!("Connection resume all requested");
+
+    let ping_timeout = Duration::from_millis(timeout.unwrap_or(PING_TIMEOUT_MILLISECONDS));
+
+    // Acquire all connected connections to re-validate
+    // Notice: connections that are not connected are skipped, as they \
+    //   already are either reconnecting or disconnected.
+    let targets = connection_targets(&state, |shared| shared.current_state().is_connected());
+
+    // Ping all connections concurrently, marking unresponsive ones as stale
+    // Notice: a stale connection gets handled as if it timed out, meaning \
+    //   that it reconnects (if reconnection is enabled), or emits a timeout \
+    //   state otherwise (which lets the implementor connect it again).
+    let results = future::join_all(targets.into_iter().map(
+        |(id, domain, sender, shared)| async move {
+            let result = ping_connection(&sender, &shared, &domain, ping_timeout).await;
+
+            if result.error.is_some() {
+                warn!(
+                    target: &log_target(&id),
+                    "Connection #{} did not respond upon resuming, will reconnect",
+                    id
+                );
+
+                shared.stale.notify_one();
+            }
+
+            (id, result)
+        },
+    ))
+    .await;
+
+    info!("Connection resume all request complete");
+
+    Ok(results.into_iter().collect())
+}
+
+#[tauri::command]
+pub fn send_to_all<R: Runtime>(
+    window: Window<R>,
+    state: State<'_, ConnectionClientState>,
+    stanza: String,
+    overrides: Option<HashMap<String, String>>,
+) -> Result<HashMap<String, BroadcastResult>, SendError> {
+    debug!("Connection send to all requested (will broadcast XMPP stanza)");
+
+    let overrides = overrides.unwrap_or_default();
+
+    // Check that the stanza template parses (shared by all connections)
+    // Notice: a failure there aborts the whole broadcast, as it would fail \
+    //   on every connection otherwise.
+    stanza.parse::<Element>().or(Err(SendError::CannotParse))?;
+
+    let mut results = HashMap::new();
+
+    // Send stanza on all connected connections
+    // Notice: connections that are not connected are skipped, and a failure \
+    //   on a given connection does not prevent sending on the others.
+    for connection in state.connections.iter() {
+        if !connection.shared.current_state().is_connected() {
+            continue;
+        }
+
+        let id = connection.key();
+
+        // Use the connection-specific stanza? (if any)
+        // Notice: the stanza gets prepared for each connection, as stanza \
+        //   validation and duplicate identifiers tracking are per-connection.
+        let stanza = overrides.get(id).unwrap_or(&stanza);
+
+        let result = prepare_stanza(&window, id, &connection.shared, stanza).and_then(|root| {
+            queue_stanza(&window, id, &connection, root, stanza.len(), None, None)
+        });
+
+        if let Err(ref err) = result {
+            warn!(
+                target: &log_target(id),
+                "Connection #{} send to all failed on connection: {}",
+                id, err
+            );
+        }
+
+        results.insert(id.to_owned(), BroadcastResult { error: result.err() });
+    }
+
+    debug!("Connection send to all request complete");
+
+    Ok(results)
+}
+
+#[tauri::command]
+pub async fn send_iq(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+    stanza: String,
+    timeout: Option<u64>,
+) -> Result<String, SendIqError> {
+    debug!(target: &log_target(id), "Connection #{} IQ send requested", id);
+
+    let mut stanza_root: Element = stanza.parse().or(Err(SendIqError::CannotParse))?;
+
+    // Only requests expect a response (results and errors do not)
+    if !stanza_root.is("iq", NS_CLIENT)
+        || !matches!(stanza_root.attr("type"), Some("get") | Some("set"))
+    {
+        return Err(SendIqError::InvalidStanza("stanza is not an IQ get or set".to_string()));
+    }
+
+    // Assign a request identifier? (if none was set)
+    // Notice: the identifier is used to correlate the response, which then \
+    //   does not get emitted as a regular received stanza.
+    if stanza_root.attr("id").is_none() {
+        stanza_root.set_attr("id", make_iq_id());
+    }
+
+    // Acquire connection sender
+    let Some((sender, shared)) = connection_handles(&state, id) else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} IQ send failed, as connection does not exist",
+            id
+        );
+
+        return Err(SendIqError::ConnectionDoesNotExist);
+    };
+
+    let response = request_iq(
+        &sender,
+        &shared,
+        stanza_root,
+        Duration::from_millis(timeout.unwrap_or(IQ_TIMEOUT_MILLISECONDS)),
+    )
+    .await
+    .map_err(|err| {
+        warn!(target: &log_target(id), "Connection #{} IQ send failed, because: {}", id, err);
+
+        SendIqError::RequestFailed(err)
+    })?;
+
+    match response.attr("type") {
+        Some("error") => Err(SendIqError::ServerError(make_stanza_error_condition(&response))),
+        _ => {
+            debug!(target: &log_target(id), "Connection #{} IQ send complete (got result)", id);
+
+            Ok(String::from(&response))
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn set_carbons(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+    enabled: bool,
+    timeout: Option<u64>,
+) -> Result<(), CarbonsError> {
+    debug!(
+        target: &log_target(id),
+        "Connection #{} carbons change requested (enabled: {})",
+        id, enabled
+    );
+
+    // Acquire connection sender
+    let Some((sender, shared)) = connection_handles(&state, id) else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} carbons change failed, as connection does not exist",
+            id
+        );
+
+        return Err(CarbonsError::ConnectionDoesNotExist);
+    };
+
+    let request_timeout = Duration::from_millis(timeout.unwrap_or(CARBONS_TIMEOUT_MILLISECONDS));
+
+    // Assert that the server supports message carbons
+    let domain = shared.jid().map(|jid| jid.domain().to_string());
+
+    let disco_get = Element::builder("iq", NS_CLIENT)
+        .attr("type", "get")
+        .attr("id", make_iq_id())
+        .attr("to", domain)
+        .append(Element::builder("query", NS_DISCO_INFO).build())
+        .build();
+
+    let response = request_iq(&sender, &shared, disco_get, request_timeout)
+        .await
+        .map_err(CarbonsError::RequestFailed)?;
+
+    if !has_disco_feature(&response, NS_CARBONS) {
+        warn!(
+            target: &log_target(id),
+            "Connection #{} carbons change failed, as server does not support them",
+            id
+        );
+
+        return Err(CarbonsError::Unsupported);
+    }
+
+    // Enable or disable message carbons
+    // Notice: carbons are scoped to the stream, meaning that they do not \
+    //   get enabled again upon reconnecting (unless set in the options).
+    let carbons_set = Element::builder("iq", NS_CLIENT)
+        .attr("type", "set")
+        .attr("id", make_iq_id())
+        .append(Element::builder(if enabled { "enable" } else { "disable" }, NS_CARBONS).build())
+        .build();
+
+    let response = request_iq(&sender, &shared, carbons_set, request_timeout)
+        .await
+        .map_err(CarbonsError::RequestFailed)?;
+
+    match response.attr("type") {
+        Some("error") => Err(CarbonsError::ServerError(make_stanza_error_condition(&response))),
+        _ => {
+            shared.carbons_enabled.store(enabled, Ordering::SeqCst);
+
+            debug!(
+                target: &log_target(id),
+                "Connection #{} carbons change complete (enabled: {})",
+                id, enabled
+            );
+
+            Ok(())
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn fetch_roster(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+    timeout: Option<u64>,
+) -> Result<Vec<RosterItem>, RosterError> {
+    debug!(target: &log_target(id), "Connection #{} roster fetch requested", id);
+
+    // Acquire connection sender
+    let Some((sender, shared)) = connection_handles(&state, id) else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} roster fetch failed, as connection does not exist",
+            id
+        );
+
+        return Err(RosterError::ConnectionDoesNotExist);
+    };
+
+    let roster_get = Element::builder("iq", NS_CLIENT)
+        .attr("type", "get")
+        .attr("id", make_iq_id())
+        .append(Element::builder("query", NS_ROSTER).build())
+        .build();
+
+    let response = request_iq(
+        &sender,
+        &shared,
+        roster_get,
+        Duration::from_millis(timeout.unwrap_or(ROSTER_TIMEOUT_MILLISECONDS)),
+    )
+    .await
+    .map_err(RosterError::RequestFailed)?;
+
+    let items = make_roster_items(&response).map_err(|err| {
+        warn!(target: &log_target(id), "Connection #{} roster fetch failed, because: {}", id, err);
+
+        err
+    })?;
+
+    debug!(
+        target: &log_target(id),
+        "Connection #{} roster fetch complete (with {} items)",
+        id,
+        items.len()
+    );
+
+    Ok(items)
+}
+
+#[tauri::command]
+pub async fn mam_query(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+    query: Option<MamQuery>,
+    timeout: Option<u64>,
+) -> Result<MamPage, MamError> {
+    debug!(target: &log_target(id), "Connection #{} archive query requested", id);
+
+    // Acquire connection sender
+    let Some((sender, shared)) = connection_handles(&state, id) else {
+        error!(
+            target: &log_target(id),
+            "Connection #{} archive query failed, as connection does not exist",
+            id
+        );
+
+        return Err(MamError::ConnectionDoesNotExist);
+    };
+
+    let query = query.unwrap_or_default();
+    let query_id = make_iq_id();
+    let account = shared.jid().map(|jid| jid.to_string()).unwrap_or_default();
+
+    // Register results collector (before requesting, so that no result can \
+    //   race the registration)
+    // Notice: archived messages are all received before the final response, \
+    //   thus the collector holds the full page once the request completes.
+    shared.register_mam(&query_id, query.to.as_deref().unwrap_or(&account));
+
+    let response = request_iq(
+        &sender,
+        &shared,
+        make_mam_query(&query, &query_id),
+        Duration::from_millis(timeout.unwrap_or(MAM_TIMEOUT_MILLISECONDS)),
+    )
+    .await;
+
+    let messages = shared.unregister_mam(&query_id);
+
+    let response = response.map_err(MamError::RequestFailed)?;
+
+    let page = make_mam_page(&response, messages).map_err(|err| {
+        warn!(target: &log_target(id), "Connection #{} archive query failed, because: {}", id, err);
+
+        err
+    })?;
+
+    debug!(
+        target: &log_target(id),
+        "Connection #{} archive query complete (with {} messages)",
+        id,
+        page.messages.len()
+    );
+
+    Ok(page)
+}
+
+#[tauri::command]
+pub async fn probe_server(
+    state: State<'_, ConnectionClientState>,
+    domain: &str,
+    server_host: Option<String>,
+    server_port: Option<u16>,
+) -> Result<ServerProbe, ProbeError> {
+    info!("Server probe requested on domain: {}", domain);
+
+    let jid = Jid::new(domain).or(Err(ProbeError::InvalidDomain))?;
+
+    // Open a stream up to the features stage (post-TLS)
+    // Notice: this does not authenticate, meaning that features only \
+    //   advertised after authentication (eg. stream management on most \
+    //   servers) might not be reported there.
+    let report = ConnectorReport::default();
+    let mut connector = ConnectionConnector::new(report.clone())
+        .with_tor_proxy(state.settings.tor_proxy.clone());
+
+    if let Some(server_host) = server_host {
+        connector = connector.with_endpoint(server_host, server_port);
+    }
+
+    // Notice: the whole probe is bounded, as a server might accept the \
+    //   connection but never send its stream features.
+    let connected = timeout(
+        Duration::from_millis(SERVER_TIMEOUT_MILLISECONDS),
+        connector.connect(&jid, NS_CLIENT),
+    )
+    .await
+    .map_err(|_| {
+        warn!("Server probe on domain: {} timed out", domain);
+
+        ProbeError::Timeout
+    })?;
+
+    let mut stream = connected.map_err(|err| {
+        warn!(
+            "Server probe on domain: {} failed, because: {}",
+            domain, err
+        );
+
+        if report.failure() == Some(ConnectorStage::Resolve) {
+            ProbeError::ResolutionFailure
+        } else {
+            ProbeError::ConnectionError
+        }
+    })?;
+
+    let probe = make_server_probe(&stream.stream_features.0);
+
+    // Close the stream (we are done probing)
+    stream.send(Packet::StreamEnd).await.ok();
+
+    info!("Server probe on domain: {} complete", domain);
+
+    Ok(probe)
+}
+
+// Important: this command is meant for test builds only, and is thus \
+//   compiled out unless the 'test-commands' feature is enabled.
+#[cfg(feature = "test-commands")]
+#[tauri::command]
+pub fn test_inject_disconnect(
+    id: &str,
+    state: State<'_, ConnectionClientState>,
+    disconnect_state: ConnectionState,
+) -> Result<(), InjectError> {
+    warn!(
+        target: &log_target(id),
+        "Connection #{} synthetic disconnect requested (with state: {:?})",
+        id, disconnect_state
+    );
+
+    if matches!(
+        disconnect_state,
+        ConnectionState::Secured
+            | ConnectionState::Connected
+            | ConnectionState::Resumed
+            | ConnectionState::Reconnecting
+    ) {
+        return Err(InjectError::InvalidState);
+    }
+
+    if let Some(connection) = state.connections.get(id) {
+        // Hand over the state to the read poller, then wake it up
+        // Notice: the read poller then aborts as if the connection was lost \
+        //   (including reconnecting, if enabled and the state is recoverable).
+        *connection.shared.injected_state.lock().unwrap() = Some(disconnect_state);
+
+        connection.shared.stale.notify_one();
 
-                // Continue
-                None
-            }
-        }
+        Ok(())
     } else {
-        // Abort here (no more events)
-        Some(Ok(()))
+        Err(InjectError::ConnectionDoesNotExist)
     }
 }
 
 /**************************************************************************
- * COMMANDS
+ * PROVIDERS
  * ************************************************************************* */
 
-#[tauri::command]
-pub fn connect<R: Runtime>(
-    window: Window<R>,
-    state: State<'_, ConnectionClientState>,
-    id: &str,
-    jid: &str,
-    password: &str,
-    timeout: Option<u64>,
-) -> Result<(), ConnectError> {
-    info!("Connection #{} connect requested on JID: {}", id, jid);
+pub fn provide<R: Runtime>(settings: ConnectionSettings) -> TauriPlugin<R> {
+    Builder::new("connection")
+        .invoke_handler(tauri::generate_handler![
+            connect,
+            disconnect,
+            disconnect_all,
+            drain,
+            destroy,
+            rename_connection,
+            validate_stanza,
+            send,
+            send_batch,
+            set_presence,
+            appear_offline,
+            appear_online,
+            set_receive_filter,
+            mute_receive,
+            unmute_receive,
+            set_connection_log_level,
+            time_to_timeout,
+            get_state,
+            get_stream_features,
+            get_metrics,
+            set_default_read_timeout,
+            get_global_stats,
+            dump_diagnostics,
+            list_connections,
+            list_connections_by_state,
+            get_recent_stanzas,
+            get_error_history,
+            clear_error_history,
+            list_pending_iqs,
+            ping_all,
+            resume_all,
+            send_to_all,
+            send_iq,
+            set_carbons,
+            fetch_roster,
+            mam_query,
+            probe_server,
+            #[cfg(feature = "test-commands")]
+            test_inject_disconnect
+        ])
+        .setup(move |app_handle, _| {
+            // Spawn connection tasks on a dedicated runtime? (if configured)
+            // Notice: this isolates XMPP I/O from other tasks running on the \
+            //   application runtime, falling back to the latter upon failure.
+            let runtime = settings.runtime.as_ref().and_then(make_dedicated_runtime);
+            let auth_failures = Arc::new(AuthFailureTracker::new(settings.auth_cooldown));
+            let default_read_timeout = RwLock::new(settings.read_timeout);
 
-    // Parse JID
-    let jid_full = FullJid::new(jid).or(Err(ConnectError::InvalidJid))?;
-    let jid_bare = jid_full.to_bare();
+            app_handle.manage(ConnectionClientState {
+                settings,
+                runtime,
+                auth_failures,
+                default_read_timeout,
+                ..Default::default()
+            });
 
-    // Assert that connection identifier does not already exist
-    if state.connections.read().unwrap().contains_key(id) {
-        return Err(ConnectError::ConnectionAlreadyExists);
-    }
+            // Grant test commands to the main window? (test builds only)
+            // Notice: this is not part of the default capability, as test \
+            //   commands must never be reachable from production builds.
+            #[cfg(feature = "test-commands")]
+            app_handle.add_capability(
+                tauri::ipc::CapabilityBuilder::new("test-commands")
+                    .window("main")
+                    .permission("connection:allow-test-inject-disconnect"),
+            )?;
 
-    // Assert that another connection with this JID does not already exist in \
-    //   the global state. This prevents connection manager mis-uses where the \
-    //   implementor client would request multiple parallel connections on the \
-    //   same JID.
-    {
-        // Scan all connections in the state
-        let state_connections = state.connections.read().unwrap();
+            Ok(())
+        })
+        .on_event(|app_handle, event| {
+            // Drain all connections upon exit (ie. disconnect cleanly)
+            if let RunEvent::Exit = event {
+                let state = app_handle.state::<ConnectionClientState>();
 
-        for (connection_id, connection) in (&*state_connections).into_iter() {
-            // Found another active connection in the state on the same JID?
-            if jid_bare == connection.jid {
-                error!(
-                    "Connection #{} connect request found to conflict with: #{}",
-                    id, connection_id
-                );
+                let connections = take_connections(&state);
+                let drain_timeout = state.settings.shutdown_drain_timeout();
 
-                return Err(ConnectError::AnotherConnectionBound);
+                // Important: the exit hook runs from a thread that is already \
+                //   driven by the Tokio runtime, so blocking on the drain from \
+                //   there would panic. Spawn it instead, and wait for it over a \
+                //   plain channel (up to the drain deadline).
+                let (drained_sender, drained_receiver) = sync_channel(1);
+
+                tauri::async_runtime::spawn(async move {
+                    drain_connections(connections, drain_timeout).await;
+
+                    drained_sender.send(()).ok();
+                });
+
+                if drained_receiver
+                    .recv_timeout(
+                        drain_timeout + Duration::from_millis(SHUTDOWN_DRAIN_MARGIN_MILLISECONDS),
+                    )
+                    .is_err()
+                {
+                    warn!("Connections drain did not complete before exit, giving up");
+                }
             }
-        }
-    };
+        })
+        .build()
+}
 
-    // Create new client
-    let mut client = Client::new(jid_full, password);
+/**************************************************************************
+ * TESTS
+ * ************************************************************************* */
 
-    // Connections are single-use only
-    client.set_reconnect(false);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
 
-    // Split client into RX (for writer) and TX (for reader)
-    let (tx, rx) = mpsc::unbounded_channel();
-    let (writer, reader) = client.split();
+    #[tokio::test]
+    async fn test_retired_connection_does_not_emit() {
+        let state: DashMap<String, Arc<ConnectionShared>> = DashMap::new();
+        let emitted = Arc::new(Mutex::new(Vec::new()));
 
-    // Spawn all tasks
-    let write_handle = {
-        let id = id.to_owned();
+        // Destroy and re-connect the same identifier in a tight loop, while \
+        //   tasks from the destroyed connection are still emitting
+        for generation in 0..200 {
+            let shared = Arc::new(ConnectionShared::default());
 
-        task::spawn(async move {
-            info!("Connection #{} write poller has started", id);
+            state.insert("connection".to_string(), shared.clone());
 
-            // Poll for output events
-            if let Err(err) = poll_output_events(&id, writer, rx).await {
-                warn!(
-                    "Connection #{} write poller terminated with error: {}",
-                    id, err
-                );
-            } else {
-                info!("Connection #{} write poller was stopped", id);
+            let emitter = {
+                let (shared, emitted) = (shared.clone(), emitted.clone());
+
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        shared.while_live(|| emitted.lock().unwrap().push(Some(generation)));
+                    }
+                })
+            };
+
+            if let Some((_, shared)) = state.remove("connection") {
+                shared.retire();
+                shared.settle_emissions().await;
             }
-        })
-    };
 
-    let read_handle = {
-        let id = id.to_owned();
-        let read_timeout = Duration::from_millis(timeout.unwrap_or(READ_TIMEOUT_MILLISECONDS));
+            // Mark destroyed (any later event is from the next connection)
+            emitted.lock().unwrap().push(None);
 
-        task::spawn(async move {
-            info!(
-                "Connection #{} read poller has started (with timeout: {}ms)",
-                id,
-                read_timeout.as_millis()
-            );
+            emitter.join().unwrap();
+        }
 
-            // Poll for input events
-            if let Err(err) = poll_input_events(&window, &id, read_timeout, reader).await {
-                warn!(
-                    "Connection #{} read poller terminated with error: {}",
-                    id, err
-                );
-            } else {
-                info!("Connection #{} read poller was stopped", id);
+        // Assert that there is no cross-talk between connections
+        let mut destroyed = 0;
+
+        for event in emitted.lock().unwrap().iter() {
+            match event {
+                Some(generation) => assert_eq!(*generation, destroyed),
+                None => destroyed += 1,
             }
-        })
-    };
+        }
 
-    // Add new connection in state
-    {
-        let mut state_connections = state.connections.write().unwrap();
-
-        state_connections.insert(
-            id.to_string(),
-            ConnectionClient {
-                jid: jid_bare,
-                sender: tx,
-                read_handle,
-                write_handle,
+        assert_eq!(destroyed, 200);
+    }
+
+    #[tokio::test]
+    async fn test_wait_terminated() {
+        let shared = Arc::new(ConnectionShared::default());
+        let deadline = || time::Instant::now() + Duration::from_millis(1000);
+
+        // Not terminated in time
+        assert!(!shared.wait_terminated(time::Instant::now()).await);
+
+        // Terminated while waiting
+        let waiter = tokio::spawn({
+            let shared = shared.clone();
+
+            async move { shared.wait_terminated(deadline()).await }
+        });
+
+        tokio::task::yield_now().await;
+
+        shared.transition_state(ConnectionState::Disconnected, || {});
+
+        assert!(waiter.await.unwrap());
+
+        // Already terminated
+        assert!(shared.wait_terminated(deadline()).await);
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        assert_eq!(reconnect_delay(0), Duration::from_secs(1));
+        assert_eq!(reconnect_delay(1), Duration::from_secs(2));
+        assert_eq!(reconnect_delay(2), Duration::from_secs(4));
+        assert_eq!(reconnect_delay(6), Duration::from_secs(60));
+        assert_eq!(reconnect_delay(100), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_cancelled_connection_does_not_reconnect() {
+        let shared = ConnectionShared {
+            options: ConnectOptions {
+                reconnect: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert!(shared.can_reconnect(ConnectionState::ConnectionTimeout));
+        assert!(!shared.can_reconnect(ConnectionState::AuthenticationFailure));
+
+        // Disconnecting explicitly cancels any pending reconnection
+        shared.cancel_reconnect();
+
+        assert!(!shared.can_reconnect(ConnectionState::ConnectionTimeout));
+    }
+
+    #[test]
+    fn test_terminal_error_does_not_reconnect() {
+        let shared = ConnectionShared {
+            options: ConnectOptions {
+                reconnect: true,
+                ..Default::default()
             },
+            ..Default::default()
+        };
+
+        *shared.error_kind.lock().unwrap() = Some(ConnectionErrorKind::IoConnectionReset);
+
+        assert!(shared.can_reconnect(ConnectionState::ConnectionError));
+
+        // Configuration errors never recover by reconnecting
+        *shared.error_kind.lock().unwrap() = Some(ConnectionErrorKind::CertificatePinMismatch);
+
+        assert!(!shared.can_reconnect(ConnectionState::ConnectionError));
+    }
+
+    #[test]
+    fn test_match_reflection() {
+        let shared = ConnectionShared {
+            jid: RwLock::new(Some("valerian@prose.org".parse().unwrap())),
+            ..Default::default()
+        };
+
+        let message = |from: &str, kind: &str| {
+            Element::builder("message", NS_CLIENT)
+                .attr("id", "abc")
+                .attr("from", from)
+                .attr("to", "room@muc.prose.org")
+                .attr("type", kind)
+                .build()
+        };
+
+        // Reflected by the MUC room it was sent to
+        shared.expect_reflection(&message("", "groupchat"), "abc", "token");
+
+        assert_eq!(shared.match_reflection(&message("other@prose.org", "chat")), None);
+        assert_eq!(
+            shared.match_reflection(&message("room@muc.prose.org/valerian", "groupchat")),
+            Some("token".to_string())
         );
 
-        info!(
-            "There are now {} connections in the global state: {}",
-            state_connections.len(),
-            state_connections
-                .keys()
-                .map(|id| format!("#{}", id))
-                .collect::<Vec<_>>()
-                .join(", ")
+        // Reflected by our own bare JID only (not a MUC message)
+        shared.expect_reflection(&message("", "chat"), "abc", "token");
+
+        assert_eq!(shared.match_reflection(&message("room@muc.prose.org", "chat")), None);
+        assert_eq!(
+            shared.match_reflection(&message("valerian@prose.org", "chat")),
+            Some("token".to_string())
         );
     }
 
-    info!("Connection #{} connect request complete", id);
+    #[test]
+    fn test_record_sm_resumption() {
+        let shared = ConnectionShared::default();
 
-    Ok(())
-}
+        let enabled = Element::builder("enabled", NS_SM)
+            .attr("id", "some-long-sm-id")
+            .attr("location", "[2001:41D0:1:A49b::1]:9222")
+            .attr("max", "300")
+            .attr("resume", "true")
+            .build();
 
-#[tauri::command]
-pub fn disconnect<R: Runtime>(
-    window: Window<R>,
-    id: &str,
-    state: State<'_, ConnectionClientState>,
-) -> Result<(), DisconnectError> {
-    info!("Connection #{} disconnect requested", id);
+        assert!(shared.stream_management.lock().unwrap().resumption.is_none());
 
-    // Send stream end?
-    if let Some(ref connection) = state.connections.read().unwrap().get(id) {
-        // Abort read task handle (so that no other IPC gets sent)
-        connection.read_handle.abort();
+        shared.handle_sm_stanza(&enabled);
 
-        // Emit end-of-stream packet (requesting a clean disconnection)
-        match connection.sender.send(Packet::StreamEnd) {
-            Ok(_) => {
-                info!("Connection #{} disconnect request complete", id);
+        let resumption = shared.stream_management.lock().unwrap().resumption.clone().unwrap();
 
-                // Consider as disconnected immediately
-                // Notice: this saves some time, instead of waiting for stream end \
-                //   acknowledgement from server which may never come in case of a \
-                //   disconnect request following network issues (thus we would be \
-                //   waiting a long time for the TCP timeout to trigger).
-                emit_connection_abort(&window, id, ConnectionState::Disconnected);
+        assert_eq!(resumption.id.as_deref(), Some("some-long-sm-id"));
+        assert_eq!(resumption.location.as_deref(), Some("[2001:41D0:1:A49b::1]:9222"));
+        assert_eq!(resumption.max, Some(300));
+        assert!(resumption.resume);
 
-                Ok(())
-            }
-            Err(err) => {
-                error!(
-                    "Connection #{} disconnect request failed, because: {}",
-                    id, err
-                );
+        // Forgotten once the stream goes away
+        shared.take_sm_unacked(None);
 
-                // Recover from closed sender channel state (implicitly disconnect)
-                recover_closed_sender_channel(&window, id, connection);
+        assert!(shared.stream_management.lock().unwrap().resumption.is_none());
 
-                Err(DisconnectError::CannotWrite)
-            }
+        // Not recorded if resumption was not granted
+        shared.handle_sm_stanza(&Element::builder("enabled", NS_SM).attr("id", "abc").build());
+
+        assert!(shared.stream_management.lock().unwrap().resumption.is_none());
+    }
+
+    #[test]
+    fn test_resume_sm() {
+        let shared = ConnectionShared::default();
+
+        let message = |id: &str| Element::builder("message", NS_CLIENT).attr("id", id).build();
+        let ids = |stanzas: Vec<Element>| {
+            stanzas
+                .iter()
+                .map(|stanza| stanza.attr("id").unwrap_or_default().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        shared.start_sm_outbound();
+
+        for id in ["a", "b", "c"] {
+            shared.track_sm_outbound(message(id));
         }
-    } else {
-        error!(
-            "Connection #{} disconnect request failed, as connection does not exist",
-            id
+
+        // Not resumable until resumption gets granted
+        *shared.bound_jid.lock().unwrap() = Some("valerian@prose.org/web".to_string());
+
+        assert!(shared.sm_resume_request().is_none());
+
+        shared.handle_sm_stanza(
+            &Element::builder("enabled", NS_SM)
+                .attr("id", "abc")
+                .attr("resume", "true")
+                .build(),
         );
 
-        Err(DisconnectError::ConnectionDoesNotExist)
+        let (request, jid) = shared.sm_resume_request().unwrap();
+
+        assert_eq!(request.previd, "abc");
+        assert_eq!(request.handled, 0);
+        assert_eq!(jid.resource().as_str(), "web");
+
+        // Only stanzas that the server did not handle are left (resumed)
+        assert_eq!(ids(shared.resume_sm(Some(2))), vec!["c"]);
+        assert!(shared.stream_management.lock().unwrap().outbound);
+        assert!(shared.stream_management.lock().unwrap().resumption.is_some());
+
+        // Only stanzas that the server did not handle are left (not resumed)
+        for id in ["d", "e"] {
+            shared.track_sm_outbound(message(id));
+        }
+
+        assert_eq!(ids(shared.take_sm_unacked(Some(3))), vec!["e"]);
+        assert!(!shared.stream_management.lock().unwrap().outbound);
+        assert!(shared.sm_resume_request().is_none());
     }
-}
 
-#[tauri::command]
-pub fn destroy(id: &str, state: State<'_, ConnectionClientState>) -> Result<(), ()> {
-    info!("Connection #{} destroy requested", id);
+    #[test]
+    fn test_validate_stanza() {
+        let message = "<message xmlns='jabber:client' type='chat' to='valerian@prose.org'/>";
 
-    // Remove existing connection?
-    // Important: this does not disconnect the XMPP stream! Please make sure to call \
-    //   the destroy command whenever the frontend is certain that the connection \
-    //   has been disconnected, that is, following an explicit or implicit \
-    //   disconnection connection state event. The destroy command is solely \
-    //   used for garbage collection purposes (ie. stopping background tasks).
-    if let Some(connection) = state.connections.write().unwrap().remove(id) {
-        // Abort both task handles
-        kill_event_handlers(&connection);
+        assert!(validate_stanza(message.to_string(), None).is_ok());
+        assert!(validate_stanza(message.to_string(), Some(true)).is_ok());
 
-        // Drop connection sender
-        drop(connection.sender);
+        assert!(matches!(
+            validate_stanza("<message xmlns='jabber:client'>".to_string(), None),
+            Err(ValidateError::CannotParse(_))
+        ));
+        assert!(matches!(
+            validate_stanza("<iq xmlns='jabber:client' type='get'/>".to_string(), Some(true)),
+            Err(ValidateError::InvalidStanza(_))
+        ));
+    }
 
-        info!("Connection #{} destroy request complete", id);
-    } else {
-        warn!(
-            "Connection #{} destroy request complete, but was already destroyed",
-            id
-        );
+    #[test]
+    fn test_redundant_state_transitions() {
+        use ConnectionState::*;
+
+        // Initial state (not terminated yet)
+        assert!(!is_redundant_state_transition(Disconnected, false, Connected));
+        assert!(!is_redundant_state_transition(Disconnected, false, ConnectionTimeout));
+        assert!(!is_redundant_state_transition(Disconnected, false, Disconnected));
+
+        // Error state, then disconnected state
+        assert!(!is_redundant_state_transition(ConnectionTimeout, false, Disconnected));
+        assert!(is_redundant_state_transition(ConnectionTimeout, false, ConnectionError));
+
+        // Terminated (only a new session can follow)
+        assert!(is_redundant_state_transition(Disconnected, true, Disconnected));
+        assert!(is_redundant_state_transition(Disconnected, true, ConnectionTimeout));
+        assert!(!is_redundant_state_transition(Disconnected, true, Reconnecting));
+        assert!(!is_redundant_state_transition(Disconnected, true, Connected));
+
+        // Repeated states
+        assert!(is_redundant_state_transition(Connected, false, Connected));
+        assert!(is_redundant_state_transition(Reconnecting, false, Reconnecting));
+        assert!(!is_redundant_state_transition(Reconnecting, false, Connected));
     }
 
-    Ok(())
-}
+    #[test]
+    fn test_concurrent_disconnect_and_timeout_states() {
+        use ConnectionState::*;
 
-#[tauri::command]
-pub fn send<R: Runtime>(
-    window: Window<R>,
-    id: &str,
-    state: State<'_, ConnectionClientState>,
-    stanza: String,
-) -> Result<(), SendError> {
-    debug!("Connection #{} send requested (will send XMPP stanza)", id);
+        for _ in 0..200 {
+            let shared = Arc::new(ConnectionShared::default());
+            let emitted = Arc::new(Mutex::new(Vec::new()));
 
-    if let Some(ref connection) = state.connections.read().unwrap().get(id) {
-        let stanza_root = stanza.parse().or(Err(SendError::CannotParse))?;
+            shared.transition_state(Connected, || {});
 
-        match connection.sender.send(Packet::Stanza(stanza_root)) {
-            Ok(_) => {
-                debug!(
-                    "Connection #{} send request complete (XMPP stanza was sent)",
-                    id
-                );
+            // Race the disconnect command against the read poller timing out \
+            //   (which emits an error state, followed by a disconnected state)
+            let races = [vec![Disconnected], vec![ConnectionTimeout, Disconnected]];
 
-                Ok(())
-            }
-            Err(err) => {
-                error!("Connection #{} send request failed, because: {}", id, err);
+            let emitters = races.map(|states| {
+                let (shared, emitted) = (shared.clone(), emitted.clone());
 
-                // Recover from closed sender channel state (implicitly disconnect)
-                recover_closed_sender_channel(&window, id, connection);
+                thread::spawn(move || {
+                    for state in states {
+                        shared.transition_state(state, || emitted.lock().unwrap().push(state));
+                    }
+                })
+            });
 
-                Err(SendError::CannotWrite)
+            for emitter in emitters {
+                emitter.join().unwrap();
             }
+
+            // Assert that the state sequence is clean (ie. ends disconnected, \
+            //   with a single disconnected state, and no error state after it)
+            let emitted = emitted.lock().unwrap();
+
+            assert!(
+                *emitted == [Disconnected] || *emitted == [ConnectionTimeout, Disconnected],
+                "unexpected state sequence: {:?}",
+                emitted
+            );
+            assert_eq!(shared.current_state(), Disconnected);
         }
-    } else {
-        error!(
-            "Connection #{} send request failed, as connection does not exist",
-            id
+    }
+
+    #[test]
+    fn test_make_stanza_chunks() {
+        let stanza = "<message xmlns='jabber:client'><body>héhé</body></message>";
+
+        assert_eq!(make_stanza_chunks(stanza, 8).concat(), stanza);
+        assert_eq!(make_stanza_chunks(stanza, 1).concat(), stanza);
+        assert_eq!(make_stanza_chunks(stanza, stanza.len()), vec![stanza]);
+
+        assert!(make_stanza_chunks(stanza, 8).iter().all(|chunk| chunk.len() <= 8));
+        assert!(make_stanza_chunks("", 8).is_empty());
+    }
+
+    #[test]
+    fn test_make_receive_chunk_event() {
+        assert_eq!(make_receive_chunk_event(EVENT_RECEIVE), (EVENT_RECEIVE_CHUNK, None));
+        assert_eq!(
+            make_receive_chunk_event(EVENT_RECEIVE_MESSAGE),
+            (EVENT_RECEIVE_MESSAGE_CHUNK, Some("message"))
         );
+        assert_eq!(
+            make_receive_chunk_event(EVENT_RECEIVE_IQ),
+            (EVENT_RECEIVE_IQ_CHUNK, Some("iq"))
+        );
+    }
 
-        Err(SendError::ConnectionDoesNotExist)
+    #[test]
+    fn test_is_valid_server_endpoint() {
+        let make_server = |host: &str, port: Option<u16>| ServerEndpoint {
+            host: host.to_string(),
+            port,
+        };
+
+        assert!(is_valid_server_endpoint(&make_server("localhost", Some(5223))));
+        assert!(is_valid_server_endpoint(&make_server("127.0.0.1", None)));
+
+        assert!(!is_valid_server_endpoint(&make_server("localhost", Some(0))));
+        assert!(!is_valid_server_endpoint(&make_server("", Some(5222))));
+        assert!(!is_valid_server_endpoint(&make_server(" localhost", Some(5222))));
+        assert!(!is_valid_server_endpoint(&make_server("local host", None)));
     }
-}
 
-/**************************************************************************
- * PROVIDERS
- * ************************************************************************* */
+    #[test]
+    fn test_make_connector_transport() {
+        let make_options = |transport, websocket_url: Option<&str>| ConnectOptions {
+            transport,
+            websocket_url: websocket_url.map(String::from),
+            ..Default::default()
+        };
 
-pub fn provide<R: Runtime>() -> TauriPlugin<R> {
-    Builder::new("connection")
-        .invoke_handler(tauri::generate_handler![connect, disconnect, destroy, send])
-        .setup(|app_handle, _| {
-            app_handle.manage(ConnectionClientState::default());
+        assert!(matches!(
+            make_connector_transport(&make_options(ConnectionTransport::Tcp, None)),
+            Ok(ConnectorTransport::Tcp)
+        ));
+        assert!(matches!(
+            make_connector_transport(&make_options(ConnectionTransport::WebSocket, None)),
+            Ok(ConnectorTransport::WebSocket { url: None })
+        ));
+        assert!(matches!(
+            make_connector_transport(&make_options(
+                ConnectionTransport::WebSocket,
+                Some("wss://prose.org/xmpp-websocket")
+            )),
+            Ok(ConnectorTransport::WebSocket { url: Some(_) })
+        ));
 
-            Ok(())
-        })
-        .build()
+        assert!(matches!(
+            make_connector_transport(&make_options(
+                ConnectionTransport::WebSocket,
+                Some("ws://prose.org/xmpp-websocket")
+            )),
+            Err(ConnectError::InvalidWebSocketUrl)
+        ));
+        assert!(matches!(
+            make_connector_transport(&make_options(
+                ConnectionTransport::Tcp,
+                Some("wss://prose.org/xmpp-websocket")
+            )),
+            Err(ConnectError::InvalidWebSocketUrl)
+        ));
+    }
+
+    #[test]
+    fn test_is_broadcast_presence() {
+        let presence = |to: Option<&str>, kind: Option<&str>| {
+            let mut builder = Element::builder("presence", NS_CLIENT);
+
+            if let Some(to) = to {
+                builder = builder.attr("to", to);
+            }
+            if let Some(kind) = kind {
+                builder = builder.attr("type", kind);
+            }
+
+            builder.build()
+        };
+
+        assert!(is_broadcast_presence(&presence(None, None)));
+        assert!(is_broadcast_presence(&presence(None, Some("unavailable"))));
+        assert!(!is_broadcast_presence(&presence(None, Some("subscribe"))));
+        assert!(!is_broadcast_presence(&presence(Some("room@muc.prose.org/valerian"), None)));
+    }
+
+    #[test]
+    fn test_log_target_id() {
+        assert_eq!(log_target_id(&log_target("abc")), Some("abc"));
+        assert_eq!(log_target_id("connection"), None);
+        assert_eq!(log_target_id("prose_app_web::connection"), None);
+    }
+
+    #[test]
+    fn test_has_disco_feature() {
+        let response: Element = "<iq xmlns='jabber:client' type='result' id='a'>\
+            <query xmlns='http://jabber.org/protocol/disco#info'>\
+            <identity category='server' type='im'/>\
+            <feature var='urn:xmpp:carbons:2'/></query></iq>"
+            .parse()
+            .unwrap();
+
+        assert!(has_disco_feature(&response, NS_CARBONS));
+        assert!(!has_disco_feature(&response, NS_MAM));
+
+        let response: Element = "<iq xmlns='jabber:client' type='error' id='a'/>".parse().unwrap();
+
+        assert!(!has_disco_feature(&response, NS_CARBONS));
+    }
+
+    #[test]
+    fn test_make_tls_trust_pin() {
+        let make_options = |pin: &str| TlsTrustOptions {
+            roots: Vec::new(),
+            pin: Some(pin.to_string()),
+        };
+
+        let pin = make_tls_trust(Some(&make_options(&"AB:".repeat(32)[..95])))
+            .unwrap()
+            .pin;
+
+        assert_eq!(pin, Some([0xAB; 32]));
+        assert!(make_tls_trust(Some(&make_options(&"ab".repeat(32)))).is_ok());
+
+        assert!(make_tls_trust(Some(&make_options("AB:CD"))).is_err());
+        assert!(make_tls_trust(Some(&make_options(&"ZZ".repeat(32)))).is_err());
+        assert!(make_tls_trust(None).unwrap().pin.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "throughput benchmark, run with: cargo test --release -- --ignored"]
+    async fn bench_connection_map_throughput() {
+        const CONNECTIONS: usize = 64;
+        const THREADS: usize = 8;
+        const SENDS: usize = 20_000;
+
+        let runtime = TokioHandle::current();
+        let ids = (0..CONNECTIONS)
+            .map(|index| format!("c{}", index))
+            .collect::<Vec<_>>();
+        let stanza: Element = "<message xmlns='jabber:client' to='valerian@prose.org'/>"
+            .parse()
+            .unwrap();
+
+        // Notice: receivers are returned along with their connection, as \
+        //   queueing fails once the receiver of a connection is dropped.
+        let make_client = || {
+            let (sender, receiver) = mpsc::unbounded_channel();
+
+            let client = ConnectionClient {
+                jid: BareJid::new("valerian@prose.org").unwrap(),
+                sender,
+                shared: Arc::default(),
+                read_handle: runtime.spawn(future::ready(())),
+                write_handle: runtime.spawn(future::ready(())),
+                bandwidth_handle: None,
+                receive_handle: None,
+                ping_handle: None,
+            };
+
+            (client, receiver)
+        };
+
+        // Measure sends from concurrent threads (each one sending to all \
+        //   connections, round-robin) through the push path, while a writer \
+        //   connects and destroys connections.
+        let measure = |send: &(dyn Fn(&str) -> Result<(), SendError> + Sync),
+                       churn: &(dyn Fn(usize) + Sync)| {
+            let started_at = Instant::now();
+
+            thread::scope(|scope| {
+                for thread_index in 0..THREADS {
+                    let ids = &ids;
+
+                    scope.spawn(move || {
+                        for index in 0..SENDS {
+                            assert!(send(&ids[(thread_index + index) % CONNECTIONS]).is_ok());
+                        }
+                    });
+                }
+
+                scope.spawn(|| (0..SENDS / 100).for_each(churn));
+            });
+
+            (THREADS * SENDS) as f64 / started_at.elapsed().as_secs_f64()
+        };
+
+        let send = |id: &str, connection: &ConnectionClient| {
+            enqueue_stanza(id, connection, stanza.clone(), 0, None, None)
+        };
+
+        // Before: single global lock
+        let mut receivers = Vec::with_capacity(CONNECTIONS);
+        let locked: RwLock<HashMap<String, ConnectionClient>> = RwLock::new(HashMap::new());
+
+        for id in &ids {
+            let (client, receiver) = make_client();
+
+            locked.write().unwrap().insert(id.clone(), client);
+            receivers.push(receiver);
+        }
+
+        let before = measure(&|id| send(id, &locked.read().unwrap()[id]), &|index| {
+            let id = format!("churn{}", index);
+
+            locked.write().unwrap().insert(id.clone(), make_client().0);
+            locked.write().unwrap().remove(&id);
+        });
+
+        receivers.clear();
+
+        // After: sharded concurrent map
+        let sharded: DashMap<String, ConnectionClient> = DashMap::new();
+
+        for id in &ids {
+            let (client, receiver) = make_client();
+
+            sharded.insert(id.clone(), client);
+            receivers.push(receiver);
+        }
+
+        let after = measure(&|id| send(id, &sharded.get(id).unwrap()), &|index| {
+            let id = format!("churn{}", index);
+
+            sharded.insert(id.clone(), make_client().0);
+            sharded.remove(&id);
+        });
+
+        // Notice: the sharded map must not serialize sends across connections, \
+        //   thus it must at least keep up with the global lock.
+        assert!(
+            after >= before,
+            "sends/s with global lock: {:.0}, with sharded map: {:.0}",
+            before,
+            after
+        );
+    }
 }
@@ -0,0 +1,38 @@
+// This file is part of prose-app-web
+//
+// Copyright 2024, Prose Foundation
+
+/**************************************************************************
+ * IMPORTS
+ * ************************************************************************* */
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{self, Write};
+
+/**************************************************************************
+ * CONSTANTS
+ * ************************************************************************* */
+
+// Notice: only 'gzip' is supported, since it is the only format that all web \
+//   views can natively decompress with the 'DecompressionStream' API (eg. \
+//   'zstd' is not supported there, and would require bundling a decoder).
+pub const ENCODING: &'static str = "gzip";
+
+/**************************************************************************
+ * HELPERS
+ * ************************************************************************* */
+
+pub fn compress(payload: &str) -> io::Result<String> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+
+    encoder.write_all(payload.as_bytes())?;
+
+    let compressed = encoder.finish()?;
+
+    // Notice: compressed bytes are encoded as Base64, since event payloads \
+    //   get serialized to JSON (which cannot carry raw bytes efficiently).
+    Ok(BASE64.encode(compressed))
+}
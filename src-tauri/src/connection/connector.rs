@@ -0,0 +1,1354 @@
+// This file is part of prose-app-web
+//
+// Copyright 2024, Prose Foundation
+
+/**************************************************************************
+ * IMPORTS
+ * ************************************************************************* */
+
+use futures::StreamExt;
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioAsyncResolver;
+use reqwest::Url;
+use jid::Jid;
+use log::{debug, info, warn};
+use minidom::Element;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::version::{TLS12, TLS13};
+use rustls::{
+    AlertDescription, ClientConfig, Error as TlsError, PeerIncompatible, ProtocolVersion,
+    RootCertStore, SupportedProtocolVersion,
+};
+use sasl::common::ChannelBinding;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio::time::timeout;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_xmpp::connect::{ServerConnector, ServerConnectorError};
+use tokio_xmpp::xmpp_stream::XMPPStream;
+use tokio_xmpp::{Error, Packet, ProtocolError};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use super::header::HeaderStream;
+use super::resume::{ResumeOutcome, ResumeRequest, ResumeStream};
+use super::throttle::ThrottledStream;
+use super::websocket::WebSocketFraming;
+
+/**************************************************************************
+ * CONSTANTS
+ * ************************************************************************* */
+
+const NS_TLS: &'static str = "urn:ietf:params:xml:ns:xmpp-tls";
+const NS_SASL: &'static str = "urn:ietf:params:xml:ns:xmpp-sasl";
+
+// Notice: those are the SASL mechanisms supported by 'tokio-xmpp', in the \
+//   order it prefers them (ie. it picks the first one the server offers).
+const SASL_MECHANISMS_SUPPORTED: [&'static str; 4] =
+    ["SCRAM-SHA-256", "SCRAM-SHA-1", "PLAIN", "ANONYMOUS"];
+
+const SRV_SERVICE: &'static str = "_xmpp-client._tcp";
+const DEFAULT_PORT: u16 = 5222;
+const DEFAULT_WEBSOCKET_PORT: u16 = 443;
+
+const DNS_PLAIN_PORT: u16 = 53;
+const DNS_HTTPS_PORT: u16 = 443;
+
+const ONION_DOMAIN_SUFFIX: &'static str = ".onion";
+
+const WEBSOCKET_SCHEME: &'static str = "wss";
+const WEBSOCKET_PROTOCOL: &'static str = "xmpp";
+const WEBSOCKET_LINK_REL: &'static str = "urn:xmpp:alt-connections:websocket";
+const WEBSOCKET_DISCOVERY_TIMEOUT_MILLISECONDS: u64 = 10000;
+
+const NS_XRD: &'static str = "http://docs.oasis-open.org/ns/xri/xrd-1.0";
+
+/**************************************************************************
+ * TYPES
+ * ************************************************************************* */
+
+type ConnectorStream = ThrottledStream<TcpStream>;
+type ConnectorTlsStream = ResumeStream<TransportStream>;
+
+/**************************************************************************
+ * ENUMERATIONS
+ * ************************************************************************* */
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ConnectorStage {
+    Resolve,
+    Connect,
+}
+
+// Notice: TLS versions below 1.2 are never supported, meaning that the \
+//   default minimum version is TLS 1.2.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+#[derive(Debug, Clone, Default)]
+pub enum ConnectorResolver {
+    #[default]
+    System,
+    Plain {
+        addresses: Vec<IpAddr>,
+        port: Option<u16>,
+    },
+    Https {
+        addresses: Vec<IpAddr>,
+        port: Option<u16>,
+        server_name: String,
+    },
+}
+
+// Notice: the WebSocket transport connects to the given URL, or to the URL \
+//   advertised by the JID domain if none is given (see XEP-0156).
+#[derive(Debug, Clone, Default)]
+pub enum ConnectorTransport {
+    #[default]
+    Tcp,
+    WebSocket {
+        url: Option<Url>,
+    },
+}
+
+#[derive(Debug)]
+pub enum TransportStream {
+    Tcp(TlsStream<ConnectorStream>),
+    WebSocket(WebSocketFraming<TlsStream<ConnectorStream>>),
+}
+
+#[derive(Debug, Error)]
+pub enum ConnectorError {
+    #[error("Could not initialize DNS resolver")]
+    ResolverUnavailable,
+    #[error("Could not resolve any server host for domain: {0}")]
+    ResolveFailed(String),
+    #[error("Could not connect to any resolved server host")]
+    ConnectFailed,
+    #[error("Connection to server host timed out")]
+    ConnectTimeout,
+    #[error("No Tor proxy configured, refusing to connect to onion domain")]
+    TorProxyMissing,
+    #[error("Proxy failure: {0}")]
+    ProxyFailed(String),
+    #[error("Invalid TLS server name")]
+    InvalidServerName,
+    #[error("TLS failure: {0}")]
+    TlsFailed(String),
+    #[error("TLS version could not be negotiated: {0}")]
+    TlsVersionUnsupported(String),
+    #[error("Server stream is from another domain: {0}")]
+    StreamMismatch(String),
+    #[error("Server certificate does not match pinned fingerprint: {0}")]
+    CertificatePinMismatch(String),
+    #[error("Client certificate was rejected by server: {0}")]
+    CertificateRejected(String),
+    #[error("Server offers no acceptable SASL mechanism")]
+    NoAcceptableSaslMechanism,
+    #[error("Could not discover WebSocket endpoint for domain: {0}")]
+    WebSocketNotDiscovered(String),
+    #[error("WebSocket failure: {0}")]
+    WebSocketFailed(String),
+}
+
+/**************************************************************************
+ * STRUCTURES
+ * ************************************************************************* */
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectorReport {
+    failure: Arc<Mutex<Option<ConnectorStage>>>,
+    tried: Arc<Mutex<Vec<String>>>,
+    certificate_expiry: Arc<Mutex<Option<i64>>>,
+    stream_from: Arc<Mutex<Option<String>>>,
+    tls_version: Arc<Mutex<Option<TlsVersion>>>,
+    stream_features: Arc<Mutex<Option<Element>>>,
+    sasl_mechanism: Arc<Mutex<Option<String>>>,
+    received_tail: Arc<Mutex<VecDeque<u8>>>,
+    resume_outcome: Arc<Mutex<Option<ResumeOutcome>>>,
+    secured: Arc<Notify>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionConnector {
+    report: ConnectorReport,
+    endpoints: Vec<(String, u16)>,
+    endpoint_timeout: Option<Duration>,
+    tor_proxy: Option<String>,
+    resolver: ConnectorResolver,
+    transport: ConnectorTransport,
+    rate_limits: (Option<u64>, Option<u64>),
+    strict_stream_from: bool,
+    lang: Option<String>,
+    tls_min_version: TlsVersion,
+    tls_trust: TlsTrust,
+    tls_identity: Option<TlsIdentity>,
+    sasl_mechanisms: Vec<String>,
+    resume: Arc<Mutex<Option<ResumeRequest>>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsTrust {
+    pub roots: Vec<CertificateDer<'static>>,
+    pub pin: Option<[u8; 32]>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsIdentity {
+    certificates: Vec<CertificateDer<'static>>,
+    key: Arc<PrivateKeyDer<'static>>,
+}
+
+/**************************************************************************
+ * HELPERS
+ * ************************************************************************* */
+
+fn is_onion_domain(domain: &str) -> bool {
+    domain.ends_with(ONION_DOMAIN_SUFFIX)
+}
+
+fn make_resolver(resolver: &ConnectorResolver) -> Result<TokioAsyncResolver, ConnectorError> {
+    // Notice: custom name servers are given as addresses, since they cannot \
+    //   be resolved themselves (this would require another resolver).
+    let name_servers = match resolver {
+        ConnectorResolver::System => {
+            return TokioAsyncResolver::tokio_from_system_conf()
+                .or(Err(ConnectorError::ResolverUnavailable))
+        }
+        ConnectorResolver::Plain { addresses, port } => NameServerConfigGroup::from_ips_clear(
+            addresses,
+            port.unwrap_or(DNS_PLAIN_PORT),
+            true,
+        ),
+        ConnectorResolver::Https {
+            addresses,
+            port,
+            server_name,
+        } => NameServerConfigGroup::from_ips_https(
+            addresses,
+            port.unwrap_or(DNS_HTTPS_PORT),
+            server_name.to_owned(),
+            true,
+        ),
+    };
+
+    if name_servers.is_empty() {
+        return Err(ConnectorError::ResolverUnavailable);
+    }
+
+    Ok(TokioAsyncResolver::tokio(
+        ResolverConfig::from_parts(None, Vec::new(), name_servers),
+        ResolverOpts::default(),
+    ))
+}
+
+async fn resolve_endpoints(
+    resolver: &ConnectorResolver,
+    domain: &str,
+) -> Result<Vec<(String, u16)>, ConnectorError> {
+    let resolver = make_resolver(resolver)?;
+
+    let mut endpoints = Vec::new();
+
+    // Resolve SRV records first (ordered by priority)
+    // Notice: a SRV target of '.' means that the service is explicitly \
+    //   not available on this domain, thus it gets ignored.
+    if let Ok(lookup) = resolver
+        .srv_lookup(format!("{}.{}.", SRV_SERVICE, domain))
+        .await
+    {
+        let mut records = lookup.iter().collect::<Vec<_>>();
+
+        records.sort_by_key(|record| record.priority());
+
+        for record in records {
+            let target = record.target().to_ascii();
+            let target = target.trim_end_matches('.');
+
+            if !target.is_empty() {
+                endpoints.push((target.to_string(), record.port()));
+            }
+        }
+    }
+
+    // No SRV record? Fallback to the domain itself on the default port
+    if endpoints.is_empty() {
+        endpoints.push((domain.to_string(), DEFAULT_PORT));
+    }
+
+    // Only retain endpoints with hosts that effectively resolve
+    // Notice: this is what lets us tell a domain that does not host XMPP \
+    //   (eg. a mistyped domain) apart from a later TCP or TLS failure.
+    let mut resolved = Vec::with_capacity(endpoints.len());
+
+    for (host, port) in endpoints {
+        match resolver.lookup_ip(host.as_str()).await {
+            Ok(_) => resolved.push((host, port)),
+            Err(err) => debug!("Could not resolve server host: {} because: {}", host, err),
+        }
+    }
+
+    if resolved.is_empty() {
+        return Err(ConnectorError::ResolveFailed(domain.to_string()));
+    }
+
+    Ok(resolved)
+}
+
+async fn connect_socks5(proxy: &str, host: &str, port: u16) -> Result<TcpStream, ConnectorError> {
+    let proxy_error = |err: std::io::Error| ConnectorError::ProxyFailed(err.to_string());
+
+    // Host must fit in a SOCKS5 domain name address
+    let host_length = u8::try_from(host.len())
+        .or(Err(ConnectorError::ProxyFailed("host is too long".to_string())))?;
+
+    let mut stream = TcpStream::connect(proxy).await.map_err(proxy_error)?;
+
+    // Greet proxy (offering the 'no authentication' method only)
+    stream
+        .write_all(&[0x05, 0x01, 0x00])
+        .await
+        .map_err(proxy_error)?;
+
+    let mut greeting = [0u8; 2];
+
+    stream
+        .read_exact(&mut greeting)
+        .await
+        .map_err(proxy_error)?;
+
+    if greeting != [0x05, 0x00] {
+        return Err(ConnectorError::ProxyFailed(
+            "authentication method refused".to_string(),
+        ));
+    }
+
+    // Request connection to host (by domain name)
+    // Notice: the host is passed as-is to the proxy, which then resolves it \
+    //   on its end. This is required for onion domains, and also avoids \
+    //   leaking DNS requests outside of the proxy.
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, host_length];
+
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+
+    stream.write_all(&request).await.map_err(proxy_error)?;
+
+    let mut reply = [0u8; 4];
+
+    stream.read_exact(&mut reply).await.map_err(proxy_error)?;
+
+    // Notice: the version and reserved bytes are checked first, as the \
+    //   reply code cannot be trusted if the proxy does not speak SOCKS5.
+    if reply[0] != 0x05 || reply[2] != 0x00 {
+        return Err(ConnectorError::ProxyFailed("invalid reply".to_string()));
+    }
+
+    if reply[1] != 0x00 {
+        return Err(ConnectorError::ProxyFailed(format!(
+            "connection refused with code: {}",
+            reply[1]
+        )));
+    }
+
+    // Skip bound address and port (we do not need them)
+    let address_length = match reply[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => {
+            let mut length = [0u8; 1];
+
+            stream.read_exact(&mut length).await.map_err(proxy_error)?;
+
+            length[0] as usize
+        }
+        _ => {
+            return Err(ConnectorError::ProxyFailed(
+                "invalid bound address".to_string(),
+            ))
+        }
+    };
+
+    let mut bound = vec![0u8; address_length + 2];
+
+    stream.read_exact(&mut bound).await.map_err(proxy_error)?;
+
+    Ok(stream)
+}
+
+fn peer_certificate_expiry(stream: &TlsStream<ConnectorStream>) -> Option<i64> {
+    let (_, connection) = stream.get_ref();
+
+    // Acquire expiry time of the end-entity certificate (ie. the first one)
+    let certificate = connection.peer_certificates()?.first()?;
+    let (_, certificate) = X509Certificate::from_der(certificate.as_ref()).ok()?;
+
+    Some(certificate.validity().not_after.timestamp())
+}
+
+fn peer_certificate_fingerprint(stream: &TlsStream<ConnectorStream>) -> Option<[u8; 32]> {
+    let (_, connection) = stream.get_ref();
+
+    // Acquire fingerprint of the end-entity certificate (ie. the first one)
+    let certificate = connection.peer_certificates()?.first()?;
+
+    Some(Sha256::digest(certificate.as_ref()).into())
+}
+
+pub fn parse_certificates(pem: &str) -> Option<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_slice_iter(pem.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+
+    (!certificates.is_empty()).then_some(certificates)
+}
+
+pub fn parse_identity(certificate: &str, key: &str) -> Option<TlsIdentity> {
+    Some(TlsIdentity {
+        certificates: parse_certificates(certificate)?,
+        key: Arc::new(PrivateKeyDer::from_pem_slice(key.as_bytes()).ok()?),
+    })
+}
+
+pub fn parse_fingerprint(fingerprint: &str) -> Option<[u8; 32]> {
+    // Notice: fingerprints are accepted either as raw hexadecimal, or with \
+    //   bytes separated by colons (as shown by most certificate tools).
+    let hex = fingerprint
+        .chars()
+        .filter(|character| *character != ':' && !character.is_whitespace())
+        .collect::<String>();
+
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut bytes = [0u8; 32];
+
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[(index * 2)..(index * 2 + 2)], 16).ok()?;
+    }
+
+    Some(bytes)
+}
+
+fn format_fingerprint(fingerprint: &[u8; 32]) -> String {
+    fingerprint
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn offered_sasl_mechanisms(features: &Element) -> Vec<String> {
+    features
+        .get_child("mechanisms", NS_SASL)
+        .map(|mechanisms| {
+            mechanisms
+                .children()
+                .filter(|mechanism| mechanism.is("mechanism", NS_SASL))
+                .map(|mechanism| mechanism.text().trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn select_sasl_mechanism(allowed: &[String], offered: &[String]) -> Option<String> {
+    let is_offered = |mechanism: &str| {
+        offered
+            .iter()
+            .any(|offered| offered.eq_ignore_ascii_case(mechanism))
+    };
+
+    // Notice: allowed mechanisms are ordered by preference, otherwise the \
+    //   'tokio-xmpp' preference order applies (if nothing is allowed).
+    if allowed.is_empty() {
+        SASL_MECHANISMS_SUPPORTED
+            .into_iter()
+            .find(|mechanism| is_offered(mechanism))
+            .map(|mechanism| mechanism.to_string())
+    } else {
+        allowed
+            .iter()
+            .find(|mechanism| {
+                is_offered(mechanism)
+                    && SASL_MECHANISMS_SUPPORTED
+                        .iter()
+                        .any(|supported| supported.eq_ignore_ascii_case(mechanism))
+            })
+            .map(|mechanism| mechanism.to_ascii_uppercase())
+    }
+}
+
+fn restrict_sasl_mechanisms(features: &mut Element, mechanism: &str) {
+    features.remove_child("mechanisms", NS_SASL);
+
+    features.append_child(
+        Element::builder("mechanisms", NS_SASL)
+            .append(
+                Element::builder("mechanism", NS_SASL)
+                    .append(mechanism.to_owned())
+                    .build(),
+            )
+            .build(),
+    );
+}
+
+fn negotiated_tls_version(stream: &TlsStream<ConnectorStream>) -> Option<TlsVersion> {
+    let (_, connection) = stream.get_ref();
+
+    match connection.protocol_version()? {
+        ProtocolVersion::TLSv1_2 => Some(TlsVersion::Tls12),
+        ProtocolVersion::TLSv1_3 => Some(TlsVersion::Tls13),
+        _ => None,
+    }
+}
+
+fn make_tls_config(
+    min_version: TlsVersion,
+    roots: &[CertificateDer<'static>],
+    identity: Option<&TlsIdentity>,
+) -> Result<ClientConfig, Error> {
+    let mut root_store = RootCertStore::empty();
+
+    root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+    // Also trust custom roots? (eg. internal certificate authorities)
+    let (added, ignored) = root_store.add_parsable_certificates(roots.iter().cloned());
+
+    if ignored > 0 {
+        warn!("Ignored {} invalid custom TLS roots (added: {})", ignored, added);
+    }
+
+    // Only offer TLS versions that meet the minimum version
+    let versions: &[&'static SupportedProtocolVersion] = match min_version {
+        TlsVersion::Tls12 => &[&TLS13, &TLS12],
+        TlsVersion::Tls13 => &[&TLS13],
+    };
+
+    let builder =
+        ClientConfig::builder_with_protocol_versions(versions).with_root_certificates(root_store);
+
+    // Authenticate with a client certificate? (if an identity is set)
+    match identity {
+        Some(identity) => builder
+            .with_client_auth_cert(identity.certificates.clone(), identity.key.clone_key())
+            .map_err(|err| Error::Connection(Box::new(ConnectorError::TlsFailed(err.to_string())))),
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+fn make_tls_error(err: std::io::Error, identified: bool) -> ConnectorError {
+    // Notice: handshake errors are wrapped in I/O errors by 'tokio-rustls', \
+    //   thus version negotiation failures have to be unwrapped from there.
+    let tls_error = err.get_ref().and_then(|err| err.downcast_ref::<TlsError>());
+
+    // Notice: a server refusing our client certificate tells so with an \
+    //   alert, which is only meaningful if we presented a certificate.
+    let is_certificate_error = identified
+        && matches!(
+            tls_error,
+            Some(TlsError::AlertReceived(
+                AlertDescription::BadCertificate
+                    | AlertDescription::UnsupportedCertificate
+                    | AlertDescription::CertificateRevoked
+                    | AlertDescription::CertificateExpired
+                    | AlertDescription::CertificateUnknown
+                    | AlertDescription::CertificateRequired
+                    | AlertDescription::UnknownCA
+                    | AlertDescription::AccessDenied
+            ))
+        );
+
+    let is_version_error = matches!(
+        tls_error,
+        Some(
+            TlsError::AlertReceived(AlertDescription::ProtocolVersion)
+                | TlsError::PeerIncompatible(
+                    PeerIncompatible::ServerDoesNotSupportTls12Or13
+                        | PeerIncompatible::ServerTlsVersionIsDisabledByOurConfig
+                        | PeerIncompatible::SupportedVersionsExtensionRequired
+                )
+        )
+    );
+
+    if is_certificate_error {
+        ConnectorError::CertificateRejected(err.to_string())
+    } else if is_version_error {
+        ConnectorError::TlsVersionUnsupported(err.to_string())
+    } else {
+        ConnectorError::TlsFailed(err.to_string())
+    }
+}
+
+async fn starttls(
+    mut xmpp_stream: XMPPStream<ConnectorStream>,
+    domain: &str,
+    config: ClientConfig,
+    identified: bool,
+) -> Result<TlsStream<ConnectorStream>, Error> {
+    // Request to upgrade stream to TLS, and wait for server to proceed
+    xmpp_stream
+        .send_stanza(Element::builder("starttls", NS_TLS).build())
+        .await?;
+
+    loop {
+        match xmpp_stream.next().await {
+            Some(Ok(Packet::Stanza(ref stanza))) if stanza.is("proceed", NS_TLS) => break,
+            Some(Ok(Packet::Text(_))) => {}
+            Some(Err(err)) => return Err(err.into()),
+            _ => return Err(Error::Protocol(ProtocolError::NoTls)),
+        }
+    }
+
+    // Perform TLS handshake over the underlying TCP stream
+    connect_tls(xmpp_stream.into_inner(), domain, config, identified).await
+}
+
+async fn connect_tls(
+    stream: ConnectorStream,
+    host: &str,
+    config: ClientConfig,
+    identified: bool,
+) -> Result<TlsStream<ConnectorStream>, Error> {
+    let server_name = ServerName::try_from(host.to_string())
+        .or(Err(Error::Connection(Box::new(ConnectorError::InvalidServerName))))?;
+
+    TlsConnector::from(Arc::new(config))
+        .connect(server_name, stream)
+        .await
+        .map_err(|err| Error::Connection(Box::new(make_tls_error(err, identified))))
+}
+
+pub fn parse_websocket_url(url: &str) -> Option<Url> {
+    // Notice: only secure WebSocket URLs are accepted, as the transport \
+    //   does not negotiate TLS on its own (unlike STARTTLS over TCP).
+    Url::parse(url)
+        .ok()
+        .filter(|url| url.scheme() == WEBSOCKET_SCHEME && url.host_str().is_some())
+}
+
+fn parse_host_meta_json(document: &str) -> Option<Url> {
+    let document = serde_json::from_str::<serde_json::Value>(document).ok()?;
+
+    document
+        .get("links")?
+        .as_array()?
+        .iter()
+        .filter(|link| link.get("rel").and_then(|rel| rel.as_str()) == Some(WEBSOCKET_LINK_REL))
+        .filter_map(|link| link.get("href").and_then(|href| href.as_str()))
+        .find_map(parse_websocket_url)
+}
+
+fn parse_host_meta_xml(document: &str) -> Option<Url> {
+    let document = document.parse::<Element>().ok()?;
+
+    document
+        .children()
+        .filter(|link| link.is("Link", NS_XRD) && link.attr("rel") == Some(WEBSOCKET_LINK_REL))
+        .filter_map(|link| link.attr("href"))
+        .find_map(parse_websocket_url)
+}
+
+async fn discover_websocket_url(domain: &str) -> Result<Url, ConnectorError> {
+    let not_discovered = || ConnectorError::WebSocketNotDiscovered(domain.to_string());
+
+    // Notice: discovery goes over HTTPS with the system resolver, which \
+    //   would leak onion domains (they need an explicit WebSocket URL).
+    if is_onion_domain(domain) {
+        return Err(not_discovered());
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(WEBSOCKET_DISCOVERY_TIMEOUT_MILLISECONDS))
+        .build()
+        .or(Err(not_discovered()))?;
+
+    // Acquire advertised WebSocket endpoint (see XEP-0156)
+    // Notice: the JSON document is tried first, as it is simpler to parse, \
+    //   then the XML document (which is the one all servers provide).
+    for (path, parse) in [
+        ("host-meta.json", parse_host_meta_json as fn(&str) -> Option<Url>),
+        ("host-meta", parse_host_meta_xml),
+    ] {
+        let document = match client
+            .get(format!("https://{}/.well-known/{}", domain, path))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+        {
+            Ok(response) => response.text().await.unwrap_or_default(),
+            Err(err) => {
+                debug!("Could not fetch {} for: {} because: {}", path, domain, err);
+
+                continue;
+            }
+        };
+
+        if let Some(url) = parse(&document) {
+            return Ok(url);
+        }
+    }
+
+    Err(not_discovered())
+}
+
+/**************************************************************************
+ * IMPLEMENTATIONS
+ * ************************************************************************* */
+
+impl ConnectorReport {
+    pub fn failure(&self) -> Option<ConnectorStage> {
+        *self.failure.lock().unwrap()
+    }
+
+    pub fn certificate_expiry(&self) -> Option<i64> {
+        *self.certificate_expiry.lock().unwrap()
+    }
+
+    pub fn stream_from(&self) -> Option<String> {
+        self.stream_from.lock().unwrap().clone()
+    }
+
+    pub fn tried(&self) -> Vec<String> {
+        self.tried.lock().unwrap().clone()
+    }
+
+    pub fn tls_version(&self) -> Option<TlsVersion> {
+        *self.tls_version.lock().unwrap()
+    }
+
+    pub fn stream_features(&self) -> Option<Element> {
+        self.stream_features.lock().unwrap().clone()
+    }
+
+    pub fn sasl_mechanism(&self) -> Option<String> {
+        self.sasl_mechanism.lock().unwrap().clone()
+    }
+
+    pub fn received_tail(&self) -> Vec<u8> {
+        self.received_tail.lock().unwrap().iter().copied().collect()
+    }
+
+    pub fn resume_outcome(&self) -> Option<ResumeOutcome> {
+        *self.resume_outcome.lock().unwrap()
+    }
+
+    pub async fn secured(&self) {
+        self.secured.notified().await
+    }
+
+    fn secure(&self) {
+        // Notice: this stores a permit if nobody is waiting yet, so that the \
+        //   waiter gets told even if it starts waiting after the handshake.
+        self.secured.notify_one();
+    }
+
+    fn fail(&self, stage: ConnectorStage) {
+        *self.failure.lock().unwrap() = Some(stage);
+    }
+
+    fn try_endpoint(&self, host: &str, port: u16) {
+        self.tried.lock().unwrap().push(format!("{}:{}", host, port));
+    }
+
+    fn reset(&self) {
+        *self.failure.lock().unwrap() = None;
+        *self.certificate_expiry.lock().unwrap() = None;
+        *self.stream_from.lock().unwrap() = None;
+        *self.tls_version.lock().unwrap() = None;
+        *self.stream_features.lock().unwrap() = None;
+        *self.sasl_mechanism.lock().unwrap() = None;
+        *self.resume_outcome.lock().unwrap() = None;
+
+        self.tried.lock().unwrap().clear();
+        self.received_tail.lock().unwrap().clear();
+    }
+}
+
+impl ConnectionConnector {
+    pub fn new(report: ConnectorReport) -> Self {
+        Self {
+            report,
+            endpoints: Vec::new(),
+            endpoint_timeout: None,
+            tor_proxy: None,
+            resolver: ConnectorResolver::System,
+            transport: ConnectorTransport::Tcp,
+            rate_limits: (None, None),
+            strict_stream_from: false,
+            lang: None,
+            tls_min_version: TlsVersion::default(),
+            tls_trust: TlsTrust::default(),
+            tls_identity: None,
+            sasl_mechanisms: Vec::new(),
+            resume: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn with_endpoint(self, host: String, port: Option<u16>) -> Self {
+        self.with_endpoints(vec![(host, port)])
+    }
+
+    pub fn with_endpoints(mut self, endpoints: Vec<(String, Option<u16>)>) -> Self {
+        self.endpoints = endpoints
+            .into_iter()
+            .map(|(host, port)| (host, port.unwrap_or(DEFAULT_PORT)))
+            .collect();
+
+        self
+    }
+
+    pub fn with_endpoint_timeout(mut self, endpoint_timeout: Option<Duration>) -> Self {
+        self.endpoint_timeout = endpoint_timeout;
+
+        self
+    }
+
+    pub fn with_rate_limits(mut self, read_rate: Option<u64>, write_rate: Option<u64>) -> Self {
+        self.rate_limits = (read_rate, write_rate);
+
+        self
+    }
+
+    pub fn with_tor_proxy(mut self, tor_proxy: Option<String>) -> Self {
+        self.tor_proxy = tor_proxy;
+
+        self
+    }
+
+    pub fn with_resolver(mut self, resolver: ConnectorResolver) -> Self {
+        self.resolver = resolver;
+
+        self
+    }
+
+    pub fn with_transport(mut self, transport: ConnectorTransport) -> Self {
+        self.transport = transport;
+
+        self
+    }
+
+    pub fn with_strict_stream_from(mut self, strict_stream_from: bool) -> Self {
+        self.strict_stream_from = strict_stream_from;
+
+        self
+    }
+
+    pub fn with_lang(mut self, lang: Option<String>) -> Self {
+        self.lang = lang;
+
+        self
+    }
+
+    pub fn with_sasl_mechanisms(mut self, sasl_mechanisms: Vec<String>) -> Self {
+        self.sasl_mechanisms = sasl_mechanisms;
+
+        self
+    }
+
+    pub fn with_tls_identity(mut self, tls_identity: Option<TlsIdentity>) -> Self {
+        self.tls_identity = tls_identity;
+
+        self
+    }
+
+    pub fn with_tls_trust(mut self, tls_trust: TlsTrust) -> Self {
+        self.tls_trust = tls_trust;
+
+        self
+    }
+
+    pub fn with_tls_min_version(mut self, tls_min_version: TlsVersion) -> Self {
+        self.tls_min_version = tls_min_version;
+
+        self
+    }
+
+    pub fn request_resume(&self, request: Option<ResumeRequest>) {
+        // Notice: the request is shared between clones of this connector, \
+        //   and only applies to the next connection (it gets taken then).
+        *self.resume.lock().unwrap() = request;
+    }
+
+    async fn connect_endpoint(
+        &self,
+        domain: &str,
+        host: &str,
+        port: u16,
+    ) -> Result<TcpStream, Error> {
+        // Onion domain? Route through the Tor proxy
+        // Notice: never attempt a direct connection there, as this would leak \
+        //   the onion domain to the system DNS resolver.
+        if is_onion_domain(domain) {
+            let tor_proxy = self
+                .tor_proxy
+                .as_ref()
+                .ok_or(Error::Connection(Box::new(ConnectorError::TorProxyMissing)))?;
+
+            return connect_socks5(tor_proxy, host, port)
+                .await
+                .map_err(|err| Error::Connection(Box::new(err)));
+        }
+
+        // Custom resolver? Resolve host with it (rather than the system one)
+        // Notice: the system resolver might be the one returning wrong \
+        //   answers, thus it must not be relied upon there.
+        if !matches!(self.resolver, ConnectorResolver::System) {
+            let resolver =
+                make_resolver(&self.resolver).map_err(|err| Error::Connection(Box::new(err)))?;
+
+            let lookup = resolver.lookup_ip(host).await.map_err(|_| {
+                Error::Connection(Box::new(ConnectorError::ResolveFailed(host.to_string())))
+            })?;
+
+            let addresses = lookup
+                .iter()
+                .map(|address| SocketAddr::new(address, port))
+                .collect::<Vec<_>>();
+
+            return Ok(TcpStream::connect(addresses.as_slice()).await?);
+        }
+
+        Ok(TcpStream::connect((host, port)).await?)
+    }
+
+    async fn establish(
+        &self,
+        jid: &Jid,
+        ns: &str,
+        tcp_stream: TcpStream,
+        resume: Option<ResumeRequest>,
+    ) -> Result<XMPPStream<HeaderStream<ConnectorTlsStream>>, Error> {
+        // Throttle raw socket bytes? (if rate limits are set)
+        // Notice: this wraps the TCP stream, meaning that rate limits apply \
+        //   to bytes on the wire (ie. TLS overhead included).
+        let (read_rate, write_rate) = self.rate_limits;
+        let stream = ThrottledStream::new(tcp_stream, read_rate, write_rate);
+
+        // Open unencrypted stream, then upgrade it to TLS
+        let xmpp_stream = XMPPStream::start(stream, jid.clone(), ns.to_owned()).await?;
+
+        if !xmpp_stream.stream_features.can_starttls() {
+            return Err(Error::Protocol(ProtocolError::NoTls));
+        }
+
+        let tls_config = make_tls_config(
+            self.tls_min_version,
+            &self.tls_trust.roots,
+            self.tls_identity.as_ref(),
+        )?;
+        let tls_stream = starttls(
+            xmpp_stream,
+            jid.domain().as_str(),
+            tls_config,
+            self.tls_identity.is_some(),
+        )
+        .await?;
+
+        self.check_tls(jid, &tls_stream)?;
+
+        self.open(jid, ns, TransportStream::Tcp(tls_stream), resume).await
+    }
+
+    async fn establish_websocket(
+        &self,
+        jid: &Jid,
+        ns: &str,
+        url: &Url,
+        tcp_stream: TcpStream,
+        resume: Option<ResumeRequest>,
+    ) -> Result<XMPPStream<HeaderStream<ConnectorTlsStream>>, Error> {
+        let websocket_error =
+            |err: String| Error::Connection(Box::new(ConnectorError::WebSocketFailed(err)));
+
+        // Throttle raw socket bytes? (if rate limits are set)
+        let (read_rate, write_rate) = self.rate_limits;
+        let stream = ThrottledStream::new(tcp_stream, read_rate, write_rate);
+
+        // Secure the connection right away (there is no STARTTLS there)
+        // Notice: the certificate is verified against the URL host, which \
+        //   might differ from the JID domain (eg. a shared HTTPS frontend).
+        let tls_config = make_tls_config(
+            self.tls_min_version,
+            &self.tls_trust.roots,
+            self.tls_identity.as_ref(),
+        )?;
+        let tls_stream = connect_tls(
+            stream,
+            url.host_str().unwrap_or_default(),
+            tls_config,
+            self.tls_identity.is_some(),
+        )
+        .await?;
+
+        self.check_tls(jid, &tls_stream)?;
+
+        // Upgrade to WebSocket (with the XMPP sub-protocol, see RFC 7395)
+        let mut request = url
+            .as_str()
+            .into_client_request()
+            .map_err(|err| websocket_error(err.to_string()))?;
+
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static(WEBSOCKET_PROTOCOL),
+        );
+
+        let (websocket_stream, response) = tokio_tungstenite::client_async(request, tls_stream)
+            .await
+            .map_err(|err| websocket_error(err.to_string()))?;
+
+        let protocol = response
+            .headers()
+            .get("Sec-WebSocket-Protocol")
+            .and_then(|protocol| protocol.to_str().ok());
+
+        if protocol != Some(WEBSOCKET_PROTOCOL) {
+            return Err(websocket_error("XMPP sub-protocol was refused".to_string()));
+        }
+
+        let transport_stream = TransportStream::WebSocket(WebSocketFraming::new(websocket_stream));
+
+        self.open(jid, ns, transport_stream, resume).await
+    }
+
+    fn check_tls(&self, jid: &Jid, tls_stream: &TlsStream<ConnectorStream>) -> Result<(), Error> {
+        // Refuse server certificate not matching pin? (if pinned)
+        // Notice: the certificate still has to be valid, pinning only \
+        //   restricts which valid certificate is accepted. The observed \
+        //   fingerprint gets logged, so that administrators can update pins.
+        if let Some(pin) = self.tls_trust.pin {
+            let fingerprint = peer_certificate_fingerprint(tls_stream);
+
+            if fingerprint != Some(pin) {
+                let observed = fingerprint
+                    .as_ref()
+                    .map(format_fingerprint)
+                    .unwrap_or_default();
+
+                warn!(
+                    "Server certificate for: {} does not match pinned fingerprint, observed: {}",
+                    jid.domain(),
+                    observed
+                );
+
+                return Err(Error::Connection(Box::new(
+                    ConnectorError::CertificatePinMismatch(observed),
+                )));
+            }
+        }
+
+        // Tell that the stream is now secured (before authenticating)
+        self.report.secure();
+
+        // Remember when the server certificate expires (checked once bound)
+        *self.report.certificate_expiry.lock().unwrap() = peer_certificate_expiry(tls_stream);
+        *self.report.tls_version.lock().unwrap() = negotiated_tls_version(tls_stream);
+
+        Ok(())
+    }
+
+    async fn open(
+        &self,
+        jid: &Jid,
+        ns: &str,
+        transport_stream: TransportStream,
+        resume: Option<ResumeRequest>,
+    ) -> Result<XMPPStream<HeaderStream<ConnectorTlsStream>>, Error> {
+        // Resume previous stream once authenticated? (if requested)
+        // Notice: the underlying 'tokio-xmpp' client cannot resume streams, \
+        //   thus resumption gets negotiated at the bytes level, in place of \
+        //   binding (see the resume stream).
+        let resume_stream =
+            ResumeStream::new(transport_stream, resume, self.report.resume_outcome.clone());
+
+        // Open stream over the encrypted transport (re-open, if over TCP)
+        // Notice: the server stream header is sniffed there, as the encrypted \
+        //   stream is the only one that can be trusted (ie. after TLS). The \
+        //   stream language is only set there as well, as the unencrypted \
+        //   stream is only used to negotiate TLS.
+        let header_stream = HeaderStream::new(resume_stream, self.report.stream_from.clone())
+            .with_lang(self.lang.clone())
+            .with_tail(self.report.received_tail.clone());
+        let mut xmpp_stream =
+            XMPPStream::start(header_stream, jid.clone(), ns.to_owned()).await?;
+
+        // Remember stream features (as advertised over the encrypted stream)
+        *self.report.stream_features.lock().unwrap() =
+            Some(xmpp_stream.stream_features.0.clone());
+
+        // Select SASL mechanism (restricted to allowed ones, if any)
+        // Notice: 'tokio-xmpp' does not allow configuring SASL mechanisms, \
+        //   thus the advertised mechanisms get rewritten so that it can only \
+        //   pick the selected one (no fallback to any other mechanism).
+        let offered = offered_sasl_mechanisms(&xmpp_stream.stream_features.0);
+        let mechanism = select_sasl_mechanism(&self.sasl_mechanisms, &offered);
+
+        if !self.sasl_mechanisms.is_empty() {
+            let Some(ref mechanism) = mechanism else {
+                warn!(
+                    "Server for: {} offers no acceptable SASL mechanism (offered: {})",
+                    jid.domain(),
+                    offered.join(", ")
+                );
+
+                return Err(Error::Connection(Box::new(
+                    ConnectorError::NoAcceptableSaslMechanism,
+                )));
+            };
+
+            restrict_sasl_mechanisms(&mut xmpp_stream.stream_features.0, mechanism);
+        }
+
+        *self.report.sasl_mechanism.lock().unwrap() = mechanism;
+
+        // Refuse stream from another domain? (if strict)
+        if let Some(stream_from) = self.report.stream_from() {
+            if self.strict_stream_from && !stream_from.eq_ignore_ascii_case(jid.domain().as_str()) {
+                return Err(Error::Connection(Box::new(ConnectorError::StreamMismatch(
+                    stream_from,
+                ))));
+            }
+        }
+
+        Ok(xmpp_stream)
+    }
+}
+
+impl AsyncRead for TransportStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::WebSocket(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for TransportStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::WebSocket(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Self::WebSocket(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::WebSocket(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+impl ServerConnectorError for ConnectorError {}
+
+impl ServerConnector for ConnectionConnector {
+    type Stream = HeaderStream<ConnectorTlsStream>;
+    type Error = ConnectorError;
+
+    async fn connect(&self, jid: &Jid, ns: &str) -> Result<XMPPStream<Self::Stream>, Error> {
+        let domain = jid.domain().as_str();
+
+        self.report.reset();
+
+        let resume = self.resume.lock().unwrap().take();
+
+        // Acquire WebSocket URL? (if connecting over WebSocket)
+        let websocket_url = match self.transport {
+            ConnectorTransport::Tcp => None,
+            ConnectorTransport::WebSocket { url: Some(ref url) } => Some(url.clone()),
+            ConnectorTransport::WebSocket { url: None } => {
+                Some(discover_websocket_url(domain).await.map_err(|err| {
+                    self.report.fail(ConnectorStage::Resolve);
+
+                    Error::Connection(Box::new(err))
+                })?)
+            }
+        };
+
+        // Resolve all server endpoints (unless endpoints were forced)
+        // Notice: onion domains cannot be resolved, the Tor proxy will \
+        //   resolve them on its end. The WebSocket URL is the only endpoint \
+        //   when connecting over WebSocket.
+        let endpoints = if let Some(ref url) = websocket_url {
+            let host = url.host_str().unwrap_or_default().to_string();
+
+            vec![(host, url.port_or_known_default().unwrap_or(DEFAULT_WEBSOCKET_PORT))]
+        } else if !self.endpoints.is_empty() {
+            self.endpoints.clone()
+        } else if is_onion_domain(domain) {
+            info!("Connecting to onion domain: {} (via Tor)", domain);
+
+            vec![(domain.to_string(), DEFAULT_PORT)]
+        } else {
+            resolve_endpoints(&self.resolver, domain).await.map_err(|err| {
+                self.report.fail(ConnectorStage::Resolve);
+
+                Error::Connection(Box::new(err))
+            })?
+        };
+
+        // Connect to the first endpoint that accepts our connection (in order)
+        // Notice: if an endpoint timeout is set, then each endpoint gets this \
+        //   much time to complete both TCP and TLS, before moving on to the \
+        //   next endpoint.
+        let mut last_error = None;
+
+        for (host, port) in endpoints {
+            debug!("Connecting to server endpoint: {}:{}", host, port);
+
+            self.report.try_endpoint(&host, port);
+
+            let attempt = async {
+                let tcp_stream = self.connect_endpoint(domain, &host, port).await?;
+
+                match websocket_url {
+                    Some(ref url) => {
+                        self.establish_websocket(jid, ns, url, tcp_stream, resume.clone())
+                            .await
+                    }
+                    None => self.establish(jid, ns, tcp_stream, resume.clone()).await,
+                }
+            };
+
+            let result = if let Some(endpoint_timeout) = self.endpoint_timeout {
+                timeout(endpoint_timeout, attempt).await.unwrap_or_else(|_| {
+                    Err(Error::Connection(Box::new(ConnectorError::ConnectTimeout)))
+                })
+            } else {
+                attempt.await
+            };
+
+            match result {
+                Ok(stream) => return Ok(stream),
+                Err(err) => {
+                    warn!(
+                        "Could not connect to server endpoint: {}:{} because: {}",
+                        host, port, err
+                    );
+
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        self.report.fail(ConnectorStage::Connect);
+
+        Err(last_error
+            .unwrap_or_else(|| Error::Connection(Box::new(ConnectorError::ConnectFailed))))
+    }
+
+    fn channel_binding(stream: &Self::Stream) -> Result<ChannelBinding, Error> {
+        // Never bind to the TLS channel over WebSocket
+        // Notice: WebSocket endpoints are commonly served behind a reverse \
+        //   proxy terminating TLS, in which case the server would compute \
+        //   another binding than ours (failing authentication).
+        let TransportStream::Tcp(tls_stream) = stream.get_ref().get_ref() else {
+            return Ok(ChannelBinding::None);
+        };
+
+        // Bind to the TLS channel (only TLS 1.3 supports 'tls-exporter')
+        let (_, connection) = tls_stream.get_ref();
+
+        if connection.protocol_version() != Some(ProtocolVersion::TLSv1_3) {
+            return Ok(ChannelBinding::None);
+        }
+
+        Ok(connection
+            .export_keying_material(vec![0u8; 32], b"EXPORTER-Channel-Binding", None)
+            .map(ChannelBinding::TlsExporter)
+            .unwrap_or(ChannelBinding::None))
+    }
+}
+
+/**************************************************************************
+ * TESTS
+ * ************************************************************************* */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_sasl_mechanism() {
+        let offered = vec!["PLAIN".to_string(), "SCRAM-SHA-1".to_string()];
+
+        assert_eq!(select_sasl_mechanism(&[], &offered).as_deref(), Some("SCRAM-SHA-1"));
+        assert_eq!(
+            select_sasl_mechanism(&["plain".to_string()], &offered).as_deref(),
+            Some("PLAIN")
+        );
+        assert_eq!(select_sasl_mechanism(&["SCRAM-SHA-256".to_string()], &offered), None);
+        assert_eq!(
+            select_sasl_mechanism(&["X-OAUTH2".to_string()], &["X-OAUTH2".to_string()]),
+            None
+        );
+        assert_eq!(select_sasl_mechanism(&[], &[]), None);
+    }
+
+    #[test]
+    fn test_parse_websocket_url() {
+        assert!(parse_websocket_url("wss://prose.org/xmpp-websocket").is_some());
+        assert!(parse_websocket_url("ws://prose.org/xmpp-websocket").is_none());
+        assert!(parse_websocket_url("https://prose.org/xmpp-websocket").is_none());
+        assert!(parse_websocket_url("prose.org").is_none());
+    }
+
+    #[test]
+    fn test_parse_host_meta() {
+        let json = r#"{"links": [
+            {"rel": "urn:xmpp:alt-connections:xbosh", "href": "https://prose.org/http-bind"},
+            {"rel": "urn:xmpp:alt-connections:websocket", "href": "wss://prose.org/ws"}
+        ]}"#;
+
+        let xml = "<XRD xmlns='http://docs.oasis-open.org/ns/xri/xrd-1.0'>\
+            <Link rel='urn:xmpp:alt-connections:websocket' href='ws://prose.org/ws'/>\
+            <Link rel='urn:xmpp:alt-connections:websocket' href='wss://prose.org/ws'/>\
+            </XRD>";
+
+        assert_eq!(
+            parse_host_meta_json(json).map(String::from),
+            Some("wss://prose.org/ws".to_string())
+        );
+        assert_eq!(
+            parse_host_meta_xml(xml).map(String::from),
+            Some("wss://prose.org/ws".to_string())
+        );
+        assert_eq!(parse_host_meta_json("{}"), None);
+    }
+}
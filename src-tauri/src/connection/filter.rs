@@ -0,0 +1,205 @@
+// This file is part of prose-app-web
+//
+// Copyright 2024, Prose Foundation
+
+/**************************************************************************
+ * IMPORTS
+ * ************************************************************************* */
+
+use minidom::Element;
+use std::str::FromStr;
+use thiserror::Error;
+
+/**************************************************************************
+ * CONSTANTS
+ * ************************************************************************* */
+
+const NAME_WILDCARD: &'static str = "*";
+
+/**************************************************************************
+ * ENUMERATIONS
+ * ************************************************************************* */
+
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum FilterParseError {
+    #[error("Empty filter rule")]
+    EmptyRule,
+    #[error("Invalid element name: {0}")]
+    InvalidName(String),
+    #[error("Invalid attribute condition: {0}")]
+    InvalidCondition(String),
+}
+
+/**************************************************************************
+ * STRUCTURES
+ * ************************************************************************* */
+
+// Notice: filter expressions are made of rules separated by '|', each rule \
+//   matching an element name (or '*' for any), optionally followed by \
+//   attribute conditions, eg. 'message[type=chat]|presence[type="error"]'.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StanzaFilter {
+    rules: Vec<StanzaFilterRule>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct StanzaFilterRule {
+    name: Option<String>,
+    attributes: Vec<(String, String)>,
+}
+
+/**************************************************************************
+ * HELPERS
+ * ************************************************************************* */
+
+fn parse_rule(rule: &str) -> Result<StanzaFilterRule, FilterParseError> {
+    let rule = rule.trim();
+
+    if rule.is_empty() {
+        return Err(FilterParseError::EmptyRule);
+    }
+
+    // Parse element name (up to the first attribute condition)
+    let (name, mut conditions) = rule.split_at(rule.find('[').unwrap_or(rule.len()));
+    let name = name.trim();
+
+    if name.is_empty() || name.contains(|character: char| "]=\"'".contains(character)) {
+        return Err(FilterParseError::InvalidName(name.to_string()));
+    }
+
+    // Parse all attribute conditions (in the form: '[name=value]')
+    let mut attributes = Vec::new();
+
+    while let Some(remaining) = conditions.trim_start().strip_prefix('[') {
+        let (condition, next) = remaining
+            .split_once(']')
+            .ok_or(FilterParseError::InvalidCondition(remaining.to_string()))?;
+
+        let (attribute, value) = condition
+            .split_once('=')
+            .ok_or(FilterParseError::InvalidCondition(condition.to_string()))?;
+
+        let attribute = attribute.trim();
+
+        if attribute.is_empty() {
+            return Err(FilterParseError::InvalidCondition(condition.to_string()));
+        }
+
+        // Notice: values can optionally be quoted (with single or double \
+        //   quotes), which gets unwrapped there.
+        let value = value.trim();
+        let value = [('"', '"'), ('\'', '\'')]
+            .iter()
+            .find_map(|(open, close)| value.strip_prefix(*open)?.strip_suffix(*close))
+            .unwrap_or(value);
+
+        attributes.push((attribute.to_string(), value.to_string()));
+
+        conditions = next;
+    }
+
+    if !conditions.trim().is_empty() {
+        return Err(FilterParseError::InvalidCondition(conditions.to_string()));
+    }
+
+    Ok(StanzaFilterRule {
+        name: (name != NAME_WILDCARD).then(|| name.to_string()),
+        attributes,
+    })
+}
+
+/**************************************************************************
+ * IMPLEMENTATIONS
+ * ************************************************************************* */
+
+impl StanzaFilter {
+    pub fn matches(&self, stanza: &Element) -> bool {
+        self.rules.iter().any(|rule| rule.matches(stanza))
+    }
+}
+
+impl StanzaFilterRule {
+    fn matches(&self, stanza: &Element) -> bool {
+        if let Some(ref name) = self.name {
+            if stanza.name() != name {
+                return false;
+            }
+        }
+
+        self.attributes
+            .iter()
+            .all(|(attribute, value)| stanza.attr(attribute) == Some(value.as_str()))
+    }
+}
+
+impl FromStr for StanzaFilter {
+    type Err = FilterParseError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            rules: expression
+                .split('|')
+                .map(parse_rule)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/**************************************************************************
+ * TESTS
+ * ************************************************************************* */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_stanza(xml: &str) -> Element {
+        xml.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert!("message".parse::<StanzaFilter>().is_ok());
+        assert!("message[type=chat]|presence".parse::<StanzaFilter>().is_ok());
+        assert!("*[type='error']".parse::<StanzaFilter>().is_ok());
+        assert!("iq [type = \"get\"] [id=1]".parse::<StanzaFilter>().is_ok());
+
+        assert_eq!("".parse::<StanzaFilter>(), Err(FilterParseError::EmptyRule));
+        assert_eq!("message|".parse::<StanzaFilter>(), Err(FilterParseError::EmptyRule));
+        assert_eq!(
+            "[type=chat]".parse::<StanzaFilter>(),
+            Err(FilterParseError::InvalidName("".to_string()))
+        );
+        assert_eq!(
+            "message[type]".parse::<StanzaFilter>(),
+            Err(FilterParseError::InvalidCondition("type".to_string()))
+        );
+        assert_eq!(
+            "message[type=chat".parse::<StanzaFilter>(),
+            Err(FilterParseError::InvalidCondition("type=chat".to_string()))
+        );
+        assert_eq!(
+            "message[type=chat]x".parse::<StanzaFilter>(),
+            Err(FilterParseError::InvalidCondition("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_match_filter() {
+        let chat = make_stanza("<message xmlns='jabber:client' type='chat'/>");
+        let groupchat = make_stanza("<message xmlns='jabber:client' type='groupchat'/>");
+        let presence = make_stanza("<presence xmlns='jabber:client'/>");
+
+        let filter = "message[type=chat]|presence".parse::<StanzaFilter>().unwrap();
+
+        assert!(filter.matches(&chat));
+        assert!(!filter.matches(&groupchat));
+        assert!(filter.matches(&presence));
+
+        let filter = "*[type=\"groupchat\"]".parse::<StanzaFilter>().unwrap();
+
+        assert!(!filter.matches(&chat));
+        assert!(filter.matches(&groupchat));
+        assert!(!filter.matches(&presence));
+    }
+}
@@ -0,0 +1,304 @@
+// This file is part of prose-app-web
+//
+// Copyright 2024, Prose Foundation
+
+/**************************************************************************
+ * IMPORTS
+ * ************************************************************************* */
+
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/**************************************************************************
+ * CONSTANTS
+ * ************************************************************************* */
+
+const HEADER_SIZE_MAXIMUM: usize = 4096;
+
+const RECEIVED_TAIL_SIZE: usize = 256;
+
+const HEADER_START_TAG: &'static [u8] = b"<stream:stream";
+
+/**************************************************************************
+ * STRUCTURES
+ * ************************************************************************* */
+
+#[derive(Debug)]
+struct PendingWrite {
+    bytes: Vec<u8>,
+    offset: usize,
+    size: usize,
+}
+
+#[derive(Debug)]
+pub struct HeaderStream<S> {
+    inner: S,
+    header: Option<Vec<u8>>,
+    from: Arc<Mutex<Option<String>>>,
+    lang: Option<String>,
+    tail: Option<Arc<Mutex<VecDeque<u8>>>>,
+    pending: Option<PendingWrite>,
+}
+
+/**************************************************************************
+ * HELPERS
+ * ************************************************************************* */
+
+fn parse_stream_from(header: &[u8]) -> Option<Option<String>> {
+    let header = String::from_utf8_lossy(header);
+
+    // Locate stream header start tag (skipping any XML declaration)
+    // Notice: return none if the start tag was not fully received yet.
+    let start = header.find(":stream ").or(header.find("<stream "))?;
+    let end = start + header[start..].find('>')?;
+    let tag = &header[start..end];
+
+    // Acquire 'from' attribute value (if any)
+    let value = tag
+        .find(" from=")
+        .map(|index| &tag[(index + 6)..])
+        .and_then(|value| {
+            let quote = value.chars().next().filter(|quote| *quote == '\'' || *quote == '"')?;
+
+            value[1..].split(quote).next()
+        });
+
+    Some(value.map(|value| value.to_string()))
+}
+
+fn inject_stream_lang(buf: &[u8], lang: &str) -> Option<Vec<u8>> {
+    // Locate our stream header start tag (if this is the header)
+    let index = buf
+        .windows(HEADER_START_TAG.len())
+        .position(|window| window == HEADER_START_TAG)?
+        + HEADER_START_TAG.len();
+
+    let mut bytes = Vec::with_capacity(buf.len() + lang.len() + 12);
+
+    bytes.extend_from_slice(&buf[..index]);
+    bytes.extend_from_slice(format!(" xml:lang='{}'", lang).as_bytes());
+    bytes.extend_from_slice(&buf[index..]);
+
+    Some(bytes)
+}
+
+/**************************************************************************
+ * IMPLEMENTATIONS
+ * ************************************************************************* */
+
+impl<S> HeaderStream<S> {
+    pub fn new(inner: S, from: Arc<Mutex<Option<String>>>) -> Self {
+        Self {
+            inner,
+            header: Some(Vec::new()),
+            from,
+            lang: None,
+            tail: None,
+            pending: None,
+        }
+    }
+
+    pub fn with_lang(mut self, lang: Option<String>) -> Self {
+        self.lang = lang;
+
+        self
+    }
+
+    pub fn with_tail(mut self, tail: Arc<Mutex<VecDeque<u8>>>) -> Self {
+        self.tail = Some(tail);
+
+        self
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for HeaderStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+        // Remember the last received bytes? (if requested)
+        // Notice: this lets the caller report the bytes that the underlying \
+        //   'tokio-xmpp' stream failed to parse, which it does not expose.
+        if let Some(ref tail) = this.tail {
+            let mut tail = tail.lock().unwrap();
+
+            tail.extend(&buf.filled()[filled..]);
+
+            if tail.len() > RECEIVED_TAIL_SIZE {
+                let excess = tail.len() - RECEIVED_TAIL_SIZE;
+
+                tail.drain(..excess);
+            }
+        }
+
+        // Still sniffing the stream header? (stop once parsed)
+        // Notice: the header is sniffed at the bytes level, since the \
+        //   underlying 'tokio-xmpp' stream does not retain its attributes.
+        if let Some(ref mut header) = this.header {
+            header.extend_from_slice(&buf.filled()[filled..]);
+
+            if let Some(from) = parse_stream_from(header) {
+                *this.from.lock().unwrap() = from;
+
+                this.header = None;
+            } else if header.len() >= HEADER_SIZE_MAXIMUM || buf.filled().len() == filled {
+                this.header = None;
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for HeaderStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // Inject language in our stream header? (if this is a header)
+        // Notice: the underlying 'tokio-xmpp' stream does not support setting \
+        //   the stream language, thus it gets set at the bytes level. This is \
+        //   done for every header, since the stream gets restarted after \
+        //   authenticating (the restarted stream is the one carrying stanzas).
+        let injected = match (this.pending.as_ref(), this.lang.as_deref()) {
+            (None, Some(lang)) => inject_stream_lang(buf, lang),
+            _ => None,
+        };
+
+        if let Some(bytes) = injected {
+            this.pending = Some(PendingWrite {
+                bytes,
+                offset: 0,
+                size: buf.len(),
+            });
+        }
+
+        // Write rewritten bytes first? (then tell that the original bytes \
+        //   were written, as the caller knows nothing about the rewrite)
+        if let Some(pending) = this.pending.as_mut() {
+            while pending.offset < pending.bytes.len() {
+                let remaining = &pending.bytes[pending.offset..];
+                let written = ready!(Pin::new(&mut this.inner).poll_write(cx, remaining))?;
+
+                if written == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+
+                pending.offset += written;
+            }
+
+            let size = pending.size;
+
+            this.pending = None;
+
+            return Poll::Ready(Ok(size));
+        }
+
+        Pin::new(&mut this.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/**************************************************************************
+ * TESTS
+ * ************************************************************************* */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stream_from() {
+        let header = b"<?xml version='1.0'?><stream:stream xmlns='jabber:client' \
+            xmlns:stream='http://etherx.jabber.org/streams' id='abc' \
+            from='prose.org' version='1.0'>";
+
+        assert_eq!(parse_stream_from(header), Some(Some("prose.org".to_string())));
+    }
+
+    #[test]
+    fn test_parse_stream_from_missing() {
+        let header = b"<stream:stream xmlns=\"jabber:client\" id=\"abc\">";
+
+        assert_eq!(parse_stream_from(header), Some(None));
+    }
+
+    #[test]
+    fn test_inject_stream_lang() {
+        let header = b"<?xml version='1.0'?><stream:stream to='prose.org'>";
+
+        assert_eq!(
+            inject_stream_lang(header, "fr").as_deref(),
+            Some(&b"<?xml version='1.0'?><stream:stream xml:lang='fr' to='prose.org'>"[..])
+        );
+        assert_eq!(inject_stream_lang(b"<iq type='get'/>", "fr"), None);
+    }
+
+    #[tokio::test]
+    async fn test_inject_stream_lang_on_restart() {
+        use tokio::io::AsyncWriteExt;
+
+        let mut stream =
+            HeaderStream::new(Vec::new(), Arc::new(Mutex::new(None))).with_lang(Some("fr".into()));
+
+        // Write initial header, then restarted header (after authenticating)
+        stream.write_all(b"<stream:stream to='prose.org'>").await.unwrap();
+        stream.write_all(b"<auth/>").await.unwrap();
+        stream.write_all(b"<stream:stream to='prose.org'>").await.unwrap();
+
+        assert_eq!(
+            stream.get_ref().as_slice(),
+            &b"<stream:stream xml:lang='fr' to='prose.org'><auth/>\
+                <stream:stream xml:lang='fr' to='prose.org'>"[..]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remember_received_tail() {
+        use tokio::io::AsyncReadExt;
+
+        let tail = Arc::new(Mutex::new(VecDeque::new()));
+        let input = [b"<message>".repeat(40), b"<<garbage".to_vec()].concat();
+
+        let mut stream =
+            HeaderStream::new(&input[..], Arc::new(Mutex::new(None))).with_tail(tail.clone());
+
+        stream.read_to_end(&mut Vec::new()).await.unwrap();
+
+        let tail = tail.lock().unwrap();
+
+        assert_eq!(tail.len(), RECEIVED_TAIL_SIZE);
+        assert!(tail.iter().copied().collect::<Vec<_>>().ends_with(b"<<garbage"));
+    }
+
+    #[test]
+    fn test_parse_stream_from_incomplete() {
+        let header = b"<?xml version='1.0'?><stream:stream from='prose.o";
+
+        assert_eq!(parse_stream_from(header), None);
+    }
+}
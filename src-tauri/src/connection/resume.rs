@@ -0,0 +1,507 @@
+// This file is part of prose-app-web
+//
+// Copyright 2024, Prose Foundation
+
+/**************************************************************************
+ * IMPORTS
+ * ************************************************************************* */
+
+use minidom::Element;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/**************************************************************************
+ * CONSTANTS
+ * ************************************************************************* */
+
+const NS_SM: &'static str = "urn:xmpp:sm:3";
+
+const READ_CHUNK_SIZE: usize = 4096;
+const HELD_SIZE_MAXIMUM: usize = 65536;
+
+const SASL_SUCCESS_TAG: &'static str = "<success";
+const SASL_FAILURE_TAG: &'static str = "<failure";
+
+const FEATURES_END_TAGS: [&'static str; 2] = ["</stream:features>", "<stream:features/>"];
+
+/**************************************************************************
+ * ENUMERATIONS
+ * ************************************************************************* */
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ResumeOutcome {
+    Resumed(Option<u32>),
+    Failed(Option<u32>),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum ResumePhase {
+    Authenticating,
+    Restarting,
+    Resuming,
+    Done,
+}
+
+/**************************************************************************
+ * STRUCTURES
+ * ************************************************************************* */
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ResumeRequest {
+    pub previd: String,
+    pub handled: u32,
+}
+
+#[derive(Debug)]
+pub struct ResumeStream<S> {
+    inner: S,
+    request: Option<ResumeRequest>,
+    outcome: Arc<Mutex<Option<ResumeOutcome>>>,
+    phase: ResumePhase,
+    held: Vec<u8>,
+    features: Vec<u8>,
+    request_bytes: Option<(Vec<u8>, usize)>,
+    release: Vec<u8>,
+    released: usize,
+}
+
+/**************************************************************************
+ * HELPERS
+ * ************************************************************************* */
+
+fn find(haystack: &[u8], needle: &str) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle.as_bytes())
+}
+
+fn find_tag(haystack: &[u8], name: &str) -> Option<usize> {
+    // Notice: the tag name must end there, so that eg. '<bind' does not \
+    //   match '<binding'.
+    let tag = format!("<{}", name);
+
+    let mut offset = 0;
+
+    while let Some(index) = find(&haystack[offset..], &tag) {
+        let start = offset + index;
+
+        match haystack.get(start + tag.len()) {
+            Some(b' ' | b'/' | b'>' | b'\t' | b'\r' | b'\n') => return Some(start),
+            Some(_) => offset = start + tag.len(),
+            None => return None,
+        }
+    }
+
+    None
+}
+
+fn find_element(haystack: &[u8], name: &str) -> Option<(usize, usize)> {
+    // Locate element start tag, then its end (self-closing or not)
+    // Notice: return none if the element was not fully received yet.
+    let start = find_tag(haystack, name)?;
+    let tag_end = start + haystack[start..].iter().position(|byte| *byte == b'>')?;
+
+    if haystack[tag_end - 1] == b'/' {
+        return Some((start, tag_end + 1));
+    }
+
+    let close = format!("</{}>", name);
+    let end = tag_end + find(&haystack[tag_end..], &close)? + close.len();
+
+    Some((start, end))
+}
+
+fn parse_handled(element: &[u8]) -> Option<u32> {
+    let element = String::from_utf8_lossy(element);
+    let tag = &element[..element.find('>')?];
+
+    tag.find(" h=")
+        .map(|index| &tag[(index + 3)..])
+        .and_then(|value| {
+            let quote = value.chars().next().filter(|quote| *quote == '\'' || *quote == '"')?;
+
+            value[1..].split(quote).next()
+        })
+        .and_then(|value| value.parse().ok())
+}
+
+fn strip_element(bytes: &mut Vec<u8>, name: &str) {
+    if let Some((start, end)) = find_element(bytes, name) {
+        bytes.drain(start..end);
+    }
+}
+
+fn make_resume_bytes(request: &ResumeRequest) -> Vec<u8> {
+    let element = Element::builder("resume", NS_SM)
+        .attr("h", request.handled.to_string())
+        .attr("previd", &request.previd)
+        .build();
+
+    String::from(&element).into_bytes()
+}
+
+/**************************************************************************
+ * IMPLEMENTATIONS
+ * ************************************************************************* */
+
+impl<S> ResumeStream<S> {
+    pub fn new(
+        inner: S,
+        request: Option<ResumeRequest>,
+        outcome: Arc<Mutex<Option<ResumeOutcome>>>,
+    ) -> Self {
+        let phase = if request.is_some() {
+            ResumePhase::Authenticating
+        } else {
+            ResumePhase::Done
+        };
+
+        Self {
+            inner,
+            request,
+            outcome,
+            phase,
+            held: Vec::new(),
+            features: Vec::new(),
+            request_bytes: None,
+            release: Vec::new(),
+            released: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    fn finish(&mut self, outcome: Option<ResumeOutcome>, rest: Vec<u8>) {
+        // Release held bytes (features first, then anything that followed)
+        self.release = std::mem::take(&mut self.features);
+        self.release.extend(rest);
+        self.released = 0;
+
+        self.held.clear();
+        self.phase = ResumePhase::Done;
+
+        *self.outcome.lock().unwrap() = outcome;
+    }
+
+    fn handle_features(&mut self) {
+        // Stream features not fully received yet?
+        let Some(end) = FEATURES_END_TAGS
+            .iter()
+            .filter_map(|tag| find(&self.held, tag).map(|index| index + tag.len()))
+            .min()
+        else {
+            return;
+        };
+
+        let rest = self.held.split_off(end);
+
+        self.features = std::mem::take(&mut self.held);
+        self.held = rest;
+
+        // Server cannot resume streams there? (bind as usual)
+        // Notice: the caller is told that resuming failed, so that it \
+        //   knows that the bound stream is a new one.
+        let supported = find_tag(&self.features, "sm")
+            .and_then(|start| find(&self.features[start..], NS_SM))
+            .is_some();
+
+        match (supported, self.request.take()) {
+            (true, Some(request)) => {
+                self.request_bytes = Some((make_resume_bytes(&request), 0));
+                self.phase = ResumePhase::Resuming;
+            }
+            _ => {
+                let rest = std::mem::take(&mut self.held);
+
+                self.finish(Some(ResumeOutcome::Failed(None)), rest);
+            }
+        }
+    }
+
+    fn handle_answer(&mut self) {
+        // Resumption answer not fully received yet?
+        let answer = find_element(&self.held, "resumed")
+            .map(|range| (range, true))
+            .or_else(|| find_element(&self.held, "failed").map(|range| (range, false)));
+
+        let Some(((start, end), resumed)) = answer else {
+            return;
+        };
+
+        let handled = parse_handled(&self.held[start..end]);
+        let rest = self.held.split_off(end);
+
+        // Stream resumed? (then binding must not happen)
+        // Notice: the underlying 'tokio-xmpp' client only binds if the \
+        //   server advertises binding, thus it gets hidden from the stream \
+        //   features. The answer itself is consumed there, as the client \
+        //   would not expect it.
+        if resumed {
+            strip_element(&mut self.features, "bind");
+
+            self.finish(Some(ResumeOutcome::Resumed(handled)), rest);
+        } else {
+            self.finish(Some(ResumeOutcome::Failed(handled)), rest);
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for ResumeStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            // Release held bytes first (if any)
+            if this.released < this.release.len() {
+                let remaining = &this.release[this.released..];
+                let size = remaining.len().min(buf.remaining());
+
+                buf.put_slice(&remaining[..size]);
+
+                this.released += size;
+
+                if this.released == this.release.len() {
+                    this.release = Vec::new();
+                    this.released = 0;
+                }
+
+                return Poll::Ready(Ok(()));
+            }
+
+            match this.phase {
+                ResumePhase::Done => return Pin::new(&mut this.inner).poll_read(cx, buf),
+                ResumePhase::Authenticating => {
+                    let filled = buf.filled().len();
+
+                    ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+                    // Authenticated? (the restarted stream comes next)
+                    // Notice: only the last bytes are kept around, as the \
+                    //   SASL answer tag might be split over multiple reads.
+                    this.held.extend_from_slice(&buf.filled()[filled..]);
+
+                    if find(&this.held, SASL_SUCCESS_TAG).is_some() {
+                        this.held.clear();
+                        this.phase = ResumePhase::Restarting;
+                    } else if find(&this.held, SASL_FAILURE_TAG).is_some()
+                        || buf.filled().len() == filled
+                    {
+                        this.held.clear();
+                        this.phase = ResumePhase::Done;
+                    } else if this.held.len() > SASL_SUCCESS_TAG.len() {
+                        let excess = this.held.len() - SASL_SUCCESS_TAG.len();
+
+                        this.held.drain(..excess);
+                    }
+
+                    return Poll::Ready(Ok(()));
+                }
+                ResumePhase::Restarting | ResumePhase::Resuming => {
+                    // Write resumption request? (once features are received)
+                    if let Some((ref bytes, ref mut offset)) = this.request_bytes {
+                        while *offset < bytes.len() {
+                            let remaining = &bytes[*offset..];
+                            let written =
+                                ready!(Pin::new(&mut this.inner).poll_write(cx, remaining))?;
+
+                            if written == 0 {
+                                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                            }
+
+                            *offset += written;
+                        }
+
+                        ready!(Pin::new(&mut this.inner).poll_flush(cx))?;
+
+                        this.request_bytes = None;
+                    }
+
+                    // Hold received bytes, until the resumption answer is known
+                    // Notice: the stream features get held as well, since \
+                    //   they might have to be rewritten.
+                    let mut chunk = [0; READ_CHUNK_SIZE];
+                    let mut read = ReadBuf::new(&mut chunk);
+
+                    ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read))?;
+
+                    if read.filled().is_empty() || this.held.len() >= HELD_SIZE_MAXIMUM {
+                        let rest = std::mem::take(&mut this.held);
+
+                        this.finish(None, [rest, read.filled().to_vec()].concat());
+
+                        continue;
+                    }
+
+                    this.held.extend_from_slice(read.filled());
+
+                    if this.phase == ResumePhase::Restarting {
+                        this.handle_features();
+                    }
+
+                    if this.phase == ResumePhase::Resuming && this.request_bytes.is_none() {
+                        this.handle_answer();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ResumeStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/**************************************************************************
+ * TESTS
+ * ************************************************************************* */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEATURES: &'static [u8] = b"<stream:stream xmlns='jabber:client' id='b'>\
+        <stream:features><bind xmlns='urn:ietf:params:xml:ns:xmpp-bind'/>\
+        <sm xmlns='urn:xmpp:sm:3'/></stream:features>";
+
+    #[test]
+    fn test_find_element() {
+        assert_eq!(find_element(b"<a/><resumed h='3'/>", "resumed"), Some((4, 20)));
+        assert_eq!(find_element(b"<failed h='1'><x/></failed>", "failed"), Some((0, 27)));
+        assert_eq!(find_element(b"<binding/><bind/>", "bind"), Some((10, 17)));
+        assert_eq!(find_element(b"<failed h='1'><x/>", "failed"), None);
+    }
+
+    #[test]
+    fn test_parse_handled() {
+        assert_eq!(parse_handled(b"<resumed xmlns='urn:xmpp:sm:3' h='42'/>"), Some(42));
+        assert_eq!(parse_handled(b"<failed xmlns=\"urn:xmpp:sm:3\" h=\"7\">"), Some(7));
+        assert_eq!(parse_handled(b"<failed xmlns='urn:xmpp:sm:3'/>"), None);
+    }
+
+    async fn resume_with(answer: &[u8]) -> (Vec<u8>, Option<ResumeOutcome>, Vec<u8>) {
+        use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+        let (client, mut server) = duplex(READ_CHUNK_SIZE);
+        let outcome = Arc::new(Mutex::new(None));
+
+        let request = ResumeRequest {
+            previd: "some-id".to_string(),
+            handled: 5,
+        };
+
+        let mut stream = ResumeStream::new(client, Some(request), outcome.clone());
+
+        server
+            .write_all(b"<success xmlns='urn:ietf:params:xml:ns:xmpp-sasl'/>")
+            .await
+            .unwrap();
+
+        let mut received = vec![0; READ_CHUNK_SIZE];
+        let size = stream.read(&mut received).await.unwrap();
+
+        assert!(received[..size].starts_with(b"<success"));
+
+        // Server sends restarted stream features, then answers resumption
+        server.write_all(FEATURES).await.unwrap();
+
+        let reader = tokio::spawn(async move {
+            let mut received = vec![0; READ_CHUNK_SIZE];
+            let size = stream.read(&mut received).await.unwrap();
+
+            received.truncate(size);
+            received
+        });
+
+        let mut request = vec![0; READ_CHUNK_SIZE];
+        let size = server.read(&mut request).await.unwrap();
+
+        request.truncate(size);
+
+        server.write_all(answer).await.unwrap();
+
+        let received = reader.await.unwrap();
+        let outcome = *outcome.lock().unwrap();
+
+        (request, outcome, received)
+    }
+
+    #[tokio::test]
+    async fn test_resume_stream_resumed() {
+        let (request, outcome, received) =
+            resume_with(b"<resumed xmlns='urn:xmpp:sm:3' h='2' previd='some-id'/><message/>")
+                .await;
+
+        let request = String::from_utf8(request).unwrap();
+
+        assert!(request.starts_with("<resume "));
+        assert!(request.contains("h=\"5\""));
+        assert!(request.contains("previd=\"some-id\""));
+
+        assert_eq!(outcome, Some(ResumeOutcome::Resumed(Some(2))));
+
+        // Binding is hidden, resumption answer is consumed
+        let received = String::from_utf8(received).unwrap();
+
+        assert!(!received.contains("<bind"));
+        assert!(!received.contains("<resumed"));
+        assert!(received.ends_with("</stream:features><message/>"));
+    }
+
+    #[tokio::test]
+    async fn test_resume_stream_failed() {
+        let (_, outcome, received) = resume_with(
+            b"<failed xmlns='urn:xmpp:sm:3' h='4'>\
+                <item-not-found xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/></failed>",
+        )
+        .await;
+
+        assert_eq!(outcome, Some(ResumeOutcome::Failed(Some(4))));
+
+        // Binding is still advertised, failure answer is consumed
+        assert_eq!(received, FEATURES);
+    }
+
+    #[tokio::test]
+    async fn test_resume_stream_passthrough() {
+        use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+        let (client, mut server) = duplex(READ_CHUNK_SIZE);
+        let outcome = Arc::new(Mutex::new(None));
+
+        let mut stream = ResumeStream::new(client, None, outcome.clone());
+
+        server.write_all(FEATURES).await.unwrap();
+
+        drop(server);
+
+        let mut received = Vec::new();
+
+        stream.read_to_end(&mut received).await.unwrap();
+
+        assert_eq!(received, FEATURES);
+        assert_eq!(*outcome.lock().unwrap(), None);
+    }
+}
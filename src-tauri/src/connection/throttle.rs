@@ -0,0 +1,165 @@
+// This file is part of prose-app-web
+//
+// Copyright 2024, Prose Foundation
+
+/**************************************************************************
+ * IMPORTS
+ * ************************************************************************* */
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{self, Sleep};
+
+/**************************************************************************
+ * CONSTANTS
+ * ************************************************************************* */
+
+const WAIT_MINIMUM_MILLISECONDS: u64 = 10;
+
+/**************************************************************************
+ * STRUCTURES
+ * ************************************************************************* */
+
+#[derive(Debug)]
+struct RateLimiter {
+    rate: u64,
+    allowance: u64,
+    refilled_at: Instant,
+    wait: Option<Pin<Box<Sleep>>>,
+}
+
+#[derive(Debug)]
+pub struct ThrottledStream<S> {
+    inner: S,
+    read_limiter: Option<RateLimiter>,
+    write_limiter: Option<RateLimiter>,
+}
+
+/**************************************************************************
+ * IMPLEMENTATIONS
+ * ************************************************************************* */
+
+impl RateLimiter {
+    fn new(rate: u64) -> Self {
+        let rate = rate.max(1);
+
+        Self {
+            rate,
+            allowance: rate,
+            refilled_at: Instant::now(),
+            wait: None,
+        }
+    }
+
+    fn poll_allowance(&mut self, cx: &mut Context<'_>) -> Poll<usize> {
+        loop {
+            // Still waiting for allowance to refill?
+            if let Some(wait) = self.wait.as_mut() {
+                ready!(wait.as_mut().poll(cx));
+
+                self.wait = None;
+            }
+
+            // Refill allowance with bytes earned since last refill
+            // Notice: allowance is capped to one second worth of bytes, so \
+            //   that a stream that stayed idle for a while cannot burst.
+            let earned = (self.refilled_at.elapsed().as_secs_f64() * self.rate as f64) as u64;
+
+            if earned > 0 {
+                self.allowance = (self.allowance + earned).min(self.rate);
+                self.refilled_at = Instant::now();
+            }
+
+            if self.allowance > 0 {
+                return Poll::Ready(self.allowance as usize);
+            }
+
+            // No allowance left, wait until at least one byte is earned
+            let wait = Duration::from_secs_f64(1.0 / self.rate as f64)
+                .max(Duration::from_millis(WAIT_MINIMUM_MILLISECONDS));
+
+            self.wait = Some(Box::pin(time::sleep(wait)));
+        }
+    }
+
+    fn consume(&mut self, size: usize) {
+        self.allowance = self.allowance.saturating_sub(size as u64);
+    }
+}
+
+impl<S> ThrottledStream<S> {
+    pub fn new(inner: S, read_rate: Option<u64>, write_rate: Option<u64>) -> Self {
+        Self {
+            inner,
+            read_limiter: read_rate.map(RateLimiter::new),
+            write_limiter: write_rate.map(RateLimiter::new),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ThrottledStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        // Not throttled? (pass through)
+        let limiter = match this.read_limiter {
+            Some(ref mut limiter) if buf.remaining() > 0 => limiter,
+            _ => return Pin::new(&mut this.inner).poll_read(cx, buf),
+        };
+
+        // Read at most the allowed amount of bytes
+        // Notice: this reads right into the unfilled part of the buffer, \
+        //   meaning that no intermediate buffer gets allocated nor copied.
+        let size = ready!(limiter.poll_allowance(cx)).min(buf.remaining());
+        let mut chunk_buf = ReadBuf::new(buf.initialize_unfilled_to(size));
+
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+
+        let read = chunk_buf.filled().len();
+
+        limiter.consume(read);
+        buf.advance(read);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ThrottledStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // Not throttled? (pass through)
+        let limiter = match this.write_limiter {
+            Some(ref mut limiter) if !buf.is_empty() => limiter,
+            _ => return Pin::new(&mut this.inner).poll_write(cx, buf),
+        };
+
+        // Write at most the allowed amount of bytes
+        let size = ready!(limiter.poll_allowance(cx)).min(buf.len());
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..size]))?;
+
+        limiter.consume(written);
+
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
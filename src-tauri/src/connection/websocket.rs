@@ -0,0 +1,468 @@
+// This file is part of prose-app-web
+//
+// Copyright 2024, Prose Foundation
+
+/**************************************************************************
+ * IMPORTS
+ * ************************************************************************* */
+
+use futures::{Sink, Stream};
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/**************************************************************************
+ * CONSTANTS
+ * ************************************************************************* */
+
+const NS_FRAMING: &'static str = "urn:ietf:params:xml:ns:xmpp-framing";
+const NS_CLIENT: &'static str = "jabber:client";
+const NS_STREAMS: &'static str = "http://etherx.jabber.org/streams";
+
+const STREAM_HEADER_TAG: &'static str = "<stream:stream";
+const STREAM_FOOTER_TAG: &'static str = "</stream:stream>";
+
+/**************************************************************************
+ * ENUMERATIONS
+ * ************************************************************************* */
+
+#[derive(Debug, Eq, PartialEq)]
+enum OutgoingFrame {
+    Open(String),
+    Element(String),
+    Close,
+}
+
+/**************************************************************************
+ * STRUCTURES
+ * ************************************************************************* */
+
+// Notice: this adapts an RFC 7395 WebSocket framing to the usual XMPP \
+//   stream framing, since the underlying 'tokio-xmpp' client only speaks \
+//   the latter. Each WebSocket message holds one complete element, and the \
+//   stream header and footer are replaced with '<open/>' and '<close/>'.
+#[derive(Debug)]
+pub struct WebSocketFraming<S> {
+    inner: WebSocketStream<S>,
+    read: Vec<u8>,
+    read_offset: usize,
+    written: Vec<u8>,
+    outgoing: VecDeque<String>,
+    ended: bool,
+}
+
+/**************************************************************************
+ * HELPERS
+ * ************************************************************************* */
+
+fn find(haystack: &[u8], needle: &str) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle.as_bytes())
+}
+
+fn find_tag_end(tag: &[u8]) -> Option<usize> {
+    // Notice: attribute values may hold '>' characters, thus quotes must \
+    //   be skipped over.
+    let mut quote = None;
+
+    for (index, byte) in tag.iter().enumerate() {
+        match (quote, *byte) {
+            (None, b'>') => return Some(index),
+            (None, b'\'' | b'"') => quote = Some(*byte),
+            (Some(current), byte) if current == byte => quote = None,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn is_tag(tag: &str, name: &str) -> bool {
+    tag.strip_prefix(name)
+        .and_then(|rest| rest.chars().next())
+        .is_some_and(|next| next.is_whitespace() || next == '/' || next == '>')
+}
+
+fn make_attributes(tag: &str) -> String {
+    let mut attributes = String::new();
+
+    // Skip tag name, then copy all attributes (except namespace ones)
+    // Notice: values are copied as-is, as they are already escaped.
+    let mut rest = tag
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .trim_end_matches('/')
+        .trim_start_matches(|character: char| !character.is_whitespace());
+
+    while let Some(equals) = rest.find('=') {
+        let name = rest[..equals].trim();
+        let value = rest[(equals + 1)..].trim_start();
+
+        let Some(quote) = value.chars().next().filter(|quote| *quote == '\'' || *quote == '"')
+        else {
+            break;
+        };
+        let Some(end) = value[1..].find(quote) else {
+            break;
+        };
+
+        if name != "xmlns" && !name.starts_with("xmlns:") {
+            attributes.push_str(&format!(" {}={}", name, &value[..(end + 2)]));
+        }
+
+        rest = &value[(end + 2)..];
+    }
+
+    attributes
+}
+
+fn take_outgoing_frames(buffer: &mut Vec<u8>) -> Vec<OutgoingFrame> {
+    let mut frames = Vec::new();
+
+    let (mut index, mut consumed, mut depth, mut start) = (0, 0, 0, 0);
+
+    // Split written bytes into top-level elements (streams are not nested)
+    // Notice: incomplete elements are left in the buffer, and get scanned \
+    //   again from their start (at depth zero) once more bytes come in. \
+    //   Text in-between top-level elements gets dropped (eg. whitespace \
+    //   keep-alives), as it must not be sent over WebSocket.
+    while index < buffer.len() {
+        if buffer[index] != b'<' {
+            index += 1;
+
+            if depth == 0 {
+                consumed = index;
+            }
+
+            continue;
+        }
+
+        let rest = &buffer[index..];
+
+        // Skip over declarations, comments and CDATA sections
+        if rest.starts_with(b"<?") || rest.starts_with(b"<!") {
+            let terminator = if rest.starts_with(b"<?") {
+                "?>"
+            } else if rest.starts_with(b"<!--") {
+                "-->"
+            } else {
+                "]]>"
+            };
+
+            let Some(end) = find(rest, terminator) else {
+                break;
+            };
+
+            index += end + terminator.len();
+
+            if depth == 0 {
+                consumed = index;
+            }
+
+            continue;
+        }
+
+        let Some(end) = find_tag_end(rest) else {
+            break;
+        };
+
+        let next = index + end + 1;
+        let tag = String::from_utf8_lossy(&rest[..(end + 1)]);
+
+        if tag.starts_with("</") {
+            if depth == 0 {
+                // Closing stream footer (at the top level)
+                if tag.trim_end() == STREAM_FOOTER_TAG {
+                    frames.push(OutgoingFrame::Close);
+                }
+
+                consumed = next;
+            } else {
+                depth -= 1;
+
+                if depth == 0 {
+                    frames.push(OutgoingFrame::Element(
+                        String::from_utf8_lossy(&buffer[start..next]).into_owned(),
+                    ));
+
+                    consumed = next;
+                }
+            }
+        } else if depth == 0 && is_tag(&tag, STREAM_HEADER_TAG) {
+            frames.push(OutgoingFrame::Open(make_attributes(&tag)));
+
+            consumed = next;
+        } else if tag.ends_with("/>") {
+            if depth == 0 {
+                frames.push(OutgoingFrame::Element(tag.into_owned()));
+
+                consumed = next;
+            }
+        } else {
+            if depth == 0 {
+                start = index;
+            }
+
+            depth += 1;
+        }
+
+        index = next;
+    }
+
+    buffer.drain(..consumed);
+
+    frames
+}
+
+fn make_outgoing_message(frame: OutgoingFrame) -> String {
+    match frame {
+        OutgoingFrame::Open(attributes) => {
+            format!("<open xmlns='{}'{}/>", NS_FRAMING, attributes)
+        }
+        OutgoingFrame::Element(element) => element,
+        OutgoingFrame::Close => format!("<close xmlns='{}'/>", NS_FRAMING),
+    }
+}
+
+fn make_incoming_bytes(message: &str) -> Vec<u8> {
+    let trimmed = message.trim();
+
+    // Translate framing elements? (to stream header and footer)
+    // Notice: stanzas are parsed in the context of the stream header, \
+    //   which is why the header declares the default client namespace.
+    if trimmed.contains(NS_FRAMING) {
+        if is_tag(trimmed, "<open") {
+            return format!(
+                "<stream:stream xmlns='{}' xmlns:stream='{}'{}>",
+                NS_CLIENT,
+                NS_STREAMS,
+                make_attributes(trimmed)
+            )
+            .into_bytes();
+        }
+
+        if is_tag(trimmed, "<close") {
+            return STREAM_FOOTER_TAG.as_bytes().to_vec();
+        }
+    }
+
+    message.as_bytes().to_vec()
+}
+
+fn make_io_error<E: std::error::Error + Send + Sync + 'static>(err: E) -> io::Error {
+    io::Error::other(err)
+}
+
+/**************************************************************************
+ * IMPLEMENTATIONS
+ * ************************************************************************* */
+
+impl<S> WebSocketFraming<S> {
+    pub fn new(inner: WebSocketStream<S>) -> Self {
+        Self {
+            inner,
+            read: Vec::new(),
+            read_offset: 0,
+            written: Vec::new(),
+            outgoing: VecDeque::new(),
+            ended: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        self.inner.get_ref()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WebSocketFraming<S> {
+    fn poll_send_outgoing(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.outgoing.is_empty() {
+            ready!(Pin::new(&mut self.inner).poll_ready(cx)).map_err(make_io_error)?;
+
+            if let Some(message) = self.outgoing.pop_front() {
+                Pin::new(&mut self.inner)
+                    .start_send(Message::Text(message))
+                    .map_err(make_io_error)?;
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketFraming<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            // Hand over the last received message first (if any left)
+            if this.read_offset < this.read.len() {
+                let remaining = &this.read[this.read_offset..];
+                let size = remaining.len().min(buf.remaining());
+
+                buf.put_slice(&remaining[..size]);
+
+                this.read_offset += size;
+
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.ended {
+                return Poll::Ready(Ok(()));
+            }
+
+            // Notice: control messages (eg. pings) are handled by the \
+            //   WebSocket stream itself, thus they get ignored there.
+            let message = match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(Message::Binary(bytes))) => String::from_utf8_lossy(&bytes).into_owned(),
+                Some(Ok(Message::Close(_))) | None => {
+                    this.ended = true;
+
+                    continue;
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => return Poll::Ready(Err(make_io_error(err))),
+            };
+
+            this.read = make_incoming_bytes(&message);
+            this.read_offset = 0;
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketFraming<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // Notice: previous messages must be sent first, so that a slow \
+        //   WebSocket applies back-pressure on the writer.
+        ready!(this.poll_send_outgoing(cx))?;
+
+        this.written.extend_from_slice(buf);
+
+        for frame in take_outgoing_frames(&mut this.written) {
+            this.outgoing.push_back(make_outgoing_message(frame));
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        ready!(this.poll_send_outgoing(cx))?;
+
+        Pin::new(&mut this.inner).poll_flush(cx).map_err(make_io_error)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        ready!(this.poll_send_outgoing(cx))?;
+
+        Pin::new(&mut this.inner).poll_close(cx).map_err(make_io_error)
+    }
+}
+
+/**************************************************************************
+ * TESTS
+ * ************************************************************************* */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_outgoing_frames() {
+        let mut buffer = b"<?xml version='1.0'?>\n<stream:stream xmlns='jabber:client' \
+            xmlns:stream='http://etherx.jabber.org/streams' to='prose.org' version='1.0'>\
+            <auth xmlns='urn:ietf:params:xml:ns:xmpp-sasl' mechanism='PLAIN'>AGE=</auth> \
+            <presence/><message to='a@prose.org'><body>1 > 0 <![CDATA[<]]></body>"
+            .to_vec();
+
+        assert_eq!(
+            take_outgoing_frames(&mut buffer),
+            vec![
+                OutgoingFrame::Open(" to='prose.org' version='1.0'".to_string()),
+                OutgoingFrame::Element(
+                    "<auth xmlns='urn:ietf:params:xml:ns:xmpp-sasl' mechanism='PLAIN'>\
+                        AGE=</auth>"
+                        .to_string()
+                ),
+                OutgoingFrame::Element("<presence/>".to_string()),
+            ]
+        );
+
+        // Incomplete message is kept, until it gets completed
+        assert!(buffer.starts_with(b"<message"));
+
+        buffer.extend_from_slice(b"</message></stream:stream>");
+
+        assert_eq!(
+            take_outgoing_frames(&mut buffer),
+            vec![
+                OutgoingFrame::Element(
+                    "<message to='a@prose.org'><body>1 > 0 <![CDATA[<]]></body></message>"
+                        .to_string()
+                ),
+                OutgoingFrame::Close,
+            ]
+        );
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_take_outgoing_frames_quoted() {
+        let mut buffer = b"<iq id='a>b' type=\"get\"/>".to_vec();
+
+        assert_eq!(
+            take_outgoing_frames(&mut buffer),
+            vec![OutgoingFrame::Element("<iq id='a>b' type=\"get\"/>".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_make_outgoing_message() {
+        assert_eq!(
+            make_outgoing_message(OutgoingFrame::Open(" to='prose.org'".to_string())),
+            "<open xmlns='urn:ietf:params:xml:ns:xmpp-framing' to='prose.org'/>"
+        );
+        assert_eq!(
+            make_outgoing_message(OutgoingFrame::Close),
+            "<close xmlns='urn:ietf:params:xml:ns:xmpp-framing'/>"
+        );
+    }
+
+    #[test]
+    fn test_make_incoming_bytes() {
+        assert_eq!(
+            make_incoming_bytes(
+                "<open xmlns=\"urn:ietf:params:xml:ns:xmpp-framing\" from=\"prose.org\" \
+                    id=\"abc\" version=\"1.0\" xml:lang=\"en\"/>"
+            ),
+            b"<stream:stream xmlns='jabber:client' \
+                xmlns:stream='http://etherx.jabber.org/streams' from=\"prose.org\" \
+                id=\"abc\" version=\"1.0\" xml:lang=\"en\">"
+                .to_vec()
+        );
+        assert_eq!(
+            make_incoming_bytes("<close xmlns='urn:ietf:params:xml:ns:xmpp-framing'/>"),
+            b"</stream:stream>".to_vec()
+        );
+        assert_eq!(make_incoming_bytes("<presence/>"), b"<presence/>".to_vec());
+    }
+}
@@ -11,6 +11,33 @@ use tauri::plugin::Plugin;
 use tauri::Runtime;
 use tauri_plugin_log::{RotationStrategy, Target, TargetKind, TimezoneStrategy};
 
+use crate::connection;
+
+/**************************************************************************
+ * CONSTANTS
+ * ************************************************************************* */
+
+// Notice: filter which logs ultimately get ingested there (based on the \
+//   environment)
+#[cfg(dev)]
+const LOG_LEVEL: LevelFilter = LevelFilter::Debug;
+
+#[cfg(not(dev))]
+const LOG_LEVEL: LevelFilter = LevelFilter::Error;
+
+/**************************************************************************
+ * HELPERS
+ * ************************************************************************* */
+
+pub fn refresh_max_level() {
+    // Notice: the maximum log level gates all log sites, thus it only gets \
+    //   raised while a connection log level override is more verbose than \
+    //   the global log level, and restored once no such override remains.
+    let max_level = connection::log_level_max().map_or(LOG_LEVEL, |level| level.max(LOG_LEVEL));
+
+    log::set_max_level(max_level);
+}
+
 /**************************************************************************
  * PROVIDERS
  * ************************************************************************* */
@@ -20,29 +47,36 @@ pub fn provide<R: Runtime>() -> impl Plugin<R> {
         time::format_description::parse("[[[year]-[month]-[day]][[[hour]:[minute]:[second]]")
             .unwrap();
 
-    // Notice: filter which logs ultimately get ingested there (based on the \
-    //   environment)
-    #[cfg(dev)]
-    let log_level = LevelFilter::Debug;
-
-    #[cfg(not(dev))]
-    let log_level = LevelFilter::Error;
-
     tauri_plugin_log::Builder::default()
         .rotation_strategy(RotationStrategy::KeepOne)
         .targets([
             Target::new(TargetKind::LogDir { file_name: None }),
             Target::new(TargetKind::Stdout),
         ])
-        .level(log_level)
+        .level(LOG_LEVEL)
+        // Notice: connection logs are let through there whatever their level, \
+        //   as connections might have their own log level, which takes \
+        //   precedence over the global log level (this is used to debug a \
+        //   single connection). The filter below enforces it.
+        .level_for(connection::LOG_TARGET, LevelFilter::Trace)
+        .filter(|metadata| {
+            metadata.level() <= connection::log_level_for(metadata.target()).unwrap_or(LOG_LEVEL)
+        })
         .format(move |out, message, record| {
+            // Notice: connection logs get prefixed with their connection \
+            //   label (if any), as connection identifiers are hard to read.
+            let label = connection::log_label_for(record.target())
+                .map(|label| format!("[{}] ", label))
+                .unwrap_or_default();
+
             out.finish(format_args!(
-                "{}[{}] {}",
+                "{}[{}] {}{}",
                 TimezoneStrategy::UseUtc
                     .get_now()
                     .format(&time_format)
                     .unwrap(),
                 record.level(),
+                label,
                 message
             ))
         })
@@ -82,7 +82,7 @@ async fn main() {
 
     // Mount all internal plugins
     builder = builder
-        .plugin(connection::provide())
+        .plugin(connection::provide(connection::ConnectionSettings::default()))
         .plugin(download::provide())
         .plugin(notifications::provide())
         .plugin(logger::provide());
@@ -123,6 +123,11 @@ async fn main() {
 
     // Setup application
     builder = builder.setup(|app| {
+        // Restrict the maximum log level to the global log level
+        // Notice: the log plugin raises it to its most verbose target level \
+        //   upon initialization, which would enable all connection log sites.
+        logger::refresh_max_level();
+
         let window = app.get_webview_window("main").unwrap();
 
         // Apply vibrancy on window (macOS only)
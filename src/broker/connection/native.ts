@@ -22,6 +22,7 @@ import BrokerConnection from "@/broker/connection/index";
 import {
   default as UtilitiesRuntime,
   RuntimeConnectionState,
+  RuntimeConnectionStateDetail,
   RuntimeConnectionID
 } from "@/utilities/runtime";
 import logger from "@/utilities/logger";
@@ -112,16 +113,37 @@ class BrokerConnectionNativeTauri
 
     // Register all connection handlers
     UtilitiesRuntime.registerConnectionHandlers(connectionId, {
-      state: (state: RuntimeConnectionState) => {
+      state: (
+        state: RuntimeConnectionState,
+        _detail?: RuntimeConnectionStateDetail,
+        _error?: string,
+        jid?: string
+      ) => {
         switch (state) {
+          case RuntimeConnectionState.Secured: {
+            logger.info("Broker secured connection (authenticating)");
+
+            break;
+          }
+
           case RuntimeConnectionState.Connected: {
-            logger.info("Broker connected");
+            // Notice: the bound JID is the full JID assigned by the server, \
+            //   which might differ from the requested one (eg. its resource).
+            logger.info(`Broker connected (bound on: ${jid || "unknown"})`);
 
             handlers.succeed();
 
             break;
           }
 
+          case RuntimeConnectionState.Resumed: {
+            // Notice: the previous stream was resumed after reconnecting, \
+            //   meaning that its session was kept (nothing was missed).
+            logger.info(`Broker resumed (bound on: ${jid || "unknown"})`);
+
+            break;
+          }
+
           case RuntimeConnectionState.Disconnected: {
             logger.warn("Broker disconnected");
 
@@ -164,6 +186,22 @@ class BrokerConnectionNativeTauri
 
             break;
           }
+
+          case RuntimeConnectionState.ResolutionFailure: {
+            logger.error("Broker resolution failure");
+
+            handlers.fail(ProseConnectionErrorType.Generic);
+
+            break;
+          }
+
+          case RuntimeConnectionState.ReauthRequired: {
+            // Notice: a disconnected state follows, from which the client \
+            //   reconnects with its stored credentials (no error to show).
+            logger.warn("Broker re-authentication required");
+
+            break;
+          }
         }
       },
 
@@ -93,8 +93,14 @@ enum RuntimeNotificationInteractionAction {
 }
 
 enum RuntimeConnectionState {
+  // Secured state.
+  Secured = "secured",
   // Connected state.
   Connected = "connected",
+  // Resumed state.
+  Resumed = "resumed",
+  // Reconnecting state.
+  Reconnecting = "reconnecting",
   // Disconnected state.
   Disconnected = "disconnected",
   // Authentication failure state.
@@ -102,7 +108,36 @@ enum RuntimeConnectionState {
   // Connection timeout state.
   ConnectionTimeout = "connection-timeout",
   // Connection error state.
-  ConnectionError = "connection-error"
+  ConnectionError = "connection-error",
+  // Resolution failure state.
+  ResolutionFailure = "resolution-failure",
+  // Re-authentication required state.
+  ReauthRequired = "reauth-required"
+}
+
+enum RuntimeConnectionStateReason {
+  // Bad credentials reason.
+  BadCredentials = "bad-credentials",
+  // Account disabled reason.
+  AccountDisabled = "account-disabled",
+  // Credentials expired reason.
+  CredentialsExpired = "credentials-expired",
+  // Temporary failure reason.
+  TemporaryFailure = "temporary-failure",
+  // Encryption required reason.
+  EncryptionRequired = "encryption-required",
+  // Mechanism unsupported reason.
+  MechanismUnsupported = "mechanism-unsupported",
+  // Aborted reason.
+  Aborted = "aborted",
+  // System shutdown reason.
+  SystemShutdown = "system-shutdown",
+  // Idle timeout reason.
+  IdleTimeout = "idle-timeout",
+  // Servers unreachable reason.
+  ServersUnreachable = "servers-unreachable",
+  // Other reason.
+  Other = "other"
 }
 
 enum RuntimeConnectionMethod {
@@ -127,7 +162,12 @@ type RuntimeRouteHandler = (
   params?: { [name: string]: string }
 ) => Promise<void>;
 
-type RuntimeConnectionStateHandler = (state: RuntimeConnectionState) => void;
+type RuntimeConnectionStateHandler = (
+  state: RuntimeConnectionState,
+  detail?: RuntimeConnectionStateDetail,
+  error?: string,
+  jid?: string
+) => void;
 type RuntimeConnectionReceiveHandler = (stanza: string) => void;
 
 type RuntimeConnectionID = string;
@@ -150,11 +190,34 @@ interface RuntimeNotificationInteractionPayload {
 interface RuntimeConnectionReceivePayload {
   id: RuntimeConnectionID;
   stanza: string;
+  encoding?: "gzip";
+  sequence?: number;
+}
+
+interface RuntimeConnectionReceiveChunkPayload {
+  id: RuntimeConnectionID;
+  stream: number;
+  marker: "start" | "data" | "end";
+  kind?: "message" | "presence" | "iq";
+  chunk?: string;
+  encoding?: "gzip";
+  sequence?: number;
+}
+
+interface RuntimeConnectionStateDetail {
+  reason: RuntimeConnectionStateReason;
+  condition?: string;
+  reconnectDelay?: number;
+  tried?: string[];
 }
 
 interface RuntimeConnectionStatePayload {
   id: RuntimeConnectionID;
+  label: string;
   state: RuntimeConnectionState;
+  error?: string;
+  detail?: RuntimeConnectionStateDetail;
+  jid?: string;
 }
 
 interface RuntimeNotificationRoute {
@@ -225,6 +288,11 @@ class UtilitiesRuntime {
     focused: false
   };
 
+  private __receiving: Promise<void> = Promise.resolve();
+
+  private __receivingChunks: Map<string, RuntimeConnectionReceivePayload> =
+    new Map();
+
   private __handlers = {
     global: {
       focus: null as RuntimeFocusHandler | null,
@@ -299,6 +367,9 @@ class UtilitiesRuntime {
   unregisterConnectionHandlers(id: RuntimeConnectionID): void {
     // Unregister platform-agnostic connection handlers
     delete this.__handlers.connection[id];
+
+    // Drop stanzas still being assembled (they will never complete)
+    this.__dropReceivingChunks(id);
   }
 
   async requestOpenUrl(
@@ -735,10 +806,11 @@ class UtilitiesRuntime {
     }
   }
 
-  async requestConnectionDisconnect(id: RuntimeConnectionID): Promise<void> {
+  async requestConnectionDisconnect(id: RuntimeConnectionID): Promise<number> {
     if (this.__isApplication === true) {
       // Request to disconnect via Tauri API (application build)
-      await tauriInvoke("plugin:connection|disconnect", { id });
+      // Notice: this returns the amount of packets that were not delivered
+      return await tauriInvoke("plugin:connection|disconnect", { id });
     } else {
       // This method should NEVER be used on other platforms
       throw new Error(
@@ -747,10 +819,11 @@ class UtilitiesRuntime {
     }
   }
 
-  async requestConnectionDestroy(id: RuntimeConnectionID): Promise<void> {
+  async requestConnectionDestroy(id: RuntimeConnectionID): Promise<number> {
     if (this.__isApplication === true) {
       // Request to destroy via Tauri API (application build)
-      await tauriInvoke("plugin:connection|destroy", { id });
+      // Notice: this returns the amount of packets that were not delivered
+      return await tauriInvoke("plugin:connection|destroy", { id });
     } else {
       // This method should NEVER be used on other platforms
       throw new Error(
@@ -893,8 +966,23 @@ class UtilitiesRuntime {
         "connection:state",
 
         ({ payload }) => {
+          // Drop stanzas still being assembled? (stream is gone)
+          // Notice: the end marker of those stanzas will never be received, \
+          //   as the stream they were received on went away.
+          if (
+            payload.state === RuntimeConnectionState.Disconnected ||
+            payload.state === RuntimeConnectionState.Reconnecting
+          ) {
+            this.__dropReceivingChunks(payload.id);
+          }
+
           // Trigger connection state handler (if any)
-          this.__handlers.connection[payload.id]?.state(payload.state);
+          this.__handlers.connection[payload.id]?.state(
+            payload.state,
+            payload.detail,
+            payload.error,
+            payload.jid
+          );
         }
       );
 
@@ -902,8 +990,52 @@ class UtilitiesRuntime {
         "connection:receive",
 
         ({ payload }) => {
-          // Trigger connection receive handler (if any)
-          this.__handlers.connection[payload.id]?.receive(payload.stanza);
+          this.__receiveStanza(payload);
+        }
+      );
+
+      tauriWindow().listen<RuntimeConnectionReceiveChunkPayload>(
+        "connection:receive-chunk",
+
+        ({ payload }) => {
+          // Notice: large stanzas are streamed in chunks, which are \
+          //   assembled here until the end marker is received.
+          const key = `${payload.id}:${payload.stream}`;
+
+          switch (payload.marker) {
+            case "start": {
+              this.__receivingChunks.set(key, {
+                id: payload.id,
+                stanza: "",
+                encoding: payload.encoding,
+                sequence: payload.sequence
+              });
+
+              break;
+            }
+
+            case "data": {
+              const assembled = this.__receivingChunks.get(key);
+
+              if (assembled !== undefined) {
+                assembled.stanza += payload.chunk || "";
+              }
+
+              break;
+            }
+
+            case "end": {
+              const assembled = this.__receivingChunks.get(key);
+
+              if (assembled !== undefined) {
+                this.__receivingChunks.delete(key);
+
+                this.__receiveStanza(assembled);
+              }
+
+              break;
+            }
+          }
         }
       );
     } else {
@@ -919,6 +1051,63 @@ class UtilitiesRuntime {
     }
   }
 
+  private __dropReceivingChunks(id: RuntimeConnectionID): void {
+    const prefix = `${id}:`;
+
+    for (const key of this.__receivingChunks.keys()) {
+      if (key.startsWith(prefix) === true) {
+        this.__receivingChunks.delete(key);
+      }
+    }
+  }
+
+  private __receiveStanza(payload: RuntimeConnectionReceivePayload): void {
+    // Notice: received stanzas are chained, since decompressing a stanza \
+    //   is asynchronous, and stanzas must be handled in order.
+    this.__receiving = this.__receiving.then(async () => {
+      try {
+        const stanza =
+          payload.encoding !== undefined
+            ? await this.__decompressStanza(payload.stanza, payload.encoding)
+            : payload.stanza;
+
+        // Trigger connection receive handler (if any)
+        this.__handlers.connection[payload.id]?.receive(stanza);
+      } catch (error) {
+        logger.error(`Could not decompress stanza on: #${payload.id}`, error);
+
+        // Disconnect, as the stanza cannot be handled
+        // Important: never drop a stanza silently, as the client state would \
+        //   then drift. Disconnecting lets the client reconnect and catch up \
+        //   on anything missed, as it would upon any other connection loss.
+        this.requestConnectionDisconnect(payload.id).catch(disconnectError => {
+          logger.error(
+            `Could not disconnect after decompress failure on: #${payload.id}`,
+            disconnectError
+          );
+        });
+      }
+    });
+  }
+
+  private async __decompressStanza(
+    stanza: string,
+    encoding: string
+  ): Promise<string> {
+    // Notice: stanza is encoded as Base64, and then decompressed using the \
+    //   native decompression streams (only formats supported by all web \
+    //   views get used by the backend).
+    const bytes = Uint8Array.from(atob(stanza), character => {
+      return character.charCodeAt(0);
+    });
+
+    const stream = new Blob([bytes])
+      .stream()
+      .pipeThrough(new DecompressionStream(encoding as CompressionFormat));
+
+    return await new Response(stream).text();
+  }
+
   private __changeFocusState(focused: boolean): void {
     this.__states.focused = focused;
 
@@ -939,10 +1128,11 @@ export {
   RuntimeDialogKind,
   RuntimeUrlOpenTarget,
   RuntimeConnectionState,
+  RuntimeConnectionStateReason,
   RuntimeConnectionMethod,
   platform,
   context,
   translucent
 };
-export type { RuntimeConnectionID };
+export type { RuntimeConnectionID, RuntimeConnectionStateDetail };
 export default new UtilitiesRuntime();